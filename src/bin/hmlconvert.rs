@@ -1,22 +1,615 @@
 use std::fs::File;
 use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 
-use clap::{value_parser, Arg, Command};
+use clap::{value_parser, Arg, ArgAction, Command};
 
 use hml_rs::hml_reader::Parser;
+use hml_rs::markup::{ContentOptions, Event};
 use hml_rs::names::{Namespace, NamespaceStack};
+use hml_rs::Posn;
 
-use lexer_rs::FmtContext;
+use lexer_rs::{FmtContext, UserPosn};
 use lexer_rs::{Lexer, LineColumn, StreamCharPos};
 
 type LexerPos = StreamCharPos<LineColumn>;
 type HmlError = hml_rs::HmlError<LexerPos>;
 
+//a Format
+//tp Format
+/// Document formats that can be read and written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Hml,
+    Xml,
+    Json,
+    Html,
+}
+
+//ip Format
+impl Format {
+    //fp of_extension
+    /// Get the format of a file from its extension, if it is known
+    fn of_extension(path: &Path) -> Option<Self> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("xml") => Some(Self::Xml),
+            Some("hml") => Some(Self::Hml),
+            Some("json") => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    //fp detect
+    /// Determine the format of a document from its filename (if
+    /// any), or else from whether its text starts with '<' (or '['
+    /// for JSON)
+    fn detect(filename: Option<&Path>, text: &str) -> Self {
+        match filename.and_then(Self::of_extension) {
+            Some(format) => format,
+            None if text.trim_start().starts_with('<') => Self::Xml,
+            None if text.trim_start().starts_with('[') => Self::Json,
+            None => Self::Hml,
+        }
+    }
+
+    //mp extension
+    /// Get the file extension for the format
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Hml => "hml",
+            Self::Xml => "xml",
+            Self::Json => "json",
+            Self::Html => "html",
+        }
+    }
+
+    //mp other
+    /// Get the format to convert to if none is specified
+    fn other(self) -> Self {
+        match self {
+            Self::Hml => Self::Xml,
+            Self::Xml => Self::Hml,
+            Self::Json => Self::Xml,
+            Self::Html => Self::Xml,
+        }
+    }
+
+    //fp of_arg
+    /// Get the format specified by a command line argument, if it is not 'auto'
+    fn of_arg(arg: Option<&String>) -> Option<Self> {
+        match arg.map(|s| s.as_str()) {
+            Some("hml") => Some(Self::Hml),
+            Some("xml") => Some(Self::Xml),
+            Some("json") => Some(Self::Json),
+            Some("html") => Some(Self::Html),
+            _ => None,
+        }
+    }
+}
+
+//a Output
+//tp Output
+/// The writer for the output document
+enum Output {
+    Xml(hml_rs::xml_writer::Writer<Box<dyn Write>>),
+    Hml(hml_rs::hml_writer::Writer<Box<dyn Write>>),
+    Json(hml_rs::json::Writer<Box<dyn Write>>),
+    Html(hml_rs::html_writer::Writer<Box<dyn Write>>),
+}
+
+//ip Output
+impl Output {
+    //fp new
+    /// Create the writer for a format; `minify` applies only to HTML output
+    ///
+    /// Whitespace content (such as that between XML elements) is
+    /// formatting, which the XML, HML and JSON writers provide
+    /// themselves if required, so they drop it; the HTML writer keeps
+    /// it unless minifying
+    fn new(format: Format, minify: bool, output: Box<dyn Write>) -> Self {
+        match format {
+            Format::Xml => Self::Xml(
+                hml_rs::xml_writer::Writer::new(output)
+                    .set_indent(Some(2))
+                    .set_content_options(ContentOptions::default().set_keep_whitespace(false)),
+            ),
+            Format::Hml => {
+                Self::Hml(hml_rs::hml_writer::Writer::new(output).set_keep_whitespace(false))
+            }
+            Format::Json => Self::Json(
+                hml_rs::json::Writer::new(output)
+                    .set_include_comments(true)
+                    .set_include_pis(true)
+                    .set_keep_whitespace(false),
+            ),
+            Format::Html => Self::Html(hml_rs::html_writer::Writer::new(output).set_minify(minify)),
+        }
+    }
+
+    //mp into_inner
+    /// Consume the writer, returning the underlying output
+    fn into_inner(self) -> Box<dyn Write> {
+        match self {
+            Self::Xml(w) => w.into_inner(),
+            Self::Hml(w) => w.into_inner(),
+            Self::Json(w) => w.into_inner(),
+            Self::Html(w) => w.into_inner(),
+        }
+    }
+
+    //mp write
+    /// Write an event to the output; return false if the document is complete
+    fn write<P: Posn>(&mut self, ns: &NamespaceStack, event: &Event<P>) -> Result<bool, String> {
+        match self {
+            Self::Xml(w) => {
+                w.write_event(ns, event).map_err(|e| e.to_string())?;
+                Ok(!event.is_end_document())
+            }
+            Self::Hml(w) => {
+                w.write_event(ns, event).map_err(|e| e.to_string())?;
+                Ok(!event.is_end_document())
+            }
+            Self::Json(w) => {
+                w.write_event(ns, event).map_err(|e| e.to_string())?;
+                Ok(!event.is_end_document())
+            }
+            Self::Html(w) => {
+                w.write_event(ns, event).map_err(|e| e.to_string())?;
+                Ok(!event.is_end_document())
+            }
+        }
+    }
+}
+
+//a Diagnostic
+//tp Diagnostic
+/// A failure to read (or write) a document, with its position in the
+/// text if known and the context of the error formatted for a user
+struct Diagnostic {
+    start: Option<(usize, usize)>,
+    end: Option<(usize, usize)>,
+    kind: &'static str,
+    message: String,
+    context: String,
+}
+
+//ip Diagnostic
+impl Diagnostic {
+    //fp new
+    fn new(kind: &'static str, message: String) -> Self {
+        Self {
+            start: None,
+            end: None,
+            kind,
+            message,
+            context: String::new(),
+        }
+    }
+
+    //cp at
+    /// Set the start and end (line, column) of the diagnostic
+    fn at(mut self, start: (usize, usize), end: (usize, usize)) -> Self {
+        self.start = Some(start);
+        self.end = Some(end);
+        self
+    }
+
+    //mp print
+    /// Print the diagnostic with its context to stderr
+    fn print(&self, filename: &str) {
+        eprint!("{}", self.context);
+        match self.start {
+            Some((line, column)) => eprintln!("{filename}:{line}:{column}: {}", self.message),
+            None => eprintln!("{filename}: {}", self.message),
+        }
+        eprintln!();
+    }
+
+    //mp json
+    /// Format the diagnostic as a single line of JSON
+    fn json(&self, filename: &str) -> String {
+        fn posn(p: Option<(usize, usize)>) -> String {
+            match p {
+                Some((line, column)) => format!("{line}, \"column\": {column}"),
+                None => "null, \"column\": null".into(),
+            }
+        }
+        format!(
+            "{{\"file\": {}, \"line\": {}, \"end\": {{\"line\": {}}}, \"kind\": {}, \"message\": {}}}",
+            hml_rs::json::quote(filename),
+            posn(self.start),
+            posn(self.end),
+            hml_rs::json::quote(self.kind),
+            hml_rs::json::quote(&self.message),
+        )
+    }
+}
+
+//a Conversions
+//fi parse_diagnostic
+/// Create a [Diagnostic] for a parse error, with its context in the
+/// text
+fn parse_diagnostic<T>(
+    lexer_string: &lexer_rs::LexerOfString<LexerPos, T, HmlError>,
+    e: HmlError,
+) -> Diagnostic {
+    let mut diagnostic = Diagnostic::new(e.kind(), e.to_string());
+    if let Some(span) = e.span() {
+        lexer_string
+            .fmt_context(&mut diagnostic.context, span.start(), span.end())
+            .unwrap();
+        let (start, end) = (span.start(), span.end());
+        diagnostic = diagnostic.at((start.line(), start.column()), (end.line(), end.column()));
+    }
+    diagnostic
+}
+
+//fi convert_hml
+/// Parse HML text and write it to the output (if any), returning a
+/// [Diagnostic] with the context in the text of any error
+fn convert_hml(
+    text: String,
+    xml_version: usize,
+    mut output: Option<&mut Output>,
+) -> Result<(), Diagnostic> {
+    let mut namespace = Namespace::new(true);
+    let mut namespace_stack = NamespaceStack::new(&mut namespace);
+    let lexer_string = lexer_rs::LexerOfString::default().set_text(text);
+    let lexer = lexer_string.lexer();
+    let lexer_parsers = hml_rs::hml_reader::parse_fns();
+    let mut lexer_iter = lexer.iter(&lexer_parsers);
+    let mut parser: Parser<LexerPos> = Parser::default().set_version(xml_version);
+    loop {
+        let event = parser
+            .next_event(&mut namespace_stack, || lexer_iter.next())
+            .map_err(|e| parse_diagnostic(&lexer_string, e))?;
+        let more = match &mut output {
+            Some(output) => output
+                .write(&namespace_stack, &event)
+                .map_err(|e| Diagnostic::new("output_error", e))?,
+            None => !event.is_end_document(),
+        };
+        if !more {
+            return Ok(());
+        }
+    }
+}
+
+//fi convert_xml
+/// Parse XML text and write it to the output (if any), returning a
+/// [Diagnostic] with the context in the text of any error
+fn convert_xml(text: String, mut output: Option<&mut Output>) -> Result<(), Diagnostic> {
+    let mut namespace = Namespace::new(true);
+    let mut namespace_stack = NamespaceStack::new(&mut namespace);
+    let lexer_string = lexer_rs::LexerOfString::default().set_text(text);
+    let lexer = lexer_string.lexer();
+    let lexer_parsers = hml_rs::xml_reader::parse_fns();
+    let mut lexer_iter = lexer.iter(&lexer_parsers);
+    let mut parser: hml_rs::xml_reader::Parser<LexerPos> = Default::default();
+    loop {
+        let event = parser
+            .next_event(&mut namespace_stack, || lexer_iter.next())
+            .map_err(|e| parse_diagnostic(&lexer_string, e))?;
+        let more = match &mut output {
+            Some(output) => output
+                .write(&namespace_stack, &event)
+                .map_err(|e| Diagnostic::new("output_error", e))?,
+            None => !event.is_end_document(),
+        };
+        if !more {
+            return Ok(());
+        }
+    }
+}
+
+//fi convert_json
+/// Read a JSON document and write it to the output (if any),
+/// returning a [Diagnostic] for any error
+fn convert_json(text: String, mut output: Option<&mut Output>) -> Result<(), Diagnostic> {
+    let mut namespace = Namespace::new(true);
+    let mut namespace_stack = NamespaceStack::new(&mut namespace);
+    let line_column = |ofs: usize| {
+        let before = &text[..ofs];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |n| n + 1) + 1;
+        (line, column)
+    };
+    let diagnostic = |e: hml_rs::json::Error| {
+        let range = e.byte_range();
+        let kind = match &e {
+            hml_rs::json::Error::Markup { source, .. } => source.kind(),
+            _ => "json_error",
+        };
+        Diagnostic::new(kind, e.to_string()).at(line_column(range.start), line_column(range.end))
+    };
+    let mut reader = hml_rs::json::Reader::new(&text).map_err(diagnostic)?;
+    loop {
+        let event = reader
+            .next_event(&mut namespace_stack)
+            .map_err(diagnostic)?;
+        let more = match &mut output {
+            Some(output) => output
+                .write(&namespace_stack, &event)
+                .map_err(|e| Diagnostic::new("output_error", e))?,
+            None => !event.is_end_document(),
+        };
+        if !more {
+            return Ok(());
+        }
+    }
+}
+
+//fi read_file
+/// Read a file (or stdin if there is no filename) to a string
+fn read_file(filename: Option<&Path>) -> Result<String, Diagnostic> {
+    let mut text = String::new();
+    let result = match filename {
+        None => std::io::stdin().read_to_string(&mut text),
+        Some(filename) => File::open(filename).and_then(|mut f| f.read_to_string(&mut text)),
+    };
+    result
+        .map(|_| text)
+        .map_err(|e| Diagnostic::new("io_error", e.to_string()))
+}
+
+//fi convert
+/// Convert text in one format, writing it to the output (if any)
+fn convert(
+    text: String,
+    from: Format,
+    xml_version: usize,
+    output: Option<&mut Output>,
+) -> Result<(), Diagnostic> {
+    match from {
+        Format::Hml => convert_hml(text, xml_version, output),
+        Format::Xml => convert_xml(text, output),
+        Format::Json => convert_json(text, output),
+        Format::Html => Err(Diagnostic::new(
+            "unsupported_format",
+            "HTML documents cannot be read".to_string(),
+        )),
+    }
+}
+
+//fi check_files
+/// Check that each of the files can be read, reporting every
+/// failure; return true if they all succeeded
+fn check_files(
+    filenames: &[&String],
+    from: Option<Format>,
+    xml_version: usize,
+    json: bool,
+) -> bool {
+    let mut okay = true;
+    for filename in filenames {
+        let path = Path::new(filename);
+        let result = read_file(Some(path)).and_then(|text| {
+            let from = from.unwrap_or_else(|| Format::detect(Some(path), &text));
+            convert(text, from, xml_version, None)
+        });
+        if let Err(diagnostic) = result {
+            okay = false;
+            diagnostic.print(filename);
+            if json {
+                println!("{}", diagnostic.json(filename));
+            }
+        }
+    }
+    okay
+}
+
+//a Batch conversion
+//tp Batch
+/// Configuration for converting many files into an output directory
+struct Batch {
+    from: Option<Format>,
+    to: Option<Format>,
+    xml_version: usize,
+    minify: bool,
+    output_dir: PathBuf,
+    force: bool,
+}
+
+//tp Job
+/// How one file of a batch is to be converted
+struct Job {
+    from: Format,
+    to: Format,
+    /// The text of the file, if it has been read to find its format
+    text: Option<String>,
+    /// The output path, with its extension
+    output: PathBuf,
+}
+
+//tp Outcome
+/// The result of converting one file of a batch
+enum Outcome {
+    Converted(PathBuf),
+    UpToDate(PathBuf),
+}
+
+//ip Batch
+impl Batch {
+    //mi accepts
+    /// Return true if a file found in a directory should be converted
+    fn accepts(&self, path: &Path) -> bool {
+        match (self.from, Format::of_extension(path)) {
+            (_, None) => false,
+            (None, Some(_)) => true,
+            (Some(from), Some(format)) => from == format,
+        }
+    }
+
+    //mi find_files
+    /// Add the files to convert within a directory (recursively) to
+    /// `files`, as pairs of input path and output path without
+    /// extension; a directory that cannot be read is added with the
+    /// error, so that the others are still converted
+    fn find_files(
+        &self,
+        dir: &Path,
+        output: &Path,
+        files: &mut Vec<(PathBuf, Result<PathBuf, Diagnostic>)>,
+    ) {
+        let entries = std::fs::read_dir(dir).and_then(|entries| {
+            entries
+                .map(|e| e.map(|e| e.path()))
+                .collect::<std::io::Result<Vec<_>>>()
+        });
+        let mut entries = match entries {
+            Ok(entries) => entries,
+            Err(e) => {
+                let diagnostic = Diagnostic::new("io_error", e.to_string());
+                files.push((dir.to_path_buf(), Err(diagnostic)));
+                return;
+            }
+        };
+        entries.sort();
+        for path in entries {
+            let output = output.join(path.file_name().unwrap());
+            if path.is_dir() {
+                self.find_files(&path, &output, files);
+            } else if self.accepts(&path) {
+                files.push((path, Ok(output)));
+            }
+        }
+    }
+
+    //mi is_up_to_date
+    /// Return true if the output exists and was modified no earlier than the input
+    fn is_up_to_date(input: &Path, output: &Path) -> bool {
+        let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified());
+        match (modified(input), modified(output)) {
+            (Ok(input), Ok(output)) => output >= input,
+            _ => false,
+        }
+    }
+
+    //mi plan
+    /// Determine how to convert a single file, with the output path
+    /// given without an extension (which depends on the output
+    /// format); the file is read if its format is not known from its
+    /// extension
+    fn plan(&self, input: &Path, output: &Path) -> Result<Job, Diagnostic> {
+        let mut text = None;
+        let from = match self.from.or_else(|| Format::of_extension(input)) {
+            Some(from) => from,
+            None => {
+                let t = read_file(Some(input))?;
+                let from = Format::detect(None, &t);
+                text = Some(t);
+                from
+            }
+        };
+        let to = self.to.unwrap_or(from.other());
+        Ok(Job {
+            from,
+            to,
+            text,
+            output: output.with_extension(to.extension()),
+        })
+    }
+
+    //mi convert_file
+    /// Convert a single file as planned
+    fn convert_file(&self, input: &Path, job: Job) -> Result<Outcome, Diagnostic> {
+        let Job {
+            from,
+            to,
+            text,
+            output,
+        } = job;
+        if !self.force && Self::is_up_to_date(input, &output) {
+            return Ok(Outcome::UpToDate(output));
+        }
+        let text = match text {
+            Some(text) => text,
+            None => read_file(Some(input))?,
+        };
+        let io_error =
+            |e: std::io::Error| Diagnostic::new("io_error", format!("{}: {e}", output.display()));
+        if let Some(parent) = output.parent() {
+            std::fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        let file = File::create(&output).map_err(io_error)?;
+        let mut writer = Output::new(to, self.minify, Box::new(std::io::BufWriter::new(file)));
+        let result = convert(text, from, self.xml_version, Some(&mut writer))
+            .and_then(|_| writer.into_inner().flush().map_err(io_error));
+        if result.is_err() {
+            // Do not leave a partial file that would appear to be up to date
+            let _ = std::fs::remove_file(&output);
+        }
+        result.map(|_| Outcome::Converted(output))
+    }
+
+    //mp run
+    /// Convert the files and directories given, reporting the
+    /// outcome for each file and a summary; return true if all the
+    /// files succeeded
+    ///
+    /// A directory that cannot be read, and every file whose output
+    /// path would be the same as that of another file, is a failure;
+    /// the other files are still converted
+    fn run(&self, inputs: &[&String]) -> bool {
+        let mut files = Vec::new();
+        for input in inputs {
+            let path = Path::new(input);
+            if path.is_dir() {
+                self.find_files(path, &self.output_dir, &mut files);
+            } else {
+                let name = path.file_name().unwrap_or(path.as_os_str());
+                files.push((path.to_path_buf(), Ok(self.output_dir.join(name))));
+            }
+        }
+        let mut jobs: Vec<(PathBuf, Result<Job, Diagnostic>)> = files
+            .into_iter()
+            .map(|(input, output)| {
+                let job = output.and_then(|output| self.plan(&input, &output));
+                (input, job)
+            })
+            .collect();
+        let mut outputs = std::collections::HashMap::new();
+        for job in jobs.iter().filter_map(|(_, job)| job.as_ref().ok()) {
+            *outputs.entry(job.output.clone()).or_insert(0) += 1;
+        }
+        for (_, job) in jobs.iter_mut() {
+            if let Ok(j) = job {
+                if outputs[&j.output] > 1 {
+                    let message =
+                        format!("{} is also the output of another input", j.output.display());
+                    *job = Err(Diagnostic::new("duplicate_output", message));
+                }
+            }
+        }
+        let (mut converted, mut up_to_date, mut failed) = (0, 0, 0);
+        for (input, job) in jobs {
+            match job.and_then(|job| self.convert_file(&input, job)) {
+                Ok(Outcome::Converted(output)) => {
+                    converted += 1;
+                    println!("{} -> {}", input.display(), output.display());
+                }
+                Ok(Outcome::UpToDate(output)) => {
+                    up_to_date += 1;
+                    println!("{} -> {} (up to date)", input.display(), output.display());
+                }
+                Err(diagnostic) => {
+                    failed += 1;
+                    println!("{} failed", input.display());
+                    diagnostic.print(&input.display().to_string());
+                }
+            }
+        }
+        println!("{converted} converted, {up_to_date} up to date, {failed} failed");
+        failed == 0
+    }
+}
+
+//a Main
 fn main() {
     let matches = Command::new("hml")
-        .about("HML parser to output file generator")
+        .about("HML and XML document converter")
         .after_help(
-            "This program reads an HML (or in the future XML) file, and outputs an XML file",
+            "This program reads an HML, XML or JSON file, and outputs it as XML, HML, JSON or HTML; by default the input format is determined by the file extension or content, and the output is XML for HML or JSON input, and HML for XML input.\n\nWith --check, each of the files is read and any errors reported, without generating output; the exit status is nonzero if any file fails.\n\nWith --output-dir, each of the files is converted in to the output directory, and each directory given is converted recursively with its tree mirrored in the output directory; files whose output is newer than the input are skipped unless --force is used. Files that would have the same output (such as 'x/a.hml' in two of the directories given) are not converted, and are reported as failures, as is a directory that cannot be read; the other files are still converted.",
         )
         .author("Gavin J Stark")
         .version("0.1")
@@ -27,6 +620,57 @@ fn main() {
                 .required(false)
                 .num_args(1), // was takes_value(true)
         )
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .help("Format of the input file")
+                .required(false)
+                .num_args(1)
+                .value_parser(["hml", "xml", "json", "auto"])
+                .default_value("auto"),
+        )
+        .arg(
+            Arg::new("to")
+                .long("to")
+                .help("Format of the output file (default is the format the input is not)")
+                .required(false)
+                .num_args(1)
+                .value_parser(["hml", "xml", "json", "html"]),
+        )
+        .arg(
+            Arg::new("minify")
+                .long("minify")
+                .help("With --to html, remove comments and whitespace that is not significant")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("output_dir")
+                .long("output-dir")
+                .help("Convert files and directories in to this directory")
+                .required(false)
+                .num_args(1)
+                .conflicts_with("output"),
+        )
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .help("With --output-dir, convert files even if their output is up to date")
+                .requires("output_dir")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("check")
+                .long("check")
+                .help("Check the input files without generating output")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("With --check, also write each error as a line of JSON to stdout")
+                .requires("check")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("xml_version")
                 .short('x')
@@ -36,7 +680,11 @@ fn main() {
                 .num_args(1) // was takes_value(true)
                 .value_parser(value_parser!(f32)),
         )
-        .arg(Arg::new("file").help("Input file to read"))
+        .arg(
+            Arg::new("file")
+                .help("Input file to read (more than one, or directories, may be given with --check or --output-dir)")
+                .num_args(0..),
+        )
         .get_matches();
 
     let mut xml_version = 100;
@@ -44,50 +692,102 @@ fn main() {
         xml_version = (x * 100.0).round() as usize;
     }
 
-    let mut text = String::new();
-    match matches.get_one::<String>("file") {
-        None => {
-            std::io::stdin().read_to_string(&mut text).unwrap();
-        }
-        Some(filename) => {
-            let mut file = File::open(filename).unwrap();
-            file.read_to_string(&mut text).unwrap();
-        }
+    let filenames: Vec<&String> = matches
+        .get_many::<String>("file")
+        .map(|f| f.collect())
+        .unwrap_or_default();
+    let from = Format::of_arg(matches.get_one::<String>("from"));
+
+    if matches.get_flag("check") {
+        let okay = check_files(&filenames, from, xml_version, matches.get_flag("json"));
+        std::process::exit(if okay { 0 } else { 1 });
     }
-    let mut namespace = Namespace::new(false);
-    let mut namespace_stack = NamespaceStack::new(&mut namespace);
-    let lexer_string = lexer_rs::LexerOfString::default().set_text(text);
-    let lexer = lexer_string.lexer();
-    let lexer_parsers = hml_rs::hml_reader::parse_fns();
-    let mut lexer_iter = lexer.iter(&lexer_parsers);
-    let mut parser: Parser<LexerPos> = Parser::default().set_version(xml_version);
-    let output = std::io::stdout();
-    let mut writer = xml::writer::EmitterConfig::new()
-        .perform_indent(true)
-        .create_writer(output);
-    loop {
-        match parser.next_event(&mut namespace_stack, || lexer_iter.next()) {
-            Ok(event) => {
-                if let Some(x) = event.as_xml_writer(&namespace_stack) {
-                    writer.write(x).unwrap();
-                } else {
-                    break;
-                }
-            }
+
+    let to = Format::of_arg(matches.get_one::<String>("to"));
+
+    if let Some(output_dir) = matches.get_one::<String>("output_dir") {
+        let batch = Batch {
+            from,
+            to,
+            xml_version,
+            minify: matches.get_flag("minify"),
+            output_dir: output_dir.into(),
+            force: matches.get_flag("force"),
+        };
+        let okay = batch.run(&filenames);
+        std::process::exit(if okay { 0 } else { 1 });
+    }
+
+    if filenames.len() > 1 {
+        eprintln!(
+            "Only one file may be converted at a time (unless using --check or --output-dir)"
+        );
+        std::process::exit(1);
+    }
+    let filename = filenames.first().map(Path::new);
+    let display_name = filename.map_or("<stdin>".into(), |f| f.display().to_string());
+    let text = match read_file(filename) {
+        Ok(text) => text,
+        Err(diagnostic) => {
+            diagnostic.print(&display_name);
+            std::process::exit(1);
+        }
+    };
+
+    let from = from.unwrap_or_else(|| Format::detect(filename, &text));
+    let to = to.unwrap_or(from.other());
+
+    let output: Box<dyn Write> = match matches.get_one::<String>("output") {
+        None => Box::new(std::io::stdout()),
+        Some(filename) => match File::create(filename) {
+            Ok(f) => Box::new(std::io::BufWriter::new(f)),
             Err(e) => {
-                let e: HmlError = e;
-                if let Some(span) = e.span() {
-                    let mut s = String::new();
-                    // reader
-                    lexer_string
-                        .fmt_context(&mut s, span.start(), span.end())
-                        .unwrap();
-                    write!(&mut std::io::stderr(), "{}", s).unwrap();
-                    eprintln!("Parse error: {e}");
-                    eprintln!();
-                }
-                break;
+                eprintln!("Failed to create output file {filename}: {e}");
+                std::process::exit(1);
             }
+        },
+    };
+    let mut output = Output::new(to, matches.get_flag("minify"), output);
+
+    if let Err(diagnostic) = convert(text, from, xml_version, Some(&mut output)) {
+        diagnostic.print(&display_name);
+        std::process::exit(1);
+    }
+}
+
+//a Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_batch_duplicate_output() {
+        let dir = std::env::temp_dir().join(format!("hmlconvert-batch-{}", std::process::id()));
+        for d in ["d1/x", "d2/x"] {
+            std::fs::create_dir_all(dir.join(d)).unwrap();
         }
+        std::fs::write(dir.join("d1/x/a.hml"), "#a").unwrap();
+        std::fs::write(dir.join("d2/x/a.hml"), "#b").unwrap();
+        std::fs::write(dir.join("d2/y.hml"), "#y").unwrap();
+        let batch = Batch {
+            from: None,
+            to: None,
+            xml_version: 100,
+            minify: false,
+            output_dir: dir.join("out"),
+            force: false,
+        };
+        let d1 = dir.join("d1").display().to_string();
+        let d2 = dir.join("d2").display().to_string();
+        let okay = batch.run(&[&d1, &d2]);
+        let a_written = dir.join("out/x/a.xml").exists();
+        let y_written = dir.join("out/y.xml").exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!okay);
+        assert!(
+            !a_written,
+            "neither of the inputs for x/a.xml should be converted"
+        );
+        assert!(y_written, "other files should still be converted");
     }
 }
@@ -0,0 +1,603 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use clap::{Arg, ArgAction, Command};
+
+use hml_rs::hml::escape::Escapable;
+use hml_rs::hml_reader::Parser;
+use hml_rs::markup::{ContentType, Event};
+use hml_rs::names::{Name, Namespace, NamespaceStack, Tag};
+use hml_rs::Posn;
+use hml_rs::{hml_writer, xml_writer};
+
+use lexer_rs::{FmtContext, Lexer, LineColumn, StreamCharPos, UserPosn};
+
+type LexerPos = StreamCharPos<LineColumn>;
+type HmlError = hml_rs::HmlError<LexerPos>;
+
+//a Path expressions
+//tp Step
+/// A step of a path, matching an element that is a child (or
+/// descendant) of the element matched by the previous step
+#[derive(Debug, PartialEq)]
+struct Step {
+    /// True if the step may match any descendant, rather than a child
+    descendant: bool,
+    /// Prefix to match, if any
+    prefix: Option<String>,
+    /// Local name to match, or None to match any element
+    name: Option<String>,
+    /// Attributes that must be present, with their values if required
+    predicates: Vec<(String, Option<String>)>,
+}
+
+//tp Select
+/// What to output for each element matched by the path
+#[derive(Debug, PartialEq)]
+enum Select {
+    /// The element itself, as a document fragment
+    Element,
+    /// The value of an attribute of the element
+    Attribute(String),
+    /// The text content of the element
+    Text,
+}
+
+//tp Query
+/// A parsed path expression
+///
+/// ```text
+/// Query := ['/' | '//'] Step (('/' | '//') Step)* ['/@' Name | '/text()']
+/// Step := ( Name | Prefix ':' Name | '*' ) ('[' '@' Name ['=' Quoted] ']')*
+/// ```
+///
+/// A query that does not start with '/' may match at any depth
+#[derive(Debug, PartialEq)]
+struct Query {
+    steps: Vec<Step>,
+    select: Select,
+}
+
+//ip Query
+impl Query {
+    //fi name_len
+    /// Get the length of the name (or '*') at the start of a string
+    fn name_len(s: &str) -> usize {
+        if s.starts_with('*') {
+            1
+        } else {
+            s.find(|c: char| !(c.is_alphanumeric() || "_-.:".contains(c)))
+                .unwrap_or(s.len())
+        }
+    }
+
+    //fi parse_predicate
+    /// Parse an '@name' or '@name=quoted' predicate
+    fn parse_predicate(s: &str) -> Result<(String, Option<String>), String> {
+        let Some(s) = s.strip_prefix('@') else {
+            return Err(format!("predicate '[{s}]' must start with '@'"));
+        };
+        match s.split_once('=') {
+            None => Ok((s.to_string(), None)),
+            Some((name, value)) => {
+                let value = value.trim();
+                let unquoted = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')));
+                match unquoted {
+                    Some(v) => Ok((name.trim().to_string(), Some(v.to_string()))),
+                    None => Err(format!("value in predicate '[@{s}]' must be quoted")),
+                }
+            }
+        }
+    }
+
+    //fp parse
+    /// Parse a path expression
+    fn parse(path: &str) -> Result<Self, String> {
+        let mut steps = Vec::new();
+        let mut select = Select::Element;
+        let mut s = path;
+        let mut descendant = !s.starts_with('/');
+        loop {
+            if let Some(rest) = s.strip_prefix("//") {
+                descendant = true;
+                s = rest;
+            } else if let Some(rest) = s.strip_prefix('/') {
+                s = rest;
+            }
+            if s == "text()" && !steps.is_empty() {
+                select = Select::Text;
+                break;
+            }
+            if let Some(name) = s.strip_prefix('@') {
+                if steps.is_empty() || name.is_empty() || Self::name_len(name) != name.len() {
+                    return Err(format!("bad attribute selection '@{name}' in path"));
+                }
+                select = Select::Attribute(name.to_string());
+                break;
+            }
+            let n = Self::name_len(s);
+            if n == 0 {
+                return Err(format!("expected an element name at '{s}' in path"));
+            }
+            let (prefix, name) = match &s[..n] {
+                "*" => (None, None),
+                full => match full.split_once(':') {
+                    Some((p, n)) => (Some(p.to_string()), Some(n.to_string())),
+                    None => (None, Some(full.to_string())),
+                },
+            };
+            s = &s[n..];
+            let mut predicates = Vec::new();
+            while let Some(rest) = s.strip_prefix('[') {
+                let Some(end) = rest.find(']') else {
+                    return Err("unterminated '[' in path".into());
+                };
+                predicates.push(Self::parse_predicate(&rest[..end])?);
+                s = &rest[end + 1..];
+            }
+            steps.push(Step {
+                descendant,
+                prefix,
+                name,
+                predicates,
+            });
+            descendant = false;
+            if s.is_empty() {
+                break;
+            }
+            if !s.starts_with('/') {
+                return Err(format!("expected '/' at '{s}' in path"));
+            }
+        }
+        Ok(Self { steps, select })
+    }
+
+    //mp step_matches
+    /// Return true if the tag matches step `n` of the path
+    fn step_matches(&self, n: usize, ns: &NamespaceStack, tag: &Tag) -> bool {
+        let step = &self.steps[n];
+        let name_str = |name: &Name| {
+            let prefix = if name.has_prefix() {
+                ns.prefix_str(name.prefix)
+            } else {
+                ""
+            };
+            (prefix, ns.name_str(name.name))
+        };
+        let (prefix, name) = name_str(&tag.name);
+        if step.name.as_ref().is_some_and(|n| n != name) {
+            return false;
+        }
+        if step.prefix.as_ref().is_some_and(|p| p != prefix) {
+            return false;
+        }
+        step.predicates.iter().all(|(attr, value)| {
+            tag.attributes.iter().any(|a| {
+                a.name.to_string(ns) == *attr && value.as_ref().map_or(true, |v| *v == a.value)
+            })
+        })
+    }
+
+    //mp advance
+    /// Given the set of steps reached by a parent element, find those
+    /// reached by a child element with tag `tag`; the path matches
+    /// the child if all the steps are reached
+    fn advance(&self, reached: &[usize], ns: &NamespaceStack, tag: &Tag) -> Vec<usize> {
+        let mut result = Vec::new();
+        for &n in reached {
+            if n == self.steps.len() {
+                continue;
+            }
+            if self.steps[n].descendant && !result.contains(&n) {
+                result.push(n);
+            }
+            if self.step_matches(n, ns, tag) && !result.contains(&(n + 1)) {
+                result.push(n + 1);
+            }
+        }
+        result
+    }
+}
+
+//a Output
+//tp Fragment
+/// A writer of an element (and its contents) as a document fragment
+enum Fragment {
+    Hml(hml_writer::Writer<Vec<u8>>),
+    Xml(xml_writer::Writer<Vec<u8>>),
+}
+
+//ip Fragment
+impl Fragment {
+    //fp new
+    fn new(xml: bool) -> Self {
+        if xml {
+            Self::Xml(
+                xml_writer::Writer::new(Vec::new())
+                    .set_declaration(false)
+                    .set_indent(Some(2)),
+            )
+        } else {
+            Self::Hml(hml_writer::Writer::new(Vec::new()))
+        }
+    }
+
+    //mp write
+    fn write<P: Posn>(&mut self, ns: &NamespaceStack, event: &Event<P>) -> Result<(), String> {
+        match self {
+            Self::Hml(w) => w.write_event(ns, event).map_err(|e| e.to_string()),
+            Self::Xml(w) => w.write_event(ns, event).map_err(|e| e.to_string()),
+        }
+    }
+
+    //mp finish
+    /// Finish the fragment, writing out anything the writer holds
+    /// back until the document ends, and return its text
+    fn finish(self) -> Result<String, String> {
+        let bytes = match self {
+            Self::Hml(mut w) => {
+                w.flush().map_err(|e| e.to_string())?;
+                w.into_inner()
+            }
+            Self::Xml(w) => w.into_inner(),
+        };
+        Ok(String::from_utf8_lossy(&bytes).trim().to_string())
+    }
+}
+
+//tp Capture
+/// Output being gathered for a matched element, until its end
+enum Capture {
+    Element(Fragment),
+    Text(String),
+}
+
+//a Matcher
+//tp Matcher
+/// Evaluates a [Query] over the event stream of a document, writing
+/// each match prefixed with its location to an output
+///
+/// Matches that print text or elements do not overlap: an element
+/// that matches within an element already matched is part of the
+/// output of that match, and is not reported separately
+struct Matcher<'a> {
+    query: &'a Query,
+    filename: &'a str,
+    xml_output: bool,
+    output: &'a mut dyn Write,
+    /// The steps reached for each open element (and the document)
+    stack: Vec<Vec<usize>>,
+    /// Output for the matched element, the depth of the stack when it
+    /// matched, and its location
+    capture: Option<(Capture, usize, String)>,
+    matches: usize,
+}
+
+//ip Matcher
+impl<'a> Matcher<'a> {
+    //fp new
+    fn new(
+        query: &'a Query,
+        filename: &'a str,
+        xml_output: bool,
+        output: &'a mut dyn Write,
+    ) -> Self {
+        Self {
+            query,
+            filename,
+            xml_output,
+            output,
+            stack: vec![vec![0]],
+            capture: None,
+            matches: 0,
+        }
+    }
+
+    //mp event
+    /// Handle the next event of the document, at a line and column
+    fn event<P: Posn>(
+        &mut self,
+        line: usize,
+        column: usize,
+        ns: &NamespaceStack,
+        event: &Event<P>,
+    ) -> Result<(), String> {
+        let location = format!("{}:{}:{}", self.filename, line, column);
+        match event {
+            Event::StartElement { tag, .. } => {
+                let reached = self.query.advance(self.stack.last().unwrap(), ns, tag);
+                let matched = reached.contains(&self.query.steps.len());
+                self.stack.push(reached);
+                // A match within the captured element is part of its output
+                if matched && self.capture.is_none() {
+                    match &self.query.select {
+                        Select::Attribute(name) => {
+                            if let Some(a) = tag
+                                .attributes
+                                .iter()
+                                .find(|a| a.name.to_string(ns) == *name)
+                            {
+                                self.matches += 1;
+                                writeln!(self.output, "{location}: {}", a.value)
+                                    .map_err(|e| e.to_string())?;
+                            }
+                        }
+                        Select::Text => {
+                            self.capture =
+                                Some((Capture::Text(String::new()), self.stack.len(), location));
+                        }
+                        Select::Element => {
+                            let fragment = Fragment::new(self.xml_output);
+                            self.capture =
+                                Some((Capture::Element(fragment), self.stack.len(), location));
+                        }
+                    }
+                }
+            }
+            Event::EndElement { .. } => {
+                if let Some((Capture::Element(f), _, _)) = &mut self.capture {
+                    f.write(ns, event)?;
+                }
+                if self
+                    .capture
+                    .as_ref()
+                    .is_some_and(|(_, d, _)| *d == self.stack.len())
+                {
+                    let (capture, _, location) = self.capture.take().unwrap();
+                    let output = match capture {
+                        Capture::Element(f) => f.finish()?,
+                        Capture::Text(t) => t,
+                    };
+                    self.matches += 1;
+                    let separator = if output.contains('\n') { "\n" } else { " " };
+                    writeln!(self.output, "{location}:{separator}{output}")
+                        .map_err(|e| e.to_string())?;
+                }
+                self.stack.pop();
+                return Ok(());
+            }
+            Event::Content { ctype, data, .. } => {
+                if let Some((Capture::Text(t), _, _)) = &mut self.capture {
+                    match ctype {
+                        ContentType::Interpretable => {
+                            let text = Escapable::new(data).map_err(|e| e.to_string())?;
+                            t.push_str(text.as_ref());
+                        }
+                        _ => t.push_str(data),
+                    }
+                }
+            }
+            _ => (),
+        }
+        if let Some((Capture::Element(f), _, _)) = &mut self.capture {
+            f.write(ns, event)?;
+        }
+        Ok(())
+    }
+}
+
+//a Documents
+//fi error_message
+/// Format a parse error with its context in the text
+fn error_message<T>(
+    lexer_string: &lexer_rs::LexerOfString<LexerPos, T, HmlError>,
+    filename: &str,
+    e: HmlError,
+) -> String {
+    let mut s = String::new();
+    if let Some(span) = e.span() {
+        lexer_string
+            .fmt_context(&mut s, span.start(), span.end())
+            .unwrap();
+        let start = span.start();
+        s.push_str(&format!(
+            "{}:{}:{}: ",
+            filename,
+            start.line(),
+            start.column()
+        ));
+    }
+    format!("{s}{e}")
+}
+
+//fi query_hml
+/// Parse an HML document and run the matcher over it
+fn query_hml(text: String, matcher: &mut Matcher) -> Result<(), String> {
+    let mut namespace = Namespace::new(true);
+    let mut namespace_stack = NamespaceStack::new(&mut namespace);
+    let lexer_string = lexer_rs::LexerOfString::default().set_text(text);
+    let lexer = lexer_string.lexer();
+    let lexer_parsers = hml_rs::hml_reader::parse_fns();
+    let mut lexer_iter = lexer.iter(&lexer_parsers);
+    let mut parser: Parser<LexerPos> = Parser::default();
+    loop {
+        let event = parser
+            .next_event(&mut namespace_stack, || lexer_iter.next())
+            .map_err(|e| error_message(&lexer_string, matcher.filename, e))?;
+        let start = event.borrow_span().start();
+        matcher.event(start.line(), start.column(), &namespace_stack, &event)?;
+        if event.is_end_document() {
+            return Ok(());
+        }
+    }
+}
+
+//fi query_xml
+/// Parse an XML document and run the matcher over it
+fn query_xml(text: String, matcher: &mut Matcher) -> Result<(), String> {
+    let mut namespace = Namespace::new(true);
+    let mut namespace_stack = NamespaceStack::new(&mut namespace);
+    let lexer_string = lexer_rs::LexerOfString::default().set_text(text);
+    let lexer = lexer_string.lexer();
+    let lexer_parsers = hml_rs::xml_reader::parse_fns();
+    let mut lexer_iter = lexer.iter(&lexer_parsers);
+    let mut parser: hml_rs::xml_reader::Parser<LexerPos> = Default::default();
+    loop {
+        let event = parser
+            .next_event(&mut namespace_stack, || lexer_iter.next())
+            .map_err(|e| error_message(&lexer_string, matcher.filename, e))?;
+        let start = event.borrow_span().start();
+        matcher.event(start.line(), start.column(), &namespace_stack, &event)?;
+        if event.is_end_document() {
+            return Ok(());
+        }
+    }
+}
+
+//a Main
+fn main() {
+    let matches = Command::new("hmlq")
+        .about("Query HML and XML documents")
+        .after_help(
+            "This program reads HML or XML files and prints the elements matching a path, such as '//book[@lang=\"en\"]/title', prefixed by their file, line and column.\n\nA path may end in '/@name' to print an attribute value, or '/text()' to print the text content of the elements; matches do not overlap, so an element that matches within an element that has already matched is printed only as part of that element. The exit status is 0 if anything matched, 1 if nothing matched, and 2 if a file could not be read.",
+        )
+        .author("Gavin J Stark")
+        .version("0.1")
+        .arg(
+            Arg::new("from")
+                .long("from")
+                .help("Format of the input files (default is by extension or content)")
+                .num_args(1)
+                .value_parser(["hml", "xml", "auto"])
+                .default_value("auto"),
+        )
+        .arg(
+            Arg::new("xml")
+                .long("xml")
+                .help("Print matched elements as XML rather than HML")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(Arg::new("path").help("Path to match").required(true))
+        .arg(
+            Arg::new("file")
+                .help("Input files to read (stdin if none)")
+                .num_args(0..),
+        )
+        .get_matches();
+
+    let query = match Query::parse(matches.get_one::<String>("path").unwrap()) {
+        Ok(query) => query,
+        Err(e) => {
+            eprintln!("Bad path: {e}");
+            std::process::exit(2);
+        }
+    };
+    let from = matches.get_one::<String>("from").unwrap().as_str();
+    let xml_output = matches.get_flag("xml");
+    let filenames: Vec<&String> = matches
+        .get_many::<String>("file")
+        .map(|f| f.collect())
+        .unwrap_or_default();
+    let stdin = "-".to_string();
+    let filenames = if filenames.is_empty() {
+        vec![&stdin]
+    } else {
+        filenames
+    };
+
+    let mut stdout = std::io::stdout();
+    let mut total = 0;
+    let mut failed = false;
+    for filename in filenames {
+        let mut text = String::new();
+        let read = if filename == "-" {
+            std::io::stdin().read_to_string(&mut text)
+        } else {
+            File::open(filename).and_then(|mut f| f.read_to_string(&mut text))
+        };
+        if let Err(e) = read {
+            eprintln!("{filename}: {e}");
+            failed = true;
+            continue;
+        }
+        let is_xml = match from {
+            "xml" => true,
+            "hml" => false,
+            _ => match Path::new(filename).extension().and_then(|e| e.to_str()) {
+                Some("xml") => true,
+                Some("hml") => false,
+                _ => text.trim_start().starts_with('<'),
+            },
+        };
+        let mut matcher = Matcher::new(&query, filename, xml_output, &mut stdout);
+        let result = if is_xml {
+            query_xml(text, &mut matcher)
+        } else {
+            query_hml(text, &mut matcher)
+        };
+        total += matcher.matches;
+        if let Err(e) = result {
+            eprintln!("{e}");
+            failed = true;
+        }
+    }
+    std::process::exit(if failed {
+        2
+    } else if total > 0 {
+        0
+    } else {
+        1
+    });
+}
+
+//a Tests
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let q = Query::parse("/a/b").unwrap();
+        assert_eq!(q.steps.len(), 2);
+        assert!(!q.steps[0].descendant);
+        assert_eq!(q.steps[1].name.as_deref(), Some("b"));
+        assert_eq!(q.select, Select::Element);
+
+        let q = Query::parse("book//x:title[@lang='en'][@id]/@ref").unwrap();
+        assert!(q.steps[0].descendant);
+        assert!(q.steps[1].descendant);
+        assert_eq!(q.steps[1].prefix.as_deref(), Some("x"));
+        assert_eq!(q.steps[1].name.as_deref(), Some("title"));
+        assert_eq!(
+            q.steps[1].predicates,
+            vec![("lang".into(), Some("en".into())), ("id".into(), None)]
+        );
+        assert_eq!(q.select, Select::Attribute("ref".into()));
+
+        let q = Query::parse("//*/text()").unwrap();
+        assert_eq!(q.steps[0].name, None);
+        assert_eq!(q.select, Select::Text);
+
+        for bad in ["", "/", "a/", "a[b]", "a[@b=c]", "a[@b", "/@x", "a b"] {
+            assert!(Query::parse(bad).is_err(), "{bad} should not parse");
+        }
+    }
+
+    /// Run a query over an HML document, returning what is output
+    fn query(path: &str, text: &str, xml_output: bool) -> String {
+        let query = Query::parse(path).unwrap();
+        let mut output = Vec::new();
+        let mut matcher = Matcher::new(&query, "q.hml", xml_output, &mut output);
+        query_hml(text.to_string(), &mut matcher).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_query() {
+        let text = "#doc\n##p id='1' \"one\"\n##p\n###b \"two\"\n";
+        assert_eq!(
+            query("//p", text, false),
+            "q.hml:2:1: #p id=\"1\" \"one\"\nq.hml:3:1:\n#p\n##b \"two\"\n"
+        );
+        assert_eq!(
+            query("//p", text, true),
+            "q.hml:2:1: <p id=\"1\">one</p>\nq.hml:3:1:\n<p>\n  <b>two</b>\n</p>\n"
+        );
+        assert_eq!(query("//p/@id", text, false), "q.hml:2:1: 1\n");
+        assert_eq!(query("/doc//text()", text, false), "q.hml:1:1: onetwo\n");
+    }
+}
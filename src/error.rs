@@ -39,6 +39,40 @@ pub enum MarkupError {
         /// Name
         name: String,
     },
+    /// An attribute value that could not be converted to the
+    /// required type
+    #[error("bad value '{value}' for attribute {name}: {reason}")]
+    BadAttributeValue {
+        /// Name of the attribute
+        name: String,
+        /// Value of the attribute
+        value: String,
+        /// Why the conversion failed
+        reason: String,
+    },
+    /// Element content that could not be converted to the required
+    /// type
+    #[error("bad content '{value}': {reason}")]
+    BadContent {
+        /// The content
+        value: String,
+        /// Why the conversion failed
+        reason: String,
+    },
+    /// An attribute with the same URI and local name as one already
+    /// in the list
+    #[error("duplicate attribute {name}")]
+    DuplicateAttribute {
+        /// Name of the attribute
+        name: String,
+    },
+    /// A lookup of an attribute by its local name that matches more
+    /// than one attribute (such as 'a:x' and 'b:x')
+    #[error("ambiguous attribute {name}: more than one attribute has its local name")]
+    AmbiguousAttribute {
+        /// Name of the second attribute with the local name
+        name: String,
+    },
 }
 
 //ip MarkupError
@@ -67,6 +101,59 @@ impl MarkupError {
             name: name.to_string(),
         }
     }
+
+    //cp bad_attribute_value
+    /// Create a [MarkupError] for an attribute value that could not
+    /// be converted
+    pub fn bad_attribute_value(name: &str, value: &str, reason: String) -> Self {
+        Self::BadAttributeValue {
+            name: name.to_string(),
+            value: value.to_string(),
+            reason,
+        }
+    }
+
+    //cp bad_content
+    /// Create a [MarkupError] for content that could not be converted
+    pub fn bad_content(value: &str, reason: String) -> Self {
+        Self::BadContent {
+            value: value.to_string(),
+            reason,
+        }
+    }
+
+    //cp duplicate_attribute
+    /// Create a [MarkupError] for an attribute that duplicates one
+    /// already in an attribute list
+    pub fn duplicate_attribute(name: &str) -> Self {
+        Self::DuplicateAttribute {
+            name: name.to_string(),
+        }
+    }
+
+    //cp ambiguous_attribute
+    /// Create a [MarkupError] for a lookup by local name that matches
+    /// more than one attribute
+    pub fn ambiguous_attribute(name: &str) -> Self {
+        Self::AmbiguousAttribute {
+            name: name.to_string(),
+        }
+    }
+
+    //mp kind
+    /// Get a short, stable identifier for the kind of error, for
+    /// tools that report errors in a machine-readable form
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::EmptyName {} => "empty_name",
+            Self::UnmappedPrefix { .. } => "unmapped_prefix",
+            Self::BadName { .. } => "bad_name",
+            Self::BadAttributeValue { .. } => "bad_attribute_value",
+            Self::BadContent { .. } => "bad_content",
+            Self::DuplicateAttribute { .. } => "duplicate_attribute",
+            Self::AmbiguousAttribute { .. } => "ambiguous_attribute",
+        }
+    }
 }
 
 //a HmlError
@@ -134,6 +221,16 @@ where
         /// Attribute
         attr: String,
     },
+    /// An attribute that duplicates an earlier attribute of the same tag
+    #[error("Duplicate attribute {attr}")]
+    DuplicateAttribute {
+        /// Span of the duplicate attribute
+        span: Span<P>,
+        /// Span of the earlier attribute
+        previous: Span<P>,
+        /// Attribute
+        attr: String,
+    },
     /// Newline in a quoted string
     #[error("Unexpected newline in quoted string")]
     UnexpectedNewlineInQuotedString {
@@ -148,12 +245,162 @@ where
         /// Character
         ch: char,
     },
+    /// A bad escape sequence in a quoted string
+    #[error("Bad escape: {source}")]
+    BadEscape {
+        /// Span of the escape sequence
+        span: Span<P>,
+        /// Error
+        source: crate::hml::escape::Error,
+    },
     /// EOF when it was not expected
     #[error("Unexpected EOF")]
     UnexpectedEOF {
         /// Span of the error
         span: Span<P>,
     },
+    /// A bad entity or character reference in XML text or an XML
+    /// attribute value
+    #[error("Bad reference: {source}")]
+    BadReference {
+        /// Span of the reference
+        span: Span<P>,
+        /// Error
+        source: crate::escape::Error,
+    },
+    /// An entity declaration in the internal subset of an XML
+    /// document type declaration that could not be parsed
+    #[error("Bad entity declaration: {source}")]
+    BadEntityDeclaration {
+        /// Span of the bad text
+        span: Span<P>,
+        /// Error
+        source: crate::escape::Error,
+    },
+    /// An XML end tag that does not match the open element
+    #[error("Expected end tag for {expected}, found {found}")]
+    MismatchedEndTag {
+        /// Span of the end tag
+        span: Span<P>,
+        /// Name of the open element
+        expected: String,
+        /// Name in the end tag
+        found: String,
+    },
+    /// An XML end tag when there is no open element
+    #[error("Unexpected end tag {name}")]
+    UnexpectedEndTag {
+        /// Span of the end tag
+        span: Span<P>,
+        /// Name in the end tag
+        name: String,
+    },
+    /// An XML element that is not closed at the end of the document
+    #[error("Element {name} is not closed")]
+    UnclosedElement {
+        /// Span of the start tag of the element
+        span: Span<P>,
+        /// Name of the element
+        name: String,
+    },
+    /// Content or a second element outside the root element of a
+    /// document
+    #[error("Content outside the root element")]
+    ContentOutsideRoot {
+        /// Span of the content
+        span: Span<P>,
+    },
+    /// An XML declaration that is not at the start of the document
+    #[error("XML declaration must be at the start of the document")]
+    MisplacedDeclaration {
+        /// Span of the declaration
+        span: Span<P>,
+    },
+    /// A tag in a fragment whose depth places it outside the
+    /// fragment's enclosing element
+    #[error("Expected a tag indent of more than {depth}, the base depth of the fragment")]
+    TagOutsideFragment {
+        /// Span of the tag
+        span: Span<P>,
+        /// Base depth of the fragment
+        depth: usize,
+    },
+    /// A document that could not be included
+    #[error("Failed to include '{path}': {source}")]
+    IncludeFailed {
+        /// Span of the include directive
+        span: Span<P>,
+        /// Path of the document
+        path: String,
+        /// Error
+        source: std::io::Error,
+    },
+    /// A document that includes itself, directly or indirectly
+    #[error("Including '{path}' would include it within itself")]
+    IncludeCycle {
+        /// Span of the include directive
+        span: Span<P>,
+        /// Name of the document
+        path: String,
+    },
+    /// An error within an included document
+    #[error("In '{path}': {}", .error.to_string())]
+    InInclude {
+        /// Span of the include directive in the including document
+        span: Span<P>,
+        /// Name of the included document
+        path: String,
+        /// The error, with a span within the included document
+        error: Box<HmlError<P>>,
+    },
+    /// A second top-level element in a document that must have a
+    /// single root element
+    #[error("Only one root element is permitted")]
+    MultipleRootElements {
+        /// Span of the second top-level element
+        span: Span<P>,
+        /// Span of the root element
+        root: Span<P>,
+    },
+    /// A document that must have a root element has none
+    #[error("The document has no root element")]
+    MissingRootElement {
+        /// Span of the end of the document
+        span: Span<P>,
+    },
+    /// An event that is not valid at its point in an event stream
+    #[error("Unexpected {event:?} event: {reason}")]
+    UnexpectedEvent {
+        /// Span of the event
+        span: Span<P>,
+        /// Type of the event
+        event: crate::markup::EventType,
+        /// Why the event is not valid
+        reason: &'static str,
+    },
+    /// A conditional directive ('if') with no matching 'endif' at
+    /// the same depth
+    #[error("Conditional directive is not closed by an 'endif' at the same depth")]
+    UnclosedConditional {
+        /// Span of the 'if' directive
+        span: Span<P>,
+    },
+    /// A preprocessor directive or substitution that is not valid
+    #[error("Bad directive: {reason}")]
+    BadDirective {
+        /// Span of the directive
+        span: Span<P>,
+        /// Why the directive is not valid
+        reason: &'static str,
+    },
+    /// A substitution of a variable that is not defined
+    #[error("Undefined variable '{name}'")]
+    UndefinedVariable {
+        /// Span of the content or attribute value
+        span: Span<P>,
+        /// Name of the variable
+        name: String,
+    },
 }
 
 //ip HmlError
@@ -208,6 +455,37 @@ where
         Err(Self::UnexpectedAttribute { span, attr })
     }
 
+    //fp duplicate_attribute
+    /// Return a duplicate_attribute error for an attribute span and
+    /// the span of the attribute it duplicates
+    pub fn duplicate_attribute<T>(span: Span<P>, previous: Span<P>, attr: &str) -> HmlResult<T, P> {
+        let attr = attr.to_string();
+        Err(Self::DuplicateAttribute {
+            span,
+            previous,
+            attr,
+        })
+    }
+
+    //fp bad_escape
+    /// Create a bad escape error from an error unescaping the
+    /// contents `data` of the quoted string token with span `span`
+    pub fn bad_escape(span: &Span<P>, data: &str, source: crate::hml::escape::Error) -> Self {
+        let span = source.span_within(span, data);
+        Self::BadEscape { span, source }
+    }
+
+    //fp map_escape_error
+    /// Map a result of unescaping the contents `data` of a quoted
+    /// string token with span `span` to an [HmlResult]
+    pub fn map_escape_error<T>(
+        result: crate::hml::escape::Result<T>,
+        span: &Span<P>,
+        data: &str,
+    ) -> HmlResult<T, P> {
+        result.map_err(|e| Self::bad_escape(span, data, e))
+    }
+
     //fp io_error
     /// An IO error
     pub fn io_error(span: Span<P>, source: std::io::Error) -> Self {
@@ -240,13 +518,71 @@ where
             Self::UnexpectedCharacter { span, .. } => Some(span),
             Self::UnexpectedTagIndent { span, .. } => Some(span),
             Self::UnexpectedAttribute { span, .. } => Some(span),
+            Self::DuplicateAttribute { span, .. } => Some(span),
             Self::UnexpectedEOF { span, .. } => Some(span),
+            Self::BadEscape { span, .. } => Some(span),
             Self::UnexpectedNewlineInQuotedString { span, .. } => Some(span),
             Self::ExpectedEquals { span, .. } => Some(span),
             Self::BeyondEndOfTokens => None,
             Self::MarkupError { span, .. } => Some(span),
             Self::ExpectedTagName { span, .. } => Some(span),
             Self::ExpectedWhitespaceAfterTag { span, .. } => Some(span),
+            Self::BadReference { span, .. } => Some(span),
+            Self::BadEntityDeclaration { span, .. } => Some(span),
+            Self::MismatchedEndTag { span, .. } => Some(span),
+            Self::UnexpectedEndTag { span, .. } => Some(span),
+            Self::UnclosedElement { span, .. } => Some(span),
+            Self::ContentOutsideRoot { span, .. } => Some(span),
+            Self::MisplacedDeclaration { span, .. } => Some(span),
+            Self::TagOutsideFragment { span, .. } => Some(span),
+            Self::IncludeFailed { span, .. } => Some(span),
+            Self::IncludeCycle { span, .. } => Some(span),
+            Self::InInclude { span, .. } => Some(span),
+            Self::MultipleRootElements { span, .. } => Some(span),
+            Self::MissingRootElement { span, .. } => Some(span),
+            Self::UnexpectedEvent { span, .. } => Some(span),
+            Self::UnclosedConditional { span, .. } => Some(span),
+            Self::BadDirective { span, .. } => Some(span),
+            Self::UndefinedVariable { span, .. } => Some(span),
+        }
+    }
+
+    //mp kind
+    /// Get a short, stable identifier for the kind of error, for
+    /// tools that report errors in a machine-readable form; for a
+    /// markup error this is the kind of the [MarkupError]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::IoError { .. } => "io_error",
+            Self::MarkupError { source, .. } => source.kind(),
+            Self::ExpectedTagName { .. } => "expected_tag_name",
+            Self::ExpectedWhitespaceAfterTag { .. } => "expected_whitespace_after_tag",
+            Self::UnexpectedCharacter { .. } => "unexpected_character",
+            Self::UnexpectedTagIndent { .. } => "unexpected_tag_indent",
+            Self::BeyondEndOfTokens => "beyond_end_of_tokens",
+            Self::UnexpectedAttribute { .. } => "unexpected_attribute",
+            Self::DuplicateAttribute { .. } => "duplicate_attribute",
+            Self::UnexpectedNewlineInQuotedString { .. } => "unexpected_newline_in_quoted_string",
+            Self::ExpectedEquals { .. } => "expected_equals",
+            Self::BadEscape { .. } => "bad_escape",
+            Self::UnexpectedEOF { .. } => "unexpected_eof",
+            Self::BadReference { .. } => "bad_reference",
+            Self::BadEntityDeclaration { .. } => "bad_entity_declaration",
+            Self::MismatchedEndTag { .. } => "mismatched_end_tag",
+            Self::UnexpectedEndTag { .. } => "unexpected_end_tag",
+            Self::UnclosedElement { .. } => "unclosed_element",
+            Self::ContentOutsideRoot { .. } => "content_outside_root",
+            Self::MisplacedDeclaration { .. } => "misplaced_declaration",
+            Self::TagOutsideFragment { .. } => "tag_outside_fragment",
+            Self::IncludeFailed { .. } => "include_failed",
+            Self::IncludeCycle { .. } => "include_cycle",
+            Self::InInclude { error, .. } => error.kind(),
+            Self::MultipleRootElements { .. } => "multiple_root_elements",
+            Self::MissingRootElement { .. } => "missing_root_element",
+            Self::UnexpectedEvent { .. } => "unexpected_event",
+            Self::UnclosedConditional { .. } => "unclosed_conditional",
+            Self::BadDirective { .. } => "bad_directive",
+            Self::UndefinedVariable { .. } => "undefined_variable",
         }
     }
 
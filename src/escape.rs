@@ -5,303 +5,595 @@
 
 # Escape handling
 
-This module is not ready for use
-
 This module provides escape handling for XML and entity replacement
 
+Escaping converts text into a form that may be placed in an XML
+document in a particular context - as element content
+([escape_text]), as an attribute value within a chosen quote
+character ([escape_attribute]), or within a CDATA section
+([escape_cdata]). The characters that are escaped can also be
+chosen explicitly with [escape] and a bitmask of the `ESCAPE_*`
+values; '&' and '<' are always escaped.
+
+Unescaping converts entity references ('&amp;' etc) and character
+references ('&#38;', '&#x26;') back into characters, using a set
+of [Entities]; malformed or unknown references are reported as an
+[Error] with the byte offsets of the reference within the string.
+Entities may be declared (from XML '<!ENTITY ...>' declarations), and
+their values are expanded recursively, with limits on the depth and
+size of the expansion.
+
+All of the functions return a [Cow<str>], which borrows the input
+string if no change is required.
+
 !*/
 
 //a Imports
+use std::borrow::Cow;
 use std::collections::HashMap;
 
+use thiserror::Error;
+
+use crate::hml_reader::utils::{is_name, is_name_start};
+
+//a Error
+//tp Error
+/// An error in unescaping a string; the offsets are byte offsets
+/// within the string being unescaped, and the range covers the
+/// erroneous reference
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A '&' that is not followed by a name or character reference
+    /// and a terminating ';'
+    #[error("unterminated reference at bytes {start}..{end}")]
+    UnterminatedReference {
+        /// Byte offset of the '&'
+        start: usize,
+        /// Byte offset of the end of the reference
+        end: usize,
+    },
+    /// A character reference that is not a valid Unicode character
+    #[error("invalid character reference at bytes {start}..{end}")]
+    InvalidCharRef {
+        /// Byte offset of the '&'
+        start: usize,
+        /// Byte offset just beyond the ';'
+        end: usize,
+    },
+    /// A reference to an entity that is not in the set of entities
+    #[error("unknown entity '{name}' at bytes {start}..{end}")]
+    UnknownEntity {
+        /// Byte offset of the '&'
+        start: usize,
+        /// Byte offset just beyond the ';'
+        end: usize,
+        /// The name of the entity
+        name: String,
+    },
+    /// An entity whose expansion references itself
+    #[error("recursive reference to entity '{name}' at bytes {start}..{end}")]
+    RecursiveEntity {
+        /// Byte offset of the '&'
+        start: usize,
+        /// Byte offset just beyond the ';'
+        end: usize,
+        /// The name of the entity
+        name: String,
+    },
+    /// Entity expansion nested more deeply than permitted
+    #[error("entity expansion too deep at bytes {start}..{end}")]
+    DepthExceeded {
+        /// Byte offset of the '&'
+        start: usize,
+        /// Byte offset just beyond the ';'
+        end: usize,
+    },
+    /// Entity expansion produced more text than permitted
+    #[error("entity expansion too large at bytes {start}..{end}")]
+    ExpansionTooLarge {
+        /// Byte offset of the start of the text or reference
+        start: usize,
+        /// Byte offset of the end of the text or reference
+        end: usize,
+    },
+    /// An entity declaration that could not be parsed
+    #[error("bad entity declaration at bytes {start}..{end}")]
+    BadDeclaration {
+        /// Byte offset of the start of the bad text
+        start: usize,
+        /// Byte offset of the end of the declaration
+        end: usize,
+    },
+}
+
+//ip Error
+impl Error {
+    //ap byte_range
+    /// Get the range of bytes of the string that are in error
+    pub fn byte_range(&self) -> std::ops::Range<usize> {
+        match self {
+            Self::UnterminatedReference { start, end }
+            | Self::InvalidCharRef { start, end }
+            | Self::UnknownEntity { start, end, .. }
+            | Self::RecursiveEntity { start, end, .. }
+            | Self::DepthExceeded { start, end }
+            | Self::ExpansionTooLarge { start, end }
+            | Self::BadDeclaration { start, end } => *start..*end,
+        }
+    }
+
+    //mi range_mut
+    fn range_mut(&mut self) -> (&mut usize, &mut usize) {
+        match self {
+            Self::UnterminatedReference { start, end }
+            | Self::InvalidCharRef { start, end }
+            | Self::UnknownEntity { start, end, .. }
+            | Self::RecursiveEntity { start, end, .. }
+            | Self::DepthExceeded { start, end }
+            | Self::ExpansionTooLarge { start, end }
+            | Self::BadDeclaration { start, end } => (start, end),
+        }
+    }
+
+    //mp offset_by
+    /// Move the byte range of the error by an offset, for an error in
+    /// a substring that starts at that offset
+    pub fn offset_by(mut self, ofs: usize) -> Self {
+        let (start, end) = self.range_mut();
+        *start += ofs;
+        *end += ofs;
+        self
+    }
+
+    //mp relocate
+    /// Set the byte range of the error
+    pub fn relocate(mut self, new_start: usize, new_end: usize) -> Self {
+        let (start, end) = self.range_mut();
+        *start = new_start;
+        *end = new_end;
+        self
+    }
+}
+
 /// Result of unescaping/unentity-ify a string
-pub type Result<T> = std::result::Result<T, std::io::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
 
-// Bit mask of escapes that should be parsed
+//a Escaping
+// Bit mask of characters that should be escaped in addition to '&' and '<'
 //cp ESCAPE_QUOTE
-/// Bitmask to enable unescaping of &quot;
+/// Bitmask to enable escaping of '"' as &quot;
 pub const ESCAPE_QUOTE: usize = 1;
 //cp ESCAPE_APOS
-/// Bitmask to enable unescaping of &apos;
+/// Bitmask to enable escaping of '\'' as &apos;
 pub const ESCAPE_APOS: usize = 2;
 //cp ESCAPE_GT
-/// Bitmask to enable unescaping of &gt;
+/// Bitmask to enable escaping of '>' as &gt;
 pub const ESCAPE_GT: usize = 4;
 //cp ESCAPE_LF
-/// Bitmask to enable unescaping of &#xA;
+/// Bitmask to enable escaping of newline as &#xA;
 pub const ESCAPE_LF: usize = 8;
 //cp ESCAPE_CR
-/// Bitmask to enable unescaping of &#xD;
+/// Bitmask to enable escaping of carriage return as &#xD;
 pub const ESCAPE_CR: usize = 16;
+//cp ESCAPE_TAB
+/// Bitmask to enable escaping of tab as &#x9;
+pub const ESCAPE_TAB: usize = 32;
 
 //cp ESCAPE_ATTR
-/// Bitmask to enable unescaping of all attributes
-pub const ESCAPE_ATTR: usize = ESCAPE_QUOTE | ESCAPE_APOS | ESCAPE_GT | ESCAPE_LF | ESCAPE_CR;
+/// Bitmask to enable escaping of all the characters that can be
+/// escaped
+pub const ESCAPE_ATTR: usize =
+    ESCAPE_QUOTE | ESCAPE_APOS | ESCAPE_GT | ESCAPE_LF | ESCAPE_CR | ESCAPE_TAB;
 
 //cp ESCAPE_PCDATA
-/// Bitmask used to unescape PCDATA - that is, none
+/// Bitmask used to escape PCDATA - that is, just '&' and '<'
 pub const ESCAPE_PCDATA: usize = 0;
 
-#[inline(always)]
-fn do_esc(char_set: usize, esc: usize) -> bool {
-    (char_set & esc) != 0
+//cp ESCAPE_TEXT
+/// Bitmask used by [escape_text]; '>' is escaped so that ']]>' cannot
+/// appear, and carriage returns so that they survive line-end
+/// normalization
+pub const ESCAPE_TEXT: usize = ESCAPE_GT | ESCAPE_CR;
+
+//tp Quote
+/// The quote character used to delimit an attribute value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quote {
+    /// The attribute value is within '"'
+    #[default]
+    Double,
+    /// The attribute value is within '\''
+    Single,
 }
 
-//fp escape_required
-/// Return a Some(string) where string is an unescaped version of the input
-pub fn escape_required(bytes: &[u8], char_set: usize, i: usize, n: usize) -> Option<String> {
-    let mut r = Vec::with_capacity(n);
-    if i > 0 {
-        r.extend_from_slice(&bytes[0..i]);
-    }
-    // for i in i..n {
-    for b in bytes.iter().take(n).skip(i) {
-        if b & 0x80 != 0 {
-            r.push(*b);
-        } else {
-            match b {
-                b'&' => {
-                    r.extend_from_slice(b"&amp;");
-                }
-                b'<' => {
-                    r.extend_from_slice(b"&lt;");
-                }
-                b'\'' if do_esc(char_set, ESCAPE_APOS) => {
-                    r.extend_from_slice(b"&apos;");
-                }
-                b'\"' if do_esc(char_set, ESCAPE_QUOTE) => {
-                    r.extend_from_slice(b"&quot;");
-                }
-                b'>' if do_esc(char_set, ESCAPE_GT) => {
-                    r.extend_from_slice(b"&gt;");
-                }
-                b'\n' if do_esc(char_set, ESCAPE_LF) => {
-                    r.extend_from_slice(b"&#xA;");
-                }
-                b'\r' if do_esc(char_set, ESCAPE_CR) => {
-                    r.extend_from_slice(b"&#xD;");
-                }
-                _ => {
-                    r.push(*b);
-                }
-            }
+//ip Quote
+impl Quote {
+    //ap as_char
+    /// Get the quote character
+    pub fn as_char(&self) -> char {
+        match self {
+            Self::Double => '"',
+            Self::Single => '\'',
+        }
+    }
+
+    //ap escape_mask
+    /// Get the escape bitmask for the quote character
+    pub fn escape_mask(&self) -> usize {
+        match self {
+            Self::Double => ESCAPE_QUOTE,
+            Self::Single => ESCAPE_APOS,
         }
     }
-    let string = unsafe { String::from_utf8_unchecked(r) };
-    Some(string)
+}
+
+//fi escape_of
+/// Get the escape for a character given the bitmask, if it needs one
+#[inline]
+fn escape_of(ch: char, char_set: usize) -> Option<&'static str> {
+    let (esc, mask) = match ch {
+        '&' => return Some("&amp;"),
+        '<' => return Some("&lt;"),
+        '"' => ("&quot;", ESCAPE_QUOTE),
+        '\'' => ("&apos;", ESCAPE_APOS),
+        '>' => ("&gt;", ESCAPE_GT),
+        '\n' => ("&#xA;", ESCAPE_LF),
+        '\r' => ("&#xD;", ESCAPE_CR),
+        '\t' => ("&#x9;", ESCAPE_TAB),
+        _ => return None,
+    };
+    if (char_set & mask) != 0 {
+        Some(esc)
+    } else {
+        None
+    }
 }
 
 //fp escape
-/// Return Some(string) if escaping is needed (given char_set), else None
-pub fn escape(s: &str, char_set: usize) -> Option<String> {
-    // Note that n == s.len is the length in bytes, not in utf8 characters
-    let n = s.len();
-    let bytes = s.as_bytes();
-    for i in 0..n {
-        match bytes[i] {
-            b'&' => {
-                return escape_required(bytes, char_set, i, n);
-            }
-            b'<' => {
-                return escape_required(bytes, char_set, i, n);
-            }
-            b'\'' if do_esc(char_set, ESCAPE_APOS) => {
-                return escape_required(bytes, char_set, i, n);
-            }
-            b'\"' if do_esc(char_set, ESCAPE_QUOTE) => {
-                return escape_required(bytes, char_set, i, n);
-            }
-            b'>' if do_esc(char_set, ESCAPE_GT) => {
-                return escape_required(bytes, char_set, i, n);
-            }
-            b'\n' if do_esc(char_set, ESCAPE_LF) => {
-                return escape_required(bytes, char_set, i, n);
-            }
-            b'\r' if do_esc(char_set, ESCAPE_CR) => {
-                return escape_required(bytes, char_set, i, n);
-            }
-            _ => (),
+/// Escape a string, always escaping '&' and '<', and escaping the
+/// other characters selected by the `char_set` bitmask
+///
+/// The string is borrowed if no escaping is required
+pub fn escape(s: &str, char_set: usize) -> Cow<'_, str> {
+    let Some(first) = s.find(|ch| escape_of(ch, char_set).is_some()) else {
+        return Cow::Borrowed(s);
+    };
+    let mut r = String::with_capacity(s.len() + 8);
+    r.push_str(&s[0..first]);
+    for ch in s[first..].chars() {
+        match escape_of(ch, char_set) {
+            Some(esc) => r.push_str(esc),
+            None => r.push(ch),
+        }
+    }
+    Cow::Owned(r)
+}
+
+//fp escape_text
+/// Escape a string for use as the character content of an element
+pub fn escape_text(s: &str) -> Cow<'_, str> {
+    escape(s, ESCAPE_TEXT)
+}
+
+//fp escape_attribute
+/// Escape a string for use as an attribute value delimited by the
+/// given quote character
+///
+/// Whitespace other than space is escaped, as attribute value
+/// normalization would otherwise convert it to spaces
+pub fn escape_attribute(s: &str, quote: Quote) -> Cow<'_, str> {
+    escape(s, quote.escape_mask() | ESCAPE_LF | ESCAPE_CR | ESCAPE_TAB)
+}
+
+//fp escape_cdata
+/// Escape a string for use as the contents of a CDATA section
+/// (between '<![CDATA[' and ']]>')
+///
+/// A CDATA section cannot contain ']]>', so any occurrence is split
+/// across two CDATA sections
+pub fn escape_cdata(s: &str) -> Cow<'_, str> {
+    if s.contains("]]>") {
+        Cow::Owned(s.replace("]]>", "]]]]><![CDATA[>"))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+//fi xml_char
+/// Classify a character for an XML version (100 or 110): None if it
+/// cannot appear in a document, Some(true) if it is a restricted
+/// character of XML 1.1 (which must be written as a character
+/// reference), and Some(false) if it may be written as it is
+fn xml_char(ch: char, version: usize) -> Option<bool> {
+    match ch {
+        '\t' | '\n' | '\r' => Some(false),
+        '\u{0}' | '\u{fffe}' | '\u{ffff}' => None,
+        '\u{1}'..='\u{1f}' if version >= 110 => Some(true),
+        '\u{1}'..='\u{1f}' => None,
+        '\u{7f}'..='\u{84}' | '\u{86}'..='\u{9f}' => Some(version >= 110),
+        _ => Some(false),
+    }
+}
+
+//fp escape_chars
+/// Check that a string has only characters that an XML document of
+/// the version (100 for 1.0, or 110 for 1.1) may contain, writing
+/// the restricted characters of XML 1.1 as character references if
+/// `references` is true (where markup permits them)
+///
+/// The string is borrowed if no references are required; the error
+/// is the first character that cannot be written
+pub fn escape_chars(
+    s: &str,
+    version: usize,
+    references: bool,
+) -> std::result::Result<Cow<'_, str>, char> {
+    let Some(first) = s.find(|ch| xml_char(ch, version) != Some(false)) else {
+        return Ok(Cow::Borrowed(s));
+    };
+    let mut r = String::with_capacity(s.len() + 8);
+    r.push_str(&s[0..first]);
+    for ch in s[first..].chars() {
+        match xml_char(ch, version) {
+            Some(false) => r.push(ch),
+            Some(true) if references => r.push_str(&format!("&#x{:X};", ch as u32)),
+            _ => return Err(ch),
         }
     }
-    None
+    Ok(Cow::Owned(r))
+}
+
+//a Entities
+//ti Entity
+/// The value of an entity, and whether references within the value
+/// are expanded when the entity is used
+#[derive(Debug, Clone)]
+struct Entity {
+    value: String,
+    expand: bool,
 }
 
+//cp DEFAULT_MAX_DEPTH
+/// Default maximum depth of nested entity expansion
+pub const DEFAULT_MAX_DEPTH: usize = 16;
+
+//cp DEFAULT_MAX_EXPANDED_SIZE
+/// Default maximum size in bytes of a string after entity expansion
+pub const DEFAULT_MAX_EXPANDED_SIZE: usize = 1 << 20;
+
 //tp Entities
 /// A set of entities that should be unmapped and how they should be unmapped
-#[derive(Default)]
-pub struct Entities<'a> {
-    map: HashMap<&'a [u8], &'a str>,
+///
+/// Entities may be predefined (such as 'amp' for XML), in which case
+/// their values are used verbatim, or declared (from an XML '<!ENTITY
+/// ...>' declaration), in which case references within their values
+/// are expanded when they are used.
+///
+/// Expansion is limited in depth and in total expanded size, so that
+/// untrusted documents cannot use nested entities to consume
+/// unbounded memory or time
+#[derive(Debug, Clone)]
+pub struct Entities {
+    map: HashMap<String, Entity>,
+    max_depth: usize,
+    max_expanded_size: usize,
+}
+
+//ip Default for Entities
+impl Default for Entities {
+    fn default() -> Self {
+        Self {
+            map: HashMap::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_expanded_size: DEFAULT_MAX_EXPANDED_SIZE,
+        }
+    }
 }
 
 //ip Entities
-impl<'a> Entities<'a> {
+impl Entities {
     //fp xml
     /// Create a new Entities set for XML entity parsing
     pub fn xml() -> Self {
-        let mut map: HashMap<&[u8], &str> = HashMap::new();
-        map.insert(b"amp", "&");
-        map.insert(b"AMP", "&");
-        map.insert(b"lt", "<");
-        map.insert(b"LT", "<");
-        map.insert(b"gt", ">");
-        map.insert(b"GT", ">");
-        map.insert(b"apos", "'");
-        map.insert(b"APOS", "'");
-        map.insert(b"quot", "\"");
-        map.insert(b"QUOT", "\"");
-        Self { map }
-    }
-
-    //fp find_span
-    /// Find the span starting with the given index `i` that is either
-    /// from an entity (starting with '&' ending with ';') - which is
-    /// then unmapped if possible, or the span until the end of string
-    /// or the next entity.
-    ///
-    /// The return value is the index of the end of the span, and a
-    /// possible replacement string or replacement character - if the
-    /// span is an entity it can be mapped to either of these (or an
-    /// unknown/bad entity is just a simple span).
-    ///
-    /// Hence a return value of (n, Some(r), None) indicates that from
-    /// `i` to `n` (inclusive to exclusive) is an entity that can be
-    /// replaced with the string `r`.
-    ///
-    /// A return value of (n, None, Some(c)) indicates that from
-    /// `i` to `n` (inclusive to exclusive) is an entity that can be
-    /// replaced with the character `c`.
-    ///
-    /// The other possible return value is (n, None, None), indicating
-    /// that the span from `i` to `n` contains no entity references
-    fn find_span(
-        &self,
-        inc_map: bool,
-        bytes: &[u8],
-        mut i: usize,
-        n: usize,
-    ) -> (usize, Option<&str>, Option<char>) {
-        if bytes[i] == b'&' {
-            i += 1;
-            let start = i;
-            let mut is_hex = false;
-            let mut is_dec = true;
-            let mut value = 0;
-            while i < n {
-                let b = bytes[i];
-                if b == b';' {
-                    if inc_map {
-                        if let Some(c) = self.map.get(&bytes[start..i]) {
-                            return (i + 1, Some(c), None);
-                        }
-                    }
-                    if is_hex || is_dec {
-                        if let Ok(c) = char::try_from(value) {
-                            return (i + 1, None, Some(c));
-                        }
-                    }
-                    i += 1;
-                    break;
-                }
-                if i == start {
-                    if b != b'#' {
-                        is_dec = false;
-                    }
-                } else if (b'a'..=b'f').contains(&b) || (b'A'..=b'F').contains(&b) {
-                    value = (value << 4) | (((b & 0xf) + 9) as u32);
-                    is_dec = false;
-                } else if b == b'x' {
-                    if i == start + 1 && is_dec {
-                        is_hex = true;
-                    }
-                    is_dec = false;
-                } else if b.is_ascii_digit() {
-                    if is_dec {
-                        value = (value * 10).wrapping_add((b - b'0') as u32);
-                    } else {
-                        value = (value << 4) | ((b & 0xf) as u32);
-                    }
-                    if value > 0x10ffff {
-                        is_dec = false;
-                        is_hex = false;
-                        value = 0;
-                    }
-                } else {
-                    is_dec = false;
-                    is_hex = false;
-                }
-                i += 1;
-            }
-            (i, None, None)
-        } else {
-            i += 1;
-            while i < n {
-                if bytes[i] == b'&' {
-                    break;
-                }
-                i += 1;
-            }
-            (i, None, None)
+        let mut entities = Self::default();
+        for (name, value) in [
+            ("amp", "&"),
+            ("AMP", "&"),
+            ("lt", "<"),
+            ("LT", "<"),
+            ("gt", ">"),
+            ("GT", ">"),
+            ("apos", "'"),
+            ("APOS", "'"),
+            ("quot", "\""),
+            ("QUOT", "\""),
+        ] {
+            entities.insert(name, value);
         }
+        entities
     }
 
-    //fp replace_entities
-    /// Replace general entity references and &#..; characters, using the map.
+    //cp set_max_depth
+    /// Set the maximum depth of nested entity expansion
+    pub fn set_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    //cp set_max_expanded_size
+    /// Set the maximum size in bytes of a string after entity
+    /// expansion (each entity reference expanded also counts as one
+    /// byte, so that expanding empty entities is limited too)
+    pub fn set_max_expanded_size(mut self, max_expanded_size: usize) -> Self {
+        self.max_expanded_size = max_expanded_size;
+        self
+    }
+
+    //mp insert
+    /// Add a predefined entity to the set, replacing any previous
+    /// value; the value is used verbatim when the entity is
+    /// referenced
+    pub fn insert(&mut self, name: &str, value: &str) {
+        let entity = Entity {
+            value: value.to_string(),
+            expand: false,
+        };
+        self.map.insert(name.to_string(), entity);
+    }
+
+    //mp declare
+    /// Declare an entity with a literal value from a declaration; the
+    /// character references in the value are replaced, and entity
+    /// references in the value are expanded when the entity is used
     ///
-    /// The buffer `bytes` is the source and it has length `n`.
+    /// As for XML, the first declaration of an entity is binding;
+    /// this returns Ok(false) if the entity was already declared
+    pub fn declare(&mut self, name: &str, value: &str) -> Result<bool> {
+        if self.map.contains_key(name) {
+            return Ok(false);
+        }
+        let value = self.replace_entities(false, value)?.into_owned();
+        let entity = Entity {
+            value,
+            expand: true,
+        };
+        self.map.insert(name.to_string(), entity);
+        Ok(true)
+    }
+
+    //mp parse_declaration
+    /// Parse an XML entity declaration ('<!ENTITY name "value">',
+    /// with the value in single or double quotes) and declare the
+    /// entity
     ///
-    /// The buffer at `bytes` has the span from 0..d as a valid UTF8 string;
-    /// at `d` there is an entity that ends at `i` which should be replaced with `c`.
+    /// Returns Ok(false) if the entity was already declared
+    pub fn parse_declaration(&mut self, s: &str) -> Result<bool> {
+        let bad = |start: usize, end: usize| Error::BadDeclaration { start, end };
+        let trimmed = s.trim_end();
+        let start = s.len() - s.trim_start().len();
+        if !(trimmed[start..].starts_with("<!ENTITY") && trimmed.ends_with('>')) {
+            return Err(bad(start, trimmed.len()));
+        }
+        let (mut i, end) = (start + 8, trimmed.len() - 1);
+        let skip_ws = |i: usize| i + (s[i..end].len() - s[i..end].trim_start().len());
+
+        let name_start = skip_ws(i);
+        if name_start == i {
+            return Err(bad(i, end));
+        }
+        let name_end = s[name_start..end]
+            .find(|ch: char| !is_name(ch))
+            .map_or(end, |n| name_start + n);
+        if !s[name_start..name_end].starts_with(is_name_start) {
+            return Err(bad(name_start, end));
+        }
+        i = skip_ws(name_end);
+        let quote = s[i..end].chars().next();
+        if i == name_end || !matches!(quote, Some('"') | Some('\'')) {
+            return Err(bad(i, end));
+        }
+        let value_start = i + 1;
+        let Some(n) = s[value_start..end].find(quote.unwrap()) else {
+            return Err(bad(i, end));
+        };
+        let value_end = value_start + n;
+        if skip_ws(value_end + 1) != end {
+            return Err(bad(value_end + 1, end));
+        }
+        self.declare(&s[name_start..name_end], &s[value_start..value_end])
+            .map_err(|e| e.offset_by(value_start))
+    }
+
+    //mp remove
+    /// Remove an entity from the set, returning its value
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        self.map.remove(name).map(|e| e.value)
+    }
+
+    //mp get
+    /// Get the value of an entity, if it is in the set
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.map.get(name).map(|e| e.value.as_str())
+    }
+
+    //mi parse_reference
+    /// Parse the reference that starts with the '&' at byte offset
+    /// `start` in `s`
     ///
-    /// From `i` there may be more entities that require replacement.
-    fn replace_entities_required(
-        &self,
+    /// Return the byte offset just beyond the terminating ';' and the
+    /// replacement; a replacement of None indicates a named reference
+    /// that is to be left unchanged (as `inc_map` is false)
+    fn parse_reference<'e>(
+        &'e self,
         inc_map: bool,
-        bytes: &[u8],
-        c: &str,
-        d: usize,
-        mut i: usize,
-        n: usize,
-    ) -> Option<String> {
-        let mut r = Vec::with_capacity(n);
-        if d > 0 {
-            r.extend_from_slice(&bytes[0..d]);
+        s: &str,
+        start: usize,
+    ) -> Result<(usize, Option<Replacement<'e>>)> {
+        let body_start = start + 1;
+        let Some(len) = s[body_start..].find(|ch: char| !(ch == '#' || is_name(ch))) else {
+            return Err(Error::UnterminatedReference {
+                start,
+                end: s.len(),
+            });
+        };
+        let body_end = body_start + len;
+        let body = &s[body_start..body_end];
+        if body.is_empty() || !s[body_end..].starts_with(';') {
+            return Err(Error::UnterminatedReference {
+                start,
+                end: body_end,
+            });
         }
-        r.extend_from_slice(c.as_bytes());
-        while i < n {
-            let (next_i, opt_a, opt_b) = self.find_span(inc_map, bytes, i, n);
-            if let Some(c) = opt_a {
-                r.extend_from_slice(c.as_bytes());
-            } else if let Some(c) = opt_b {
-                let mut buf = [0; 4];
-                let buf = c.encode_utf8(&mut buf).as_bytes();
-                r.extend_from_slice(buf);
-            } else {
-                r.extend_from_slice(&bytes[i..next_i]);
+        let end = body_end + 1;
+        if let Some(number) = body.strip_prefix('#') {
+            let value = match number.strip_prefix('x') {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => number.parse::<u32>(),
+            };
+            match value.ok().and_then(char::from_u32) {
+                Some(c) if !number.starts_with('+') && c != '\0' => {
+                    Ok((end, Some(Replacement::Char(c))))
+                }
+                _ => Err(Error::InvalidCharRef { start, end }),
             }
-            i = next_i;
+        } else if body.contains('#') {
+            Err(Error::UnterminatedReference {
+                start,
+                end: body_end,
+            })
+        } else if !inc_map {
+            Ok((end, None))
+        } else if let Some((name, entity)) = self.map.get_key_value(body) {
+            Ok((end, Some(Replacement::Entity(name, entity))))
+        } else {
+            Err(Error::UnknownEntity {
+                start,
+                end,
+                name: body.to_string(),
+            })
         }
-        let string = unsafe { String::from_utf8_unchecked(r) };
-        Some(string)
     }
 
-    //fp replace_entities
+    //mp replace_entities
     /// Replace general entity references and &#..; characters, using the map.
     ///
-    /// Return None if the string has no replacements required; else Some(new string).
+    /// Return the string borrowed if it has no replacements
+    /// required; else an owned new string. A reference that is
+    /// malformed, is not a valid character, or (if `inc_map` is true)
+    /// is to an entity not in the set, is an error.
     ///
-    /// The replacements that are used should *also* be replaced if this is expanding a general entity use.
+    /// The replacements that are used are *not* themselves expanded;
+    /// use [Entities::unescape] for that.
     ///
     /// We don't handle parameter entities here yet ('%thing;')
     ///
     /// However, the map should not be used for entity declaration
-    /// contents in XML hence inc_map is provided. However, character
+    /// contents in XML hence inc_map is provided; if it is false then
+    /// named entity references are left unchanged. However, character
     /// entities &#..; are expanded in entity declarations.
     ///
     /// Character entities are *ALSO* expanded when entities are used.
     ///
-    /// Another option would be to use two different [Entities] to
-    /// handle the two different cases.
-    ///
     /// <!ENTITY example "<p>An ampersand (&#38;#38;) may be escaped
     /// numerically (&#38;#38;#38;) or with a general entity
     /// (&amp;amp;).</p>" >
@@ -318,31 +610,164 @@ impl<'a> Entities<'a> {
     /// numerically (&#38;) or with a general entity
     /// (&amp;).
     ///
-    pub fn replace_entities(&self, inc_map: bool, s: &str) -> Option<String> {
-        // Note that s.len is the length in bytes, not in utf8 characters
-        let n = s.len();
-        let bytes = s.as_bytes();
+    pub fn replace_entities<'s>(&self, inc_map: bool, s: &'s str) -> Result<Cow<'s, str>> {
+        let mut r: Option<String> = None;
+        let mut copied_to = 0;
         let mut i = 0;
-        while i < n {
-            // Find next span
-            //
-            let (next_i, opt_a, opt_b) = self.find_span(inc_map, bytes, i, n);
-            if let Some(c) = opt_a {
-                // The return from find_span was(n, Some(c:&str), None): the span up to `n` is
-                // an entity reference to be replaced with `c`
-                return self.replace_entities_required(inc_map, bytes, c, i, next_i, n);
-            } else if let Some(c) = opt_b {
-                // The return from find_span was(n, None, Some(c:char)): the span up to `n` is
-                // an entity reference to be replaced with `c`
-                let mut buf = [0; 4];
-                let buf = c.encode_utf8(&mut buf);
-                return self.replace_entities_required(inc_map, bytes, buf, i, next_i, n);
+        while let Some(ofs) = s[i..].find('&') {
+            let start = i + ofs;
+            let (end, replacement) = self.parse_reference(inc_map, s, start)?;
+            if let Some(replacement) = replacement {
+                let r = r.get_or_insert_with(|| String::with_capacity(s.len()));
+                r.push_str(&s[copied_to..start]);
+                match replacement {
+                    Replacement::Entity(_, entity) => r.push_str(&entity.value),
+                    Replacement::Char(c) => r.push(c),
+                }
+                copied_to = end;
+            }
+            i = end;
+        }
+        match r {
+            None => Ok(Cow::Borrowed(s)),
+            Some(mut r) => {
+                r.push_str(&s[copied_to..]);
+                Ok(Cow::Owned(r))
             }
-            // The return from find_span was(n, None, None): the span up to `n` has
-            // no entity references
-            i = next_i;
         }
-        None
+    }
+
+    //mi expand_into
+    /// Expand the references in `s` into `expansion`, recursively
+    /// expanding declared entities
+    fn expand_into<'e>(&'e self, s: &str, expansion: &mut Expansion<'e>) -> Result<()> {
+        let mut copied_to = 0;
+        let mut i = 0;
+        while let Some(ofs) = s[i..].find('&') {
+            let start = i + ofs;
+            let (end, replacement) = self.parse_reference(true, s, start)?;
+            expansion.push(&s[copied_to..start], start, end)?;
+            match replacement {
+                Some(Replacement::Char(c)) => {
+                    let mut buf = [0; 4];
+                    expansion.push(c.encode_utf8(&mut buf), start, end)?;
+                }
+                Some(Replacement::Entity(_, entity)) if !entity.expand => {
+                    expansion.push(&entity.value, start, end)?;
+                }
+                Some(Replacement::Entity(name, entity)) => {
+                    expansion.enter(name, start, end)?;
+                    self.expand_into(&entity.value, expansion)
+                        .map_err(|e| expansion.relocate(e))?;
+                    expansion.leave();
+                }
+                None => (),
+            }
+            copied_to = end;
+            i = end;
+        }
+        expansion.push(&s[copied_to..], i, s.len())
+    }
+
+    //mp unescape
+    /// Unescape a string, replacing all entity and character
+    /// references, and recursively expanding references within the
+    /// values of declared entities
+    ///
+    /// An entity that references itself (directly or indirectly), an
+    /// expansion nested more deeply than the maximum depth, or an
+    /// expansion larger than the maximum size is an error; errors
+    /// within the values of entities are reported at the outermost
+    /// reference in `s`
+    pub fn unescape<'s>(&self, s: &'s str) -> Result<Cow<'s, str>> {
+        if !s.contains('&') {
+            return Ok(Cow::Borrowed(s));
+        }
+        let mut expansion = Expansion::new(self.max_depth, self.max_expanded_size);
+        self.expand_into(s, &mut expansion)?;
+        Ok(Cow::Owned(expansion.result))
+    }
+}
+
+//ti Replacement
+/// The replacement for a reference
+enum Replacement<'a> {
+    /// Replace with the value of an entity
+    Entity(&'a str, &'a Entity),
+    /// Replace with a single character (from a character reference)
+    Char(char),
+}
+
+//ti Expansion
+/// The state of a recursive expansion
+struct Expansion<'a> {
+    /// Names of entities being expanded, and the byte range of the
+    /// reference in the string it was found in
+    stack: Vec<(&'a str, usize, usize)>,
+    max_depth: usize,
+    max_expanded_size: usize,
+    /// Number of entity references expanded so far
+    references: usize,
+    result: String,
+}
+
+//ii Expansion
+impl<'a> Expansion<'a> {
+    //fi new
+    fn new(max_depth: usize, max_expanded_size: usize) -> Self {
+        Self {
+            stack: Vec::new(),
+            max_depth,
+            max_expanded_size,
+            references: 0,
+            result: String::new(),
+        }
+    }
+
+    //mi push
+    /// Push expanded text, checking the size limit
+    fn push(&mut self, s: &str, start: usize, end: usize) -> Result<()> {
+        if self.result.len() + s.len() + self.references > self.max_expanded_size {
+            return Err(Error::ExpansionTooLarge { start, end });
+        }
+        self.result.push_str(s);
+        Ok(())
+    }
+
+    //mi enter
+    /// Start expanding an entity, checking for recursion and the
+    /// depth limit
+    fn enter(&mut self, name: &'a str, start: usize, end: usize) -> Result<()> {
+        if self.stack.iter().any(|(n, _, _)| *n == name) {
+            return Err(Error::RecursiveEntity {
+                start,
+                end,
+                name: name.to_string(),
+            });
+        }
+        if self.stack.len() >= self.max_depth {
+            return Err(Error::DepthExceeded { start, end });
+        }
+        self.references += 1;
+        if self.result.len() + self.references > self.max_expanded_size {
+            return Err(Error::ExpansionTooLarge { start, end });
+        }
+        self.stack.push((name, start, end));
+        Ok(())
+    }
+
+    //mi leave
+    /// Complete expanding an entity
+    fn leave(&mut self) {
+        self.stack.pop();
+    }
+
+    //mi relocate
+    /// Relocate an error within the value of the innermost entity
+    /// being expanded to the reference to that entity
+    fn relocate(&mut self, e: Error) -> Error {
+        let (_, start, end) = self.stack.pop().unwrap();
+        e.relocate(start, end)
     }
 }
 
@@ -350,11 +775,15 @@ impl<'a> Entities<'a> {
 #[cfg(test)]
 mod test {
     use super::*;
-    // fn check_ok( r:Result<Option<String>>, e:Option<&str> ) {
-    fn check_ok(r: Option<String>, e: Option<&str>) {
-        // assert!(r.is_ok());
-        // let r = r.unwrap();
-        assert_eq!(r, e.map(|s| s.into()));
+    fn check_ok(r: Cow<str>, e: Option<&str>) {
+        match e {
+            None => assert!(matches!(r, Cow::Borrowed(_)), "Expected no change to {}", r),
+            Some(e) => assert_eq!(r, e),
+        }
+    }
+    fn check_unescape(r: Result<Cow<str>>, e: Option<&str>) {
+        assert!(r.is_ok(), "Expected Ok, got {:?}", r);
+        check_ok(r.unwrap(), e);
     }
     #[test]
     fn test0() {
@@ -380,17 +809,163 @@ mod test {
         );
     }
     #[test]
+    fn test_contexts() {
+        check_ok(escape_text("a 'b' \"c\""), None);
+        check_ok(escape_text("a]]>b\r\n"), Some("a]]&gt;b&#xD;\n"));
+        check_ok(
+            escape_attribute("it's \"x\"", Quote::Double),
+            Some("it's &quot;x&quot;"),
+        );
+        check_ok(
+            escape_attribute("it's \"x\"", Quote::Single),
+            Some("it&apos;s \"x\""),
+        );
+        check_ok(
+            escape_attribute("a\tb\nc", Quote::Double),
+            Some("a&#x9;b&#xA;c"),
+        );
+        check_ok(escape_cdata("a < & b"), None);
+        check_ok(escape_cdata("a]]>b"), Some("a]]]]><![CDATA[>b"));
+    }
+    #[test]
     fn test_entities() {
         let e = Entities::xml();
-        check_ok(e.replace_entities(true, "fred"), None);
-        check_ok(e.replace_entities(true, "&amp;&AMP;"), Some("&&"));
-        check_ok(e.replace_entities(true, "&lt;&LT;&GT;&gt;"), Some("<<>>"));
-        check_ok(e.replace_entities(true, "&blob;&QUOT;"), Some("&blob;\""));
-        check_ok(e.replace_entities(true, "&#xfffffff;"), None);
-        check_ok(e.replace_entities(true, "&#x32;"), Some("2"));
-        check_ok(e.replace_entities(true, "&#32;"), Some(" "));
-        check_ok(e.replace_entities(true, "&#9999999999999;"), None);
-        check_ok(e.replace_entities(true, "&#x32;&#32;"), Some("2 "));
-        check_ok(e.replace_entities(true, "&#32;&#x32;"), Some(" 2"));
+        check_unescape(e.replace_entities(true, "fred"), None);
+        check_unescape(e.replace_entities(true, "&amp;&AMP;"), Some("&&"));
+        check_unescape(e.replace_entities(true, "&lt;&LT;&GT;&gt;"), Some("<<>>"));
+        check_unescape(e.replace_entities(false, "&lt;&#x32;"), Some("&lt;2"));
+        check_unescape(e.replace_entities(true, "&#x32;"), Some("2"));
+        check_unescape(e.replace_entities(true, "&#32;"), Some(" "));
+        check_unescape(e.replace_entities(true, "&#x32;&#32;"), Some("2 "));
+        check_unescape(e.replace_entities(true, "&#32;&#x32;"), Some(" 2"));
+        check_unescape(
+            e.unescape("\u{1f600}&#x1f600;."),
+            Some("\u{1f600}\u{1f600}."),
+        );
+        assert_eq!(
+            e.replace_entities(true, "&blob;&QUOT;"),
+            Err(Error::UnknownEntity {
+                start: 0,
+                end: 6,
+                name: "blob".into()
+            })
+        );
+        assert_eq!(e.unescape("ab&#xfffffff;").unwrap_err().byte_range(), 2..13);
+        assert_eq!(
+            e.unescape("&#9999999999999;"),
+            Err(Error::InvalidCharRef { start: 0, end: 16 })
+        );
+        assert_eq!(
+            e.unescape("a & b"),
+            Err(Error::UnterminatedReference { start: 2, end: 3 })
+        );
+        assert_eq!(
+            e.unescape("a &amp"),
+            Err(Error::UnterminatedReference { start: 2, end: 6 })
+        );
+    }
+    #[test]
+    fn test_entity_sets() {
+        let mut e = Entities::default();
+        assert!(e.unescape("&amp;").is_err());
+        check_unescape(e.unescape("&#38;"), Some("&"));
+        e.insert("hml", "Human Markup Language");
+        check_unescape(e.unescape("&hml;!"), Some("Human Markup Language!"));
+        assert_eq!(e.remove("hml"), Some("Human Markup Language".into()));
+        assert!(e.get("hml").is_none());
+    }
+    #[test]
+    fn test_declarations() {
+        let mut e = Entities::xml();
+        assert_eq!(
+            e.parse_declaration(
+                r#"<!ENTITY example "<p>An ampersand (&#38;#38;) may be escaped
+numerically (&#38;#38;#38;) or with a general entity
+(&amp;amp;).</p>" >"#
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            e.get("example"),
+            Some(
+                "<p>An ampersand (&#38;) may be escaped
+numerically (&#38;#38;) or with a general entity
+(&amp;amp;).</p>"
+            )
+        );
+        check_unescape(
+            e.unescape("&example;"),
+            Some(
+                "<p>An ampersand (&) may be escaped
+numerically (&#38;) or with a general entity
+(&amp;).</p>",
+            ),
+        );
+        assert_eq!(e.parse_declaration("<!ENTITY who 'world'>"), Ok(true));
+        assert_eq!(
+            e.parse_declaration("<!ENTITY hi \"hello &who;\">"),
+            Ok(true)
+        );
+        assert_eq!(e.parse_declaration("<!ENTITY who 'there'>"), Ok(false));
+        check_unescape(e.unescape("&hi;!"), Some("hello world!"));
+        assert_eq!(
+            e.parse_declaration("<!ENTITY bad 'x>"),
+            Err(Error::BadDeclaration { start: 13, end: 15 })
+        );
+        assert!(e.parse_declaration("<!ENTITY 'x'>").is_err());
+        assert!(e.parse_declaration("<!ENTITY 1a 'x'>").is_err());
+        assert!(e.parse_declaration("#!ENTITY a 'x'").is_err());
+        assert!(e.parse_declaration("<!ENTITY a 'x'").is_err());
+        assert_eq!(
+            e.parse_declaration("<!ENTITY c 'a&#xffffff;'>"),
+            Err(Error::InvalidCharRef { start: 13, end: 23 })
+        );
+    }
+    #[test]
+    fn test_expansion_limits() {
+        let mut e = Entities::xml();
+        e.declare("a", "&b;").unwrap();
+        e.declare("b", "x&c;").unwrap();
+        e.declare("c", "&a;").unwrap();
+        e.declare("d", "&nope;").unwrap();
+        assert_eq!(
+            e.unescape("..&a;"),
+            Err(Error::RecursiveEntity {
+                start: 2,
+                end: 5,
+                name: "a".into()
+            })
+        );
+        assert_eq!(e.unescape("&d;").unwrap_err().byte_range(), 0..3);
+
+        // Billion laughs
+        let mut e = Entities::xml();
+        e.declare("lol0", "lol").unwrap();
+        for i in 1..10 {
+            let value = format!("&lol{};", i - 1).repeat(10);
+            e.declare(&format!("lol{}", i), &value).unwrap();
+        }
+        check_unescape(e.unescape("&lol2;"), Some(&"lol".repeat(100)));
+        assert!(matches!(
+            e.unescape("&lol9;"),
+            Err(Error::ExpansionTooLarge { start: 0, end: 6 })
+        ));
+        let e = e.set_max_expanded_size(250);
+        assert!(e.unescape("&lol2;").is_err());
+        let e = e.set_max_expanded_size(1000).set_max_depth(2);
+        check_unescape(e.unescape("&lol1;"), Some(&"lol".repeat(10)));
+        assert_eq!(
+            e.unescape(" &lol2;"),
+            Err(Error::DepthExceeded { start: 1, end: 7 })
+        );
+
+        // Empty entities still count towards the size
+        let mut e = Entities::xml().set_max_expanded_size(1000);
+        e.declare("e0", "").unwrap();
+        for i in 1..10 {
+            let value = format!("&e{};", i - 1).repeat(10);
+            e.declare(&format!("e{}", i), &value).unwrap();
+        }
+        assert!(e.unescape("&e9;").is_err());
     }
 }
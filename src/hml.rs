@@ -3,10 +3,12 @@
 
 # HML
 
-This module is not ready for use
-
-This module provides escape handling for XML and entity replacement
+This module provides escape handling for HML quoted strings: the
+resolution of Rust-style escape sequences with
+[escape::Escapable], and the inverse - producing an HML quoted
+string for arbitrary content - with [escape::quote]
 
 !*/
 
-mod escape;
+/// Escaping and unescaping of HML quoted strings
+pub mod escape;
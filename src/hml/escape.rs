@@ -1,41 +1,135 @@
 //a Imports
+use std::borrow::Cow;
 use std::convert::TryFrom;
 
+use thiserror::Error;
+
+use crate::{Posn, Span};
+
 //a Error type and result
 //tp Error
-/// Error in an escape sequence
-#[derive(Debug)]
+/// Error in an escape sequence; the offsets are byte offsets within
+/// the string being unescaped, covering the erroneous escape sequence
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
 pub enum Error {
     /// An illegal escape sequence
-    BadEscape(String),
+    #[error("bad escape sequence at bytes {start}..{end}")]
+    BadEscape {
+        /// Byte offset of the backslash
+        start: usize,
+        /// Byte offset of the end of the escape sequence
+        end: usize,
+    },
     /// A bad hex digit
-    BadHexDigit(String),
+    #[error("{reason} at bytes {start}..{end}")]
+    BadHexDigit {
+        /// Why the escape is bad
+        reason: String,
+        /// Byte offset of the backslash
+        start: usize,
+        /// Byte offset of the end of the escape sequence
+        end: usize,
+    },
     /// An illegal hex esacape
-    BadHexEscape(String),
+    #[error("{reason} at bytes {start}..{end}")]
+    BadHexEscape {
+        /// Why the escape is bad
+        reason: String,
+        /// Byte offset of the backslash
+        start: usize,
+        /// Byte offset of the end of the escape sequence
+        end: usize,
+    },
     /// An illegal unicode specified in an escape sequence
-    BadUnicode(String),
+    #[error("{reason} at bytes {start}..{end}")]
+    BadUnicode {
+        /// Why the escape is bad
+        reason: String,
+        /// Byte offset of the backslash
+        start: usize,
+        /// Byte offset of the end of the escape sequence
+        end: usize,
+    },
     /// End of string during an escape sequence
-    EndOfStringInEscape(String),
+    #[error("end of string in escape sequence at bytes {start}..{end}")]
+    EndOfStringInEscape {
+        /// Byte offset of the backslash
+        start: usize,
+        /// Byte offset of the end of the string
+        end: usize,
+    },
 }
 
 //ip Error
 impl Error {
-    fn bad_escape<T>(reason: &str, byte: u8, _bytes: &[u8], _offset: usize) -> Result<T> {
-        Err(Self::BadEscape(format!("{} '{}'", reason, byte)))
+    //fi end_of
+    /// Find the end of the escape sequence whose last byte considered
+    /// was at `i`, rounding up to a UTF-8 character boundary
+    fn end_of(bytes: &[u8], i: usize) -> usize {
+        let mut end = (i + 1).min(bytes.len());
+        while end < bytes.len() && (bytes[end] & 0xc0) == 0x80 {
+            end += 1;
+        }
+        end
+    }
+    fn bad_escape<T>(bytes: &[u8], start: usize, i: usize) -> Result<T> {
+        let end = Self::end_of(bytes, i);
+        Err(Self::BadEscape { start, end })
     }
-    fn bad_hex_digit<T>(reason: &str, _byte: u8) -> Result<T> {
-        Err(Self::BadHexDigit(reason.to_string()))
+    fn bad_hex_digit<T>(reason: &str, bytes: &[u8], start: usize, i: usize) -> Result<T> {
+        let reason = reason.to_string();
+        let end = Self::end_of(bytes, i);
+        Err(Self::BadHexDigit { reason, start, end })
     }
-    fn bad_hex_escape<T>(reason: &str, _bytes: &[u8], _offset: usize) -> Result<T> {
-        Err(Self::BadHexEscape(reason.to_string()))
+    fn bad_hex_escape<T>(reason: &str, bytes: &[u8], start: usize, i: usize) -> Result<T> {
+        let reason = reason.to_string();
+        let end = Self::end_of(bytes, i);
+        Err(Self::BadHexEscape { reason, start, end })
     }
-    fn bad_unicode<T>(reason: &str, _bytes: &[u8], _offset: usize) -> Result<T> {
-        Err(Self::BadUnicode(reason.to_string()))
+    fn bad_unicode<T>(reason: &str, bytes: &[u8], start: usize, i: usize) -> Result<T> {
+        let reason = reason.to_string();
+        let end = Self::end_of(bytes, i);
+        Err(Self::BadUnicode { reason, start, end })
     }
-    fn end_of_string_in_escape<T>(bytes: &[u8]) -> Result<T> {
-        Err(Self::EndOfStringInEscape(
-            std::str::from_utf8(bytes).unwrap().into(),
-        ))
+    fn end_of_string_in_escape<T>(bytes: &[u8], start: usize) -> Result<T> {
+        let end = bytes.len();
+        Err(Self::EndOfStringInEscape { start, end })
+    }
+
+    //ap byte_range
+    /// Get the range of bytes of the string that are in error
+    pub fn byte_range(&self) -> std::ops::Range<usize> {
+        match self {
+            Self::BadEscape { start, end }
+            | Self::BadHexDigit { start, end, .. }
+            | Self::BadHexEscape { start, end, .. }
+            | Self::BadUnicode { start, end, .. }
+            | Self::EndOfStringInEscape { start, end } => *start..*end,
+        }
+    }
+
+    //mp span_within
+    /// Get the span within a source document of the erroneous escape,
+    /// given the span of the quoted string in the document and the
+    /// (unescaped) contents of the quoted string
+    ///
+    /// The quoted string is an escaped (not raw) HML string, so it is
+    /// delimited by the same number of characters at each end
+    pub fn span_within<P: Posn>(&self, span: &Span<P>, data: &str) -> Span<P> {
+        let range = self.byte_range();
+        let quote_len = span.byte_range().len().saturating_sub(data.len()) / 2;
+        let mut posn = *span.start();
+        for _ in 0..quote_len {
+            posn = posn.move_by_char('#');
+        }
+        for ch in data[0..range.start].chars() {
+            posn = posn.move_by_char(ch);
+        }
+        let start = posn;
+        for ch in data[range].chars() {
+            posn = posn.move_by_char(ch);
+        }
+        Span::new(start, posn)
     }
 }
 
@@ -45,7 +139,8 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 //a Useful functions
 //fp hex_of_byte
-fn hex_of_byte(reason: &str, b: u8) -> Result<u32> {
+fn hex_of_byte(reason: &str, bytes: &[u8], start: usize, i: usize) -> Result<u32> {
+    let b = bytes[i];
     let value = {
         if b.is_ascii_digit() {
             b - b'0'
@@ -54,12 +149,17 @@ fn hex_of_byte(reason: &str, b: u8) -> Result<u32> {
         } else if (b'A'..=b'F').contains(&b) {
             10 + (b - b'A')
         } else {
-            return Error::bad_hex_digit(reason, b);
+            return Error::bad_hex_digit(reason, bytes, start, i);
         }
     };
     Ok(value as u32)
 }
 
+//tp Escapable
+/// A string from an HML document that may contain Rust-style escape
+/// sequences, with those escapes resolved
+///
+/// This is the inverse of [escape]
 #[derive(Debug)]
 pub struct Escapable<'a> {
     s: &'a str,
@@ -115,12 +215,14 @@ impl<'a> Escapable<'a> {
         }
         let mut escape_state = Normal;
         let mut unicode_value = 0;
+        let mut start = 0;
         while i < n {
             let b = bytes[i];
             match escape_state {
                 Normal => {
                     if b == b'\\' {
                         escape_state = Backslashed;
+                        start = i;
                     } else {
                         r.push(b);
                     }
@@ -157,25 +259,27 @@ impl<'a> Escapable<'a> {
                         }
                         b'x' => {
                             if i < n - 2 {
-                                let unicode =
-                                    (hex_of_byte("hex escape requires hex digits", bytes[i + 1])?
-                                        << 4)
-                                        + (hex_of_byte(
-                                            "hex escape requires hex digits",
-                                            bytes[i + 2],
-                                        )?);
+                                let reason = "hex escape requires hex digits";
+                                let unicode = (hex_of_byte(reason, bytes, start, i + 1)? << 4)
+                                    + (hex_of_byte(reason, bytes, start, i + 2)?);
                                 if unicode > 0x7f {
                                     return Error::bad_hex_escape(
                                         "hex escape must be in range 0-0x7f",
                                         bytes,
-                                        i,
+                                        start,
+                                        i + 2,
                                     );
                                 }
                                 r.push(unicode as u8);
                                 i += 2;
                                 escape_state = Normal;
                             } else {
-                                return Error::bad_hex_escape("hex escape must be \\xXX", bytes, i);
+                                return Error::bad_hex_escape(
+                                    "hex escape must be \\xXX",
+                                    bytes,
+                                    start,
+                                    n - 1,
+                                );
                             }
                         }
                         b'u' => {
@@ -185,20 +289,28 @@ impl<'a> Escapable<'a> {
                                     return Error::bad_unicode(
                                         "\\u escape requires { to follow",
                                         bytes,
-                                        i,
+                                        start,
+                                        i + 1,
                                     );
                                 }
                                 unicode_value = hex_of_byte(
                                     "unicode escape requires hex digits",
-                                    bytes[i + 2],
+                                    bytes,
+                                    start,
+                                    i + 2,
                                 )?;
                                 escape_state = Unicode(1);
                                 i += 2;
                             } else {
-                                return Error::bad_unicode("malformed unicode escape", bytes, i);
+                                return Error::bad_unicode(
+                                    "malformed unicode escape",
+                                    bytes,
+                                    start,
+                                    n - 1,
+                                );
                             }
                         }
-                        _ => return Error::bad_escape("bad escape", b, bytes, i),
+                        _ => return Error::bad_escape(bytes, start, i),
                     }
                 }
                 Unicode(n) => {
@@ -208,13 +320,13 @@ impl<'a> Escapable<'a> {
                             let buf = ch.encode_utf8(&mut buf).as_bytes();
                             r.extend_from_slice(buf);
                         } else {
-                            return Error::bad_unicode("invalid unicode value", bytes, i);
+                            return Error::bad_unicode("invalid unicode value", bytes, start, i);
                         }
                         escape_state = Normal;
                     } else if n == 6 {
-                        return Error::bad_unicode("at most 6 hex digits", bytes, i);
+                        return Error::bad_unicode("at most 6 hex digits", bytes, start, i);
                     } else {
-                        let v = hex_of_byte("unicode escape requires hex digits", b)?;
+                        let v = hex_of_byte("unicode escape requires hex digits", bytes, start, i)?;
                         unicode_value = (unicode_value << 4) + v;
                         escape_state = Unicode(n + 1);
                     }
@@ -223,9 +335,10 @@ impl<'a> Escapable<'a> {
             i += 1;
         }
         if escape_state != Normal {
-            Error::end_of_string_in_escape(bytes)
+            Error::end_of_string_in_escape(bytes, start)
         } else {
-            let string = unsafe { String::from_utf8_unchecked(r) };
+            // Escapes only replace ASCII with complete UTF-8 characters
+            let string = String::from_utf8(r).expect("Unescaped string is valid UTF-8");
             Ok(Some(string))
         }
     }
@@ -257,6 +370,119 @@ impl<'a> TryFrom<&'a str> for Escapable<'a> {
     }
 }
 
+//a Escaping
+//fi escape_of
+/// Get the escape sequence for a character, if it requires one
+///
+/// In a single-line string newlines must be escaped, and so must '"'
+/// as it would terminate the string (the HML lexer does not treat a
+/// backslash as escaping a quote)
+#[inline]
+fn escape_of(ch: char, multiline: bool) -> Option<Cow<'static, str>> {
+    match ch {
+        '\\' => Some("\\\\".into()),
+        '\0' => Some("\\0".into()),
+        '\t' => Some("\\t".into()),
+        '\r' => Some("\\r".into()),
+        '\n' if !multiline => Some("\\n".into()),
+        '"' if !multiline => Some("\\x22".into()),
+        '\n' => None,
+        _ if ch.is_control() => Some(format!("\\u{{{:x}}}", ch as u32).into()),
+        _ => None,
+    }
+}
+
+//fp escape
+/// Escape a string so that it can be the contents of an (escaped)
+/// HML quoted string; this is the inverse of [Escapable::new]
+///
+/// If `multiline` is false then the result is suitable for a
+/// single-line string "...", and newlines and quotes are escaped;
+/// if it is true then the result is for a #"..."# string (whose hash
+/// count must be determined by [hash_count])
+///
+/// The string is borrowed if no escaping is required
+pub fn escape(s: &str, multiline: bool) -> Cow<'_, str> {
+    let Some(first) = s.find(|ch| escape_of(ch, multiline).is_some()) else {
+        return Cow::Borrowed(s);
+    };
+    let mut r = String::with_capacity(s.len() + 8);
+    r.push_str(&s[0..first]);
+    for ch in s[first..].chars() {
+        match escape_of(ch, multiline) {
+            Some(esc) => r.push_str(&esc),
+            None => r.push(ch),
+        }
+    }
+    Cow::Owned(r)
+}
+
+//fp hash_count
+/// Find the minimum number of hashes (at least one) required for a
+/// #"..."# or r#"..."# string with the given contents
+///
+/// Such a string terminates at the first '"' followed by the hash
+/// count of '#' characters; hence the hash count must exceed the
+/// number of '#' following any '"' in the contents
+pub fn hash_count(s: &str) -> usize {
+    let mut max = 0;
+    for (i, _) in s.match_indices('"') {
+        let hashes = s[i + 1..].len() - s[i + 1..].trim_start_matches('#').len();
+        max = max.max(hashes);
+    }
+    max + 1
+}
+
+//fp delimit
+/// Delimit contents (that must already be escaped if `raw` is false)
+/// as an HML quoted string, using the minimal number of hashes
+///
+/// If `multiline` is false then the contents must not contain
+/// newlines or '"', and the string is delimited with just quotes
+pub fn delimit(s: &str, raw: bool, multiline: bool) -> String {
+    let hashes = {
+        if multiline {
+            "#".repeat(hash_count(s))
+        } else {
+            String::new()
+        }
+    };
+    let r = if raw { "r" } else { "" };
+    format!("{}{}\"{}\"{}", r, hashes, s, hashes)
+}
+
+//fp quote
+/// Produce the best HML quoted string for some content, such that
+/// parsing the quoted string (and resolving its escapes with
+/// [Escapable::new] if it is not raw) yields the content
+///
+/// The choice is, in order of preference:
+///
+/// * a plain string "..." if nothing needs escaping
+///
+/// * a raw string r"..." if the only characters needing escapes are
+///   backslashes
+///
+/// * a raw string r#"..."# if the content has quotes or newlines but
+///   nothing else that needs escaping
+///
+/// * an escaped string #"..."# if the content has newlines
+///
+/// * an escaped string "..." otherwise
+pub fn quote(s: &str) -> String {
+    let needs_escape = |ch: char| ch == '\r' || (ch.is_control() && ch != '\n' && ch != '\t');
+    let multiline = s.contains('\n');
+    if s.contains(needs_escape) {
+        delimit(&escape(s, multiline), false, multiline)
+    } else if multiline || s.contains('"') {
+        delimit(s, true, true)
+    } else if s.contains('\\') {
+        delimit(s, true, false)
+    } else {
+        delimit(s, false, false)
+    }
+}
+
 //a Test
 
 #[cfg(test)]
@@ -288,4 +514,45 @@ mod test {
         );
         assert!(Escapable::new(r"\u{110000}").is_err());
     }
+    #[test]
+    fn test_error_offsets() {
+        let range = |s| Escapable::new(s).unwrap_err().byte_range();
+        assert_eq!(range(r"ab\u{110000}c"), 2..12);
+        assert_eq!(range(r"ab\u{1234567}c"), 2..12);
+        assert_eq!(range(r"\n\z"), 2..4);
+        assert_eq!(range("\\\u{e9}"), 0..3);
+        assert_eq!(range(r"a\x8f"), 1..5);
+        assert_eq!(range(r"a\xg0"), 1..4);
+        assert_eq!(range(r"a\u[1]"), 1..4);
+        assert_eq!(range(r"a\u{12"), 1..6);
+        assert_eq!(range(r"a\"), 1..2);
+        assert!(matches!(
+            Escapable::new(r"\u{110000}"),
+            Err(Error::BadUnicode { .. })
+        ));
+    }
+    #[test]
+    fn test_escape() {
+        assert!(matches!(escape("fred", false), Cow::Borrowed(_)));
+        assert_eq!(escape("a\\b", false), r"a\\b");
+        assert_eq!(escape("a\"b\n", false), r"a\x22b\n");
+        assert_eq!(escape("a\"b\n", true), "a\"b\n");
+        assert_eq!(escape("\0\t\r\u{7f}", true), r"\0\t\r\u{7f}");
+        assert_eq!(hash_count("abc"), 1);
+        assert_eq!(hash_count("a\"b"), 1);
+        assert_eq!(hash_count("a\"#b"), 2);
+        assert_eq!(hash_count("\"## a \"#"), 3);
+        assert_eq!(quote("fred"), "\"fred\"");
+        assert_eq!(quote(r"a\b"), r#"r"a\b""#);
+        assert_eq!(quote("a\"b"), "r#\"a\"b\"#");
+        assert_eq!(quote("a\n\"#b"), "r##\"a\n\"#b\"##");
+        assert_eq!(quote("a\r\nb"), "#\"a\\r\nb\"#");
+        assert_eq!(quote("a\0\"b"), r#""a\0\x22b""#);
+        for s in ["fred", "a\"b", "\u{1f600}\t\\", "a\0\"b\\\n", "\"## a \"#"] {
+            let e = escape(s, false);
+            assert_eq!(Escapable::new(&e).unwrap().as_ref(), s);
+            let e = escape(s, true);
+            assert_eq!(Escapable::new(&e).unwrap().as_ref(), s);
+        }
+    }
 }
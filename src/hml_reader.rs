@@ -47,9 +47,10 @@ full context.
   !*/
 
 mod builder;
+mod include;
 mod parser;
 mod token;
-mod utils;
+pub(crate) mod utils;
 
 mod test_parser;
 
@@ -57,5 +58,6 @@ use builder::{CloseTag, OpenTag, StackElement};
 use token::{Token, TokenType};
 
 mod lexer_parsers;
+pub use include::{FileLoader, Includer, Loader};
 pub use lexer_parsers::parse_fns;
-pub use parser::Parser;
+pub use parser::{DocumentMode, Parser};
@@ -1,6 +1,6 @@
 //a Imports
-use crate::markup::Event;
-use crate::names::{Attributes, Name, NamespaceStack, Tag};
+use crate::markup::{AttributeSpan, Event, TagSpans};
+use crate::names::{Attribute, Attributes, Name, NamespaceStack, Tag};
 use crate::{HmlError, HmlResult, Posn, Span};
 
 //a Internal types
@@ -12,6 +12,7 @@ where
     T: std::fmt::Debug,
 {
     span: Span<P>,
+    name_span: Span<P>,
     prefix: String,
     name: String,
     pub extra: T,
@@ -23,9 +24,10 @@ where
     P: Posn,
     T: std::fmt::Debug,
 {
-    pub fn new(span: Span<P>, prefix: String, name: String, extra: T) -> Self {
+    pub fn new(span: Span<P>, name_span: Span<P>, prefix: String, name: String, extra: T) -> Self {
         Self {
             span,
+            name_span,
             prefix,
             name,
             extra,
@@ -44,8 +46,8 @@ where
     T: std::fmt::Debug,
 {
     span: Span<P>,
-    #[allow(dead_code)]
     name: Name,
+    #[allow(dead_code)]
     pub extra: T,
 }
 
@@ -68,6 +70,9 @@ where
     pub fn span(&self) -> &Span<P> {
         &self.span
     }
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
 }
 
 //ti StackElement
@@ -75,8 +80,8 @@ where
 ///
 /// The [StackElement] is created when the opening tag is seen; the
 /// tag's attributes are added to as the tag is built, and upon
-/// completion of the tag being built a 'StartElement' event can be
-/// issued.
+/// completion of the tag being built its namespace declarations are
+/// made and a 'StartElement' event can be issued.
 ///
 /// The [StartElement] remains on the top of the stack as the content of the element is handled.
 ///
@@ -97,7 +102,22 @@ where
     parent_depth: usize,
     open_tag: OpenTag<P, T>,
     tag_name: Name,
-    attributes: Attributes,
+    pending_attributes: Vec<PendingAttribute<P>>,
+}
+
+//ti PendingAttribute
+/// An attribute of a [StackElement] as read, whose name is resolved
+/// once all the attributes of the tag have been read
+#[derive(Debug)]
+struct PendingAttribute<P>
+where
+    P: Posn,
+{
+    span: AttributeSpan<P>,
+    prefix: String,
+    name: String,
+    value: String,
+    attribute: Option<Attribute>,
 }
 
 //ii StackElement
@@ -113,28 +133,83 @@ where
     ) -> Self {
         ns_stack.push_frame();
 
-        let attributes = Attributes::default();
         let tag_name = Name::none();
         StackElement {
             parent_depth,
             open_tag,
             tag_name,
-            attributes,
+            pending_attributes: Vec::new(),
         }
     }
+    pub fn open_tag(&self) -> &OpenTag<P, T> {
+        &self.open_tag
+    }
     pub fn add_attribute(
         &mut self,
-        span: Span<P>,
-        ns_stack: &mut NamespaceStack,
-        prefix: &str,
-        name: &str,
+        attribute_span: AttributeSpan<P>,
+        prefix: String,
+        name: String,
         value: String,
-    ) -> HmlResult<(), P> {
-        HmlError::map_markup_error(self.attributes.add(ns_stack, prefix, name, value), &span)
+    ) {
+        self.pending_attributes.push(PendingAttribute {
+            span: attribute_span,
+            prefix,
+            name,
+            value,
+            attribute: None,
+        });
+    }
+
+    //mp declare_namespaces
+    /// Add the namespace declarations of the tag's attributes to its
+    /// namespace stack frame, returning the prefixes (with their
+    /// spans) of the tag name and the other attributes, which are
+    /// resolved by [Self::as_start_element]
+    pub fn declare_namespaces(
+        &mut self,
+        ns_stack: &mut NamespaceStack,
+    ) -> HmlResult<Vec<(String, Span<P>)>, P> {
+        let mut prefixes = vec![(self.open_tag.prefix.clone(), self.open_tag.span)];
+        for pending in self.pending_attributes.iter_mut() {
+            let span = pending.span.span();
+            let is_declaration = ns_stack.uses_xmlns()
+                && (pending.prefix == "xmlns"
+                    || (pending.prefix.is_empty() && pending.name == "xmlns"));
+            if is_declaration {
+                let value = std::mem::take(&mut pending.value);
+                pending.attribute = Some(HmlError::map_markup_error(
+                    Attribute::new(ns_stack, &pending.prefix, &pending.name, value),
+                    &span,
+                )?);
+            } else {
+                prefixes.push((pending.prefix.clone(), span));
+            }
+        }
+        Ok(prefixes)
     }
 
     pub fn as_start_element(&mut self, ns_stack: &mut NamespaceStack) -> HmlResult<Event<P>, P> {
-        let attributes = std::mem::take(&mut self.attributes);
+        let mut attributes = Attributes::default();
+        let mut attribute_spans: Vec<AttributeSpan<P>> = Vec::new();
+        for pending in std::mem::take(&mut self.pending_attributes) {
+            let span = pending.span.span();
+            let attribute = match pending.attribute {
+                Some(attribute) => attribute,
+                None => HmlError::map_markup_error(
+                    Attribute::new(ns_stack, &pending.prefix, &pending.name, pending.value),
+                    &span,
+                )?,
+            };
+            if let Some(i) = attributes.position(&attribute.name) {
+                return HmlError::duplicate_attribute(
+                    span,
+                    attribute_spans[i].span(),
+                    &attribute.name.to_string(ns_stack),
+                );
+            }
+            attributes.push(attribute);
+            attribute_spans.push(pending.span);
+        }
         let tag = HmlError::map_markup_error(
             Tag::new(
                 ns_stack,
@@ -145,7 +220,13 @@ where
             &self.open_tag.span,
         )?;
         self.tag_name = tag.name;
-        Ok(Event::start_element(self.open_tag.span, tag))
+        let mut spans = TagSpans::new(self.open_tag.name_span);
+        spans.attributes = attribute_spans;
+        Ok(Event::start_element_with_spans(
+            self.open_tag.span,
+            tag,
+            spans,
+        ))
     }
     pub fn as_end_element(
         &self,
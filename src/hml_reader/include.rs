@@ -0,0 +1,588 @@
+//a Imports
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use lexer_rs::{LexerOfStr, ParserIterator};
+
+use super::{Parser, Token};
+use crate::markup::Event;
+use crate::names::NamespaceStack;
+use crate::{HmlError, HmlResult, Posn, Span};
+
+//a Loader
+//tt Loader
+/// A source of the documents that include directives refer to, for
+/// a parser with positions of type P
+pub trait Loader<P>
+where
+    P: Posn,
+{
+    /// Load the document at `path`, as given in an include directive
+    /// of the document named `from` (which is None for a top-level
+    /// document that has not been given a name)
+    ///
+    /// This returns the name of the document and its text; the name
+    /// must be the same however the document is referred to, as it
+    /// is used to detect include cycles, and it is the `from` for the
+    /// document's own include directives
+    fn load(&mut self, path: &str, from: Option<&str>) -> std::io::Result<(String, String)>;
+
+    /// Get the name of a top-level document given as `name`, as
+    /// [Loader::load] would name it, so that an include of the
+    /// document is detected as a cycle; by default this is `name`
+    fn document_name(&mut self, name: &str) -> String {
+        name.to_string()
+    }
+
+    /// Get the position of the start of a document that has been
+    /// loaded, from which the positions of its tokens follow; by
+    /// default this is `P::default()`, but it may identify the
+    /// document (see [crate::sources::SourceLoader])
+    fn start_posn(&mut self, _name: &str, _text: &str) -> P {
+        P::default()
+    }
+}
+
+//tp FileLoader
+/// A [Loader] that reads files, with relative paths resolved from the
+/// directory of the including file; the name of each document is
+/// its canonical path
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FileLoader;
+
+//ip Loader for FileLoader
+impl<P: Posn> Loader<P> for FileLoader {
+    fn load(&mut self, path: &str, from: Option<&str>) -> std::io::Result<(String, String)> {
+        let path = {
+            match from.and_then(|f| Path::new(f).parent()) {
+                Some(dir) if Path::new(path).is_relative() => dir.join(path),
+                _ => PathBuf::from(path),
+            }
+        };
+        let text = std::fs::read_to_string(&path)?;
+        let name = path.canonicalize()?.display().to_string();
+        Ok((name, text))
+    }
+
+    fn document_name(&mut self, name: &str) -> String {
+        match Path::new(name).canonicalize() {
+            Ok(path) => path.display().to_string(),
+            Err(_) => name.to_string(),
+        }
+    }
+}
+
+//ip Loader for HashMap
+/// A map from names to text is a [Loader] of in-memory documents;
+/// paths are not resolved, so the name of a document is the path
+/// used to include it
+impl<P: Posn> Loader<P> for HashMap<String, String> {
+    fn load(&mut self, path: &str, _from: Option<&str>) -> std::io::Result<(String, String)> {
+        match self.get(path) {
+            Some(text) => Ok((path.to_string(), text.clone())),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "no such document",
+            )),
+        }
+    }
+}
+
+//a Internal types
+//ti Included
+/// A document that is being included
+struct Included<P>
+where
+    P: Posn,
+{
+    /// Name of the document, from the [Loader]
+    name: String,
+    /// Span of the include directive in the including document
+    span: Span<P>,
+    /// Parser of the document, as a fragment
+    parser: Parser<P>,
+    /// The tokens of the document
+    tokens: std::vec::IntoIter<HmlResult<Token<P>, P>>,
+}
+
+//fi tokenize
+/// Convert the text of a document to tokens, starting at the
+/// position given, up to the first error
+fn tokenize<P: Posn>(text: &str, start: P) -> Vec<HmlResult<Token<P>, P>> {
+    let lexer: LexerOfStr<P, Token<P>, HmlError<P>> = LexerOfStr::new(text);
+    let lexer_parsers = super::parse_fns();
+    let mut tokens = Vec::new();
+    for t in ParserIterator::new(&lexer, start, &lexer_parsers) {
+        let is_err = t.is_err();
+        tokens.push(t);
+        if is_err {
+            break;
+        }
+    }
+    tokens
+}
+
+//a Includer
+//tp Includer
+/// An [Includer] expands include directives in an HML document, so
+/// that a document can be composed from many files
+///
+/// An include directive is a processing instruction (by default
+/// 'include', see [Includer::set_pi_name]) whose data (or, if it has
+/// none, name argument) is the path of the document to include, such
+/// as:
+///
+/// ```text
+/// #device
+/// ##?include "registers.hml"
+/// ```
+///
+/// The document is obtained from a [Loader], and its content is
+/// parsed as a fragment at the depth of the directive, in place of
+/// the directive, by a parser with the settings of the parser of the
+/// including document (see [Parser::nested]); it may itself include other documents, but not
+/// (directly or indirectly) itself.
+///
+/// The spans of the events of an included document are within that
+/// document; to identify the document from a span, use
+/// [crate::sources::FilePosn] positions with a
+/// [crate::sources::SourceLoader]. An error within an included document is returned as an
+/// [HmlError::InInclude], which gives the name of the included
+/// document, and the span of the include directive in the including
+/// document, for each level of inclusion; the innermost error has
+/// the span within the document that contains it.
+///
+/// The [Includer] is used in place of [Parser::next_event]:
+///
+/// ```text
+///  let mut includer = Includer::new(FileLoader).set_document_name("main.hml");
+///  loop {
+///      let event = includer.next_event(&mut namespace_stack, &mut parser, || lexer_iter.next())?;
+///      ...
+///  }
+/// ```
+pub struct Includer<P, L>
+where
+    P: Posn,
+    L: Loader<P>,
+{
+    loader: L,
+    pi_name: String,
+    expand: bool,
+    document_name: Option<String>,
+    stack: Vec<Included<P>>,
+}
+
+//ip Includer
+impl<P, L> Includer<P, L>
+where
+    P: Posn,
+    L: Loader<P>,
+{
+    //fp new
+    /// Create a new [Includer] that loads documents with a [Loader]
+    pub fn new(loader: L) -> Self {
+        Self {
+            loader,
+            pi_name: "include".into(),
+            expand: true,
+            document_name: None,
+            stack: Vec::new(),
+        }
+    }
+
+    //cp set_pi_name
+    /// Set the name of the processing instruction that is an include
+    /// directive (the default is 'include')
+    pub fn set_pi_name(mut self, pi_name: &str) -> Self {
+        self.pi_name = pi_name.into();
+        self
+    }
+
+    //cp set_expand
+    /// Set whether include directives are expanded (the default); if
+    /// not, they are provided as processing instruction events
+    pub fn set_expand(mut self, expand: bool) -> Self {
+        self.expand = expand;
+        self
+    }
+
+    //cp set_document_name
+    /// Set the name of the top-level document, which the [Loader]
+    /// uses to resolve the paths that it includes; the name is that
+    /// given by [Loader::document_name], such as a canonical path
+    pub fn set_document_name(mut self, name: &str) -> Self {
+        self.document_name = Some(self.loader.document_name(name));
+        self
+    }
+
+    //ap loader
+    /// Borrow the [Loader]
+    pub fn loader(&self) -> &L {
+        &self.loader
+    }
+
+    //mp into_loader
+    /// Consume the [Includer], returning the [Loader]
+    pub fn into_loader(self) -> L {
+        self.loader
+    }
+
+    //mi in_includes
+    /// Attribute an error to the document that it occurred in, by
+    /// wrapping it for each level of inclusion
+    fn in_includes(&self, mut e: HmlError<P>) -> HmlError<P> {
+        for included in self.stack.iter().rev() {
+            e = HmlError::InInclude {
+                span: included.span,
+                path: included.name.clone(),
+                error: Box::new(e),
+            };
+        }
+        e
+    }
+
+    //mi include_path
+    /// If the event is an include directive that should be expanded
+    /// then return the path it includes
+    fn include_path(
+        &self,
+        ns_stack: &NamespaceStack,
+        event: &Event<P>,
+    ) -> Option<HmlResult<String, P>> {
+        let Event::ProcessingInstruction {
+            span,
+            name,
+            arg,
+            data,
+        } = event
+        else {
+            return None;
+        };
+        if !self.expand || ns_stack.name_str(*name) != self.pi_name {
+            return None;
+        }
+        match data.as_ref().or(arg.as_ref()) {
+            Some(path) => Some(Ok(path.clone())),
+            None => Some(Err(HmlError::IncludeFailed {
+                span: *span,
+                path: String::new(),
+                source: std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "the include directive has no path",
+                ),
+            })),
+        }
+    }
+
+    //mi include
+    /// Start including the document at `path`, for the directive
+    /// with the span given, parsing it with `parser` (nested in the
+    /// parser of the directive, at the depth of the directive)
+    fn include(&mut self, span: Span<P>, path: &str, parser: Parser<P>) -> HmlResult<(), P> {
+        let from = {
+            match self.stack.last() {
+                Some(included) => Some(included.name.as_str()),
+                None => self.document_name.as_deref(),
+            }
+        };
+        let (name, text) = {
+            match self.loader.load(path, from) {
+                Ok(loaded) => loaded,
+                Err(source) => {
+                    let path = path.to_string();
+                    return Err(HmlError::IncludeFailed { span, path, source });
+                }
+            }
+        };
+        let cyclic = self.document_name.as_ref() == Some(&name)
+            || self.stack.iter().any(|included| included.name == name);
+        if cyclic {
+            return Err(HmlError::IncludeCycle { span, path: name });
+        }
+        let start = self.loader.start_posn(&name, &text);
+        self.stack.push(Included {
+            name,
+            span,
+            parser,
+            tokens: tokenize(&text, start).into_iter(),
+        });
+        Ok(())
+    }
+
+    //mp next_event
+    /// Get the next event of the document being parsed by `parser`
+    /// (using `get_token` to get its tokens), with include directives
+    /// expanded
+    pub fn next_event<T>(
+        &mut self,
+        ns_stack: &mut NamespaceStack,
+        parser: &mut Parser<P>,
+        mut get_token: T,
+    ) -> HmlResult<Event<P>, P>
+    where
+        T: FnMut() -> Option<HmlResult<Token<P>, P>>,
+    {
+        loop {
+            let (event, depth) = {
+                if let Some(included) = self.stack.last_mut() {
+                    let tokens = &mut included.tokens;
+                    match included
+                        .parser
+                        .next_fragment_event(ns_stack, || tokens.next())
+                    {
+                        Err(e) => return Err(self.in_includes(e)),
+                        Ok(None) => {
+                            let included = self.stack.pop().unwrap();
+                            match self.stack.last_mut() {
+                                Some(including) => including.parser.end_nested(included.parser),
+                                None => parser.end_nested(included.parser),
+                            }
+                            continue;
+                        }
+                        Ok(Some(event)) => (event, included.parser.content_depth()),
+                    }
+                } else {
+                    let event = parser.next_event(ns_stack, &mut get_token)?;
+                    (event, parser.content_depth())
+                }
+            };
+            let Some(path) = self.include_path(ns_stack, &event) else {
+                return Ok(event);
+            };
+            let nested = {
+                match self.stack.last() {
+                    Some(including) => including.parser.nested(depth),
+                    None => parser.nested(depth),
+                }
+            };
+            let result = path.and_then(|path| self.include(*event.borrow_span(), &path, nested));
+            if let Err(e) = result {
+                return Err(self.in_includes(e));
+            }
+        }
+    }
+}
+
+//a Tests
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{FileLoader, Includer};
+    use crate::hml_reader::{DocumentMode, Parser};
+    use crate::markup::Event;
+    use crate::names::{Namespace, NamespaceStack};
+    use crate::HmlError;
+
+    use lexer_rs::{Lexer, LexerOfString, LineColumn, StreamCharPos, UserPosn};
+
+    type LexerPos = StreamCharPos<LineColumn>;
+
+    //fi parse
+    /// Parse the document 'main' of a set of documents with an
+    /// includer, returning a description of the events
+    fn parse(
+        docs: &[(&str, &str)],
+        f: impl FnOnce(
+            Includer<LexerPos, HashMap<String, String>>,
+        ) -> Includer<LexerPos, HashMap<String, String>>,
+    ) -> Result<Vec<String>, HmlError<LexerPos>> {
+        parse_with(docs, Parser::default(), f).map(|(events, _)| events)
+    }
+
+    //fi parse_with
+    /// Parse the document 'main' of a set of documents with a parser
+    /// and an includer, returning a description of the events and
+    /// the number of warnings
+    fn parse_with(
+        docs: &[(&str, &str)],
+        mut parser: Parser<LexerPos>,
+        f: impl FnOnce(
+            Includer<LexerPos, HashMap<String, String>>,
+        ) -> Includer<LexerPos, HashMap<String, String>>,
+    ) -> Result<(Vec<String>, usize), HmlError<LexerPos>> {
+        let docs: HashMap<String, String> = docs
+            .iter()
+            .map(|(n, t)| (n.to_string(), t.to_string()))
+            .collect();
+        let text = docs["main"].clone();
+        let mut namespace = Namespace::new(true);
+        let mut namespace_stack = NamespaceStack::new(&mut namespace);
+        let lexer_string: LexerOfString<LexerPos, _, _> = LexerOfString::default().set_text(&text);
+        let lexer = lexer_string.lexer();
+        let lexer_parsers = crate::hml_reader::parse_fns();
+        let mut lexer_iter = lexer.iter(&lexer_parsers);
+        let mut includer = f(Includer::new(docs).set_document_name("main"));
+        let mut events = Vec::new();
+        loop {
+            let event =
+                includer.next_event(&mut namespace_stack, &mut parser, || lexer_iter.next())?;
+            events.push(match &event {
+                Event::StartElement { tag, .. } => {
+                    format!("<{}", namespace_stack.name_str(tag.name.name))
+                }
+                Event::EndElement { .. } => ">".to_string(),
+                Event::ProcessingInstruction { name, data, .. } => {
+                    format!("?{} {:?}", namespace_stack.name_str(*name), data)
+                }
+                e => format!("{:?}", e.get_type()),
+            });
+            if event.is_end_document() {
+                return Ok((events, parser.take_warnings().len()));
+            }
+        }
+    }
+
+    //fi chain
+    /// Get the chain of included documents of an error, with the
+    /// line of each include directive, and the innermost error
+    fn chain(mut e: &HmlError<LexerPos>) -> (Vec<(String, usize)>, &'static str, usize) {
+        let mut chain = Vec::new();
+        while let HmlError::InInclude { span, path, error } = e {
+            chain.push((path.clone(), span.start().line()));
+            e = error;
+        }
+        (chain, e.kind(), e.span().unwrap().start().line())
+    }
+
+    #[test]
+    fn test_include() {
+        let docs = [
+            ("main", "#device\n##?include \"regs\"\n##end"),
+            ("regs", "##reg{\n#?include \"field\"\n##reg}\n##reg"),
+            ("field", "; field\n#field ##?other"),
+        ];
+        let events = parse(&docs, |i| i).unwrap();
+        assert_eq!(
+            events.join(" "),
+            "StartDocument <device <reg Comment <field ?other None > > <reg > <end > > EndDocument"
+        );
+        let events = parse(&docs, |i| i.set_expand(false)).unwrap();
+        assert_eq!(
+            events.join(" "),
+            "StartDocument <device ?include Some(\"regs\") <end > > EndDocument"
+        );
+        let docs = [("main", "#a ##?use \"b\""), ("b", "##b")];
+        let events = parse(&docs, |i| i.set_pi_name("use")).unwrap();
+        assert_eq!(events.join(" "), "StartDocument <a <b > > EndDocument");
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let docs = [
+            ("main", "#a\n\n##?include \"b\""),
+            ("b", "##b\n##?include \"c\""),
+            ("c", "##c\n\n\n####bad"),
+            ("d", "#?include \"e\""),
+            ("e", "#e\n#?include \"d\""),
+            ("f", "#f #?include"),
+        ];
+        let err = parse(&docs, |i| i).unwrap_err();
+        assert_eq!(
+            chain(&err),
+            (
+                vec![("b".to_string(), 3), ("c".to_string(), 2)],
+                "unexpected_tag_indent",
+                4
+            )
+        );
+        assert_eq!(err.kind(), "unexpected_tag_indent");
+
+        let mut docs = docs;
+        docs[0].1 = "#?include \"d\"";
+        let err = parse(&docs, |i| i).unwrap_err();
+        assert_eq!(
+            chain(&err),
+            (
+                vec![("d".to_string(), 1), ("e".to_string(), 1)],
+                "include_cycle",
+                2
+            )
+        );
+
+        docs[0].1 = "#a\n##?include \"missing\"";
+        let err = parse(&docs, |i| i).unwrap_err();
+        assert_eq!(chain(&err), (vec![], "include_failed", 2));
+
+        docs[0].1 = "#a\n##?include \"f\"";
+        let err = parse(&docs, |i| i).unwrap_err();
+        assert_eq!(chain(&err).1, "tag_outside_fragment");
+    }
+
+    #[test]
+    fn test_include_parser_settings() {
+        let strict = || Parser::default().set_mode(DocumentMode::Strict);
+        let docs = [
+            ("main", "#a\n#?include \"x\""),
+            ("x", "#b"),
+            ("y", "; only a comment"),
+            ("z", "##z ##?include \"w\""),
+            ("w", "##w"),
+        ];
+        let err = parse_with(&docs, strict(), |i| i).unwrap_err();
+        assert_eq!(
+            chain(&err),
+            (vec![("x".to_string(), 2)], "multiple_root_elements", 1)
+        );
+
+        let mut docs = docs;
+        docs[0].1 = "#?include \"x\"\n#a";
+        let err = parse_with(&docs, strict(), |i| i).unwrap_err();
+        assert_eq!(chain(&err), (vec![], "multiple_root_elements", 2));
+
+        docs[0].1 = "#?include \"y\"\n#?include \"x\"";
+        let (events, _) = parse_with(&docs, strict(), |i| i).unwrap();
+        assert_eq!(events.join(" "), "StartDocument Comment <b > EndDocument");
+
+        docs[0].1 = "#?include \"y\"";
+        let err = parse_with(&docs, strict(), |i| i).unwrap_err();
+        assert_eq!(err.kind(), "missing_root_element");
+
+        // Includes within the root element are not at the top level
+        docs[0].1 = "#a\n##?include \"z\"";
+        let (events, _) = parse_with(&docs, strict(), |i| i).unwrap();
+        assert_eq!(events.join(" "), "StartDocument <a <z > <w > > EndDocument");
+
+        let docs = [("main", "#a\n##?include \"x\""), ("x", "##q:c")];
+        let err = parse(&docs, |i| i).unwrap_err();
+        assert_eq!(chain(&err).1, "unmapped_prefix");
+        let auto = Parser::default().set_auto_declare(true);
+        let (events, warnings) = parse_with(&docs, auto, |i| i).unwrap();
+        assert_eq!(events.join(" "), "StartDocument <a <c > > EndDocument");
+        assert_eq!(warnings, 1);
+        let mapped = Parser::default().add_prefix_mapping("q", "urn:q");
+        let (_, warnings) = parse_with(&docs, mapped, |i| i).unwrap();
+        assert_eq!(warnings, 0);
+    }
+
+    #[test]
+    fn test_file_include_cycle() {
+        let dir = std::env::temp_dir().join(format!("hml-include-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("main.hml"), "#a\n##?include \"b.hml\"").unwrap();
+        std::fs::write(dir.join("b.hml"), "##b\n##?include \"main.hml\"").unwrap();
+        // The top-level document is named with a path that is not canonical
+        let main = dir.join(".").join("main.hml").display().to_string();
+        let text = std::fs::read_to_string(&main).unwrap();
+        let mut namespace = Namespace::new(true);
+        let mut namespace_stack = NamespaceStack::new(&mut namespace);
+        let lexer_string: LexerOfString<LexerPos, _, _> = LexerOfString::default().set_text(&text);
+        let lexer = lexer_string.lexer();
+        let lexer_parsers = crate::hml_reader::parse_fns();
+        let mut lexer_iter = lexer.iter(&lexer_parsers);
+        let mut parser = Parser::default();
+        let mut includer = Includer::new(FileLoader).set_document_name(&main);
+        let err = loop {
+            match includer.next_event(&mut namespace_stack, &mut parser, || lexer_iter.next()) {
+                Ok(event) => assert!(!event.is_end_document()),
+                Err(e) => break e,
+            }
+        };
+        std::fs::remove_dir_all(&dir).unwrap();
+        let (chain, kind, line) = chain(&err);
+        assert_eq!(chain.len(), 1);
+        assert!(chain[0].0.ends_with("b.hml"));
+        assert_eq!((kind, line), ("include_cycle", 2));
+    }
+}
@@ -3,7 +3,8 @@ use crate::{HmlError, HmlResult};
 use crate::{Posn, Span};
 
 use super::utils::*;
-use super::Token;
+use super::{Token, TokenType};
+use crate::hml::escape::Escapable;
 
 //a Lexer functions
 //fi parse_comment_line
@@ -68,10 +69,25 @@ where
     L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
     P: Posn,
 {
-    let (end, Some((start, _n))) = lexer.do_while(posn, ch, &|_, ch| ch.is_whitespace()) else {
+    if !ch.is_whitespace() {
         return Ok(None);
-    };
-    let token = Token::whitespace(Span::new(start, end));
+    }
+    // do_while does not track line numbers over newlines, so consume
+    // them individually
+    let mut end = posn;
+    let mut ch = ch;
+    loop {
+        if is_newline(ch) {
+            end = lexer.consumed_char(end, ch);
+        } else {
+            (end, _) = lexer.do_while(end, ch, &|_, ch| ch.is_whitespace() && !is_newline(ch));
+        }
+        match lexer.peek_at(&end) {
+            Some(next) if next.is_whitespace() => ch = next,
+            _ => break,
+        }
+    }
+    let token = Token::whitespace(Span::new(posn, end));
     Ok(Some((end, token)))
 }
 
@@ -124,27 +140,37 @@ where
     else {
         return Ok(None);
     };
+    if lexer.peek_at(&hash_end) == Some('?') {
+        return parse_processing_instruction(lexer, start, hash_end, hash_count);
+    }
 
     let Some((end_name, ns, name)) = parse_namespace_name(lexer, hash_end)? else {
         let span = Span::new(start, hash_end);
         return Err(HmlError::ExpectedTagName { span });
     };
+    let name_span = Span::new(hash_end, end_name);
     let opt_ch = lexer.peek_at(&end_name);
     let (end_posn, result) = {
         match opt_ch {
             Some('{') => {
                 let end_posn = lexer.consumed_char(end_name, '{');
                 let span = Span::new(start, end_posn);
-                (end_posn, Token::open_boxed(span, ns, name, hash_count))
+                (
+                    end_posn,
+                    Token::open_boxed(span, name_span, ns, name, hash_count),
+                )
             }
             Some('}') => {
                 let end_posn = lexer.consumed_char(end_name, '}');
                 let span = Span::new(start, end_posn);
-                (end_posn, Token::close(span, ns, name, hash_count))
+                (
+                    end_posn,
+                    Token::close(span, name_span, ns, name, hash_count),
+                )
             }
             _ => {
                 let span = Span::new(start, end_name);
-                (end_name, Token::open(span, ns, name, hash_count))
+                (end_name, Token::open(span, name_span, ns, name, hash_count))
             }
         }
     };
@@ -158,6 +184,71 @@ where
     Ok(Some((end_posn, result)))
 }
 
+//fi parse_processing_instruction
+/// Parse a processing instruction, given the position of the '?'
+/// after its hashes: a name, and on the same line an optional name
+/// argument followed by optional data as a quoted string (whose
+/// escapes are resolved)
+fn parse_processing_instruction<L, P>(
+    lexer: &L,
+    start: P,
+    posn: P,
+    depth: usize,
+) -> HmlResult<Option<(P, Token<P>)>, P>
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+{
+    let name_start = lexer.consumed_char(posn, '?');
+    let Some((name_end, name)) = parse_name(lexer, name_start)? else {
+        let span = Span::new(start, name_start);
+        return Err(HmlError::ExpectedTagName { span });
+    };
+    let name_span = Span::new(name_start, name_end);
+    let mut end_posn = name_end;
+    let mut arg = None;
+    let mut data = None;
+    while let Some(ch) = lexer.peek_at(&end_posn) {
+        if !ch.is_whitespace() || is_newline(ch) {
+            break;
+        }
+        let (ws_end, _) =
+            lexer.do_while(end_posn, ch, &|_, ch| ch.is_whitespace() && !is_newline(ch));
+        let Some(ch) = lexer.peek_at(&ws_end) else {
+            break;
+        };
+        if let Some((string_end, mut token)) = parse_character_string(lexer, ws_end, ch)? {
+            let s = token.take_contents().pop_front().unwrap();
+            if token.token_type() == TokenType::Characters {
+                let unescaped = Escapable::new(&s).map(|e| e.as_ref().to_string());
+                data = Some(HmlError::map_escape_error(unescaped, token.get_span(), &s)?);
+            } else {
+                data = Some(s);
+            }
+            end_posn = string_end;
+            break;
+        }
+        if arg.is_some() {
+            break;
+        }
+        let Some((arg_end, name)) = parse_name(lexer, ws_end)? else {
+            break;
+        };
+        arg = Some(name);
+        end_posn = arg_end;
+    }
+    // expect whitespace or EOF after the processing instruction
+    if let Some(ch) = lexer.peek_at(&end_posn) {
+        if !ch.is_whitespace() {
+            let span = Span::new(start, end_posn);
+            return Err(HmlError::ExpectedWhitespaceAfterTag { span });
+        }
+    }
+    let span = Span::new(start, end_posn);
+    let token = Token::processing_instruction(span, name_span, name, arg, data, depth);
+    Ok(Some((end_posn, token)))
+}
+
 //fi parse_quoted_string
 /// reads a quoted string, given the stream cursor is pointing just beyond the opening quote character
 ///
@@ -265,7 +356,12 @@ where
         return Err(HmlError::ExpectedEquals { span, ch });
     };
     let span = Span::new(start, end_posn);
-    Ok(Some((end_posn, Token::attribute(span, ns, name, value))))
+    let name_span = Span::new(start, end_name);
+    let value_span = Span::new(posn, end_posn);
+    Ok(Some((
+        end_posn,
+        Token::attribute(span, name_span, value_span, ns, name, value),
+    )))
 }
 
 //fi parse_character_string
@@ -342,8 +438,6 @@ where
 }
 
 //a Tests
-#[cfg(test)]
-use super::TokenType;
 //ft test_parse_comments
 #[test]
 fn test_parse_comments() {
@@ -360,7 +454,7 @@ fn test_parse_comments() {
     let l = TestLexer::new("; This is a comment");
     let posn = Posn::default();
     let result = l.parse(posn, &parsers);
-    assert!(!result.is_err());
+    assert!(result.is_ok());
     let result = result.unwrap();
     assert!(result.is_some());
     let (posn, mut token) = result.unwrap();
@@ -371,7 +465,7 @@ fn test_parse_comments() {
     let l = TestLexer::new("    ; This is a comment");
     let posn = Posn::default();
     let result = l.parse(posn, &parsers);
-    assert!(!result.is_err());
+    assert!(result.is_ok());
     let result = result.unwrap();
     assert!(result.is_some());
     let (posn, mut token) = result.unwrap();
@@ -386,7 +480,7 @@ fn test_parse_comments() {
     );
     let posn = Posn::default();
     let result = l.parse(posn, &parsers);
-    assert!(!result.is_err());
+    assert!(result.is_ok());
     let result = result.unwrap();
     assert!(result.is_some());
     let (posn, mut token) = result.unwrap();
@@ -413,7 +507,7 @@ fn test_parse_whitespace() {
     let l = TestLexer::new("     ");
     let posn = Posn::default();
     let result = l.parse(posn, &parsers);
-    assert!(!result.is_err());
+    assert!(result.is_ok());
     let result = result.unwrap();
     assert!(result.is_some());
     let (posn, token) = result.unwrap();
@@ -423,7 +517,7 @@ fn test_parse_whitespace() {
     let l = TestLexer::new("     ");
     let posn = Posn::default();
     let result = l.parse(posn, &parsers);
-    assert!(!result.is_err());
+    assert!(result.is_ok());
     let result = result.unwrap();
     assert!(result.is_some());
     let (posn, token) = result.unwrap();
@@ -532,6 +626,64 @@ fn test_parse_tag() {
     assert!(l.peek_at(&posn).is_none());
 }
 
+//ft test_parse_processing_instruction
+#[test]
+fn test_parse_processing_instruction() {
+    use lexer_rs::*;
+    type Posn = StreamCharPos<LineColumn>;
+    type TestLexer<'a> = LexerOfStr<'a, Posn, Token<Posn>, HmlError<Posn>>;
+    let parsers = [
+        Box::new(parse_whitespace) as BoxDynLexerParseFn<TestLexer>,
+        Box::new(parse_tag),
+    ];
+
+    let text = "##?include \"a\\tb.hml\" #?pi\n#?raw r#\"x\"y\"#\n#?if X #?define Y \"a b\"";
+    let l = TestLexer::new(text);
+    let mut posn = Posn::default();
+    let mut pis = Vec::new();
+    while let Some((p, mut token)) = l.parse(posn, &parsers).unwrap() {
+        posn = p;
+        if token.token_type() == TokenType::ProcessingInstruction {
+            let depth = token.get_depth();
+            let span = token.get_span().byte_range();
+            pis.push((depth, token.take_contents(), &text[span]));
+        }
+    }
+    assert_eq!(
+        pis,
+        vec![
+            (
+                2,
+                ["include", "", "a\tb.hml"].map(String::from).into(),
+                "##?include \"a\\tb.hml\""
+            ),
+            (1, ["pi", ""].map(String::from).into(), "#?pi"),
+            (
+                1,
+                ["raw", "", "x\"y"].map(String::from).into(),
+                "#?raw r#\"x\"y\"#"
+            ),
+            (1, ["if", "X"].map(String::from).into(), "#?if X"),
+            (
+                1,
+                ["define", "Y", "a b"].map(String::from).into(),
+                "#?define Y \"a b\""
+            ),
+        ]
+    );
+
+    for text in [
+        "#? x",
+        "#?pi\"x\"",
+        "#?pi \"x\"#",
+        "#?pi \"\\q\"",
+        "#?pi x:",
+    ] {
+        let l = TestLexer::new(text);
+        assert!(l.parse(Posn::default(), &parsers).is_err(), "{}", text);
+    }
+}
+
 //ft test_parse_character_string
 #[test]
 fn test_parse_character_string() {
@@ -607,3 +759,94 @@ fn test_parse_attribute() {
 
     assert!(l.peek_at(&posn).is_none());
 }
+
+//ft test_quote_round_trip
+#[test]
+fn test_quote_round_trip() {
+    use crate::hml::escape::{quote, Escapable};
+    use lexer_rs::*;
+    type Posn = StreamCharPos<LineColumn>;
+    type TestLexer<'a> = LexerOfStr<'a, Posn, Token<Posn>, HmlError<Posn>>;
+
+    for s in [
+        "",
+        "fred",
+        r"c:\windows",
+        "it's \"quoted\"",
+        "line 1\nline 2\n",
+        "\"# and \"## in content",
+        "ends with a quote\"",
+        "tab\tand cr\r\n",
+        "nul \0 and \"\u{1b}[0m\" \\",
+        "\u{1f600} \\u{1f600}",
+    ] {
+        let q = quote(s);
+        let l = TestLexer::new(&q);
+        let parsers = [Box::new(parse_character_string) as BoxDynLexerParseFn<TestLexer>];
+        let (posn, mut token) = l.parse(Posn::default(), &parsers).unwrap().unwrap();
+        assert!(
+            l.peek_at(&posn).is_none(),
+            "Quoted {:?} as {} did not parse to the end",
+            s,
+            q
+        );
+        let contents = token.take_contents().pop_front().unwrap();
+        if token.token_type() == TokenType::RawCharacters {
+            assert_eq!(contents, s, "Raw quote {}", q);
+        } else {
+            assert_eq!(
+                Escapable::new(&contents).unwrap().as_ref(),
+                s,
+                "Quote {}",
+                q
+            );
+        }
+    }
+}
+
+//ft test_escape_error_span
+#[test]
+fn test_escape_error_span() {
+    use crate::hml::escape::Escapable;
+    use lexer_rs::*;
+    type Posn = StreamCharPos<LineColumn>;
+    type TestLexer<'a> = LexerOfStr<'a, Posn, Token<Posn>, HmlError<Posn>>;
+
+    for (text, bad) in [
+        (r#""a \u{110000} b""#, r"\u{110000}"),
+        ("##\"\u{1f600}\n \\q \"##", r"\q"),
+    ] {
+        let l = TestLexer::new(text);
+        let parsers = [Box::new(parse_character_string) as BoxDynLexerParseFn<TestLexer>];
+        let (_, mut token) = l.parse(Posn::default(), &parsers).unwrap().unwrap();
+        let span = *token.get_span();
+        let data = token.take_contents().pop_front().unwrap();
+        let err = HmlError::map_escape_error(Escapable::new(&data), &span, &data).unwrap_err();
+        assert!(matches!(err, HmlError::BadEscape { .. }));
+        assert_eq!(&text[err.span().unwrap().byte_range()], bad);
+    }
+}
+
+//ft test_whitespace_lines
+#[test]
+fn test_whitespace_lines() {
+    use lexer_rs::*;
+    type Posn = StreamCharPos<LineColumn>;
+    type TestLexer<'a> = LexerOfStr<'a, Posn, Token<Posn>, HmlError<Posn>>;
+    let parsers = [
+        Box::new(parse_whitespace) as BoxDynLexerParseFn<TestLexer>,
+        Box::new(parse_attribute),
+    ];
+
+    let l = TestLexer::new("a='1' \n\n  \t b='2'\n");
+    let (posn, _) = l.parse(Posn::default(), &parsers).unwrap().unwrap();
+    let (posn, token) = l.parse(posn, &parsers).unwrap().unwrap();
+    assert_eq!(token.token_type(), TokenType::Whitespace);
+    assert_eq!((posn.line(), posn.column()), (3, 5));
+    let (posn, token) = l.parse(posn, &parsers).unwrap().unwrap();
+    assert_eq!(token.token_type(), TokenType::Attribute);
+    assert_eq!((token.get_span().start().line(), posn.column()), (3, 10));
+    let (posn, _) = l.parse(posn, &parsers).unwrap().unwrap();
+    assert_eq!((posn.line(), posn.column()), (4, 1));
+    assert!(l.peek_at(&posn).is_none());
+}
@@ -1,8 +1,13 @@
 //a Imports
 use super::{CloseTag, OpenTag, StackElement, Token, TokenType};
-use crate::markup::{ContentType, Event};
-use crate::names::NamespaceStack;
-use crate::{HmlError, HmlResult, Posn, Span};
+use crate::markup::{AttributeSpan, ContentType, Event};
+use crate::names::{Name, NamespaceStack};
+use crate::{HmlError, HmlResult, MarkupError, Posn, Span};
+
+//a Constants
+/// The start of the URI used for a prefix that is automatically
+/// declared (the prefix is appended to it)
+const AUTO_DECLARE_URI: &str = "urn:hml:undeclared:";
 
 //a Internal types
 //ti TagExtra
@@ -17,15 +22,61 @@ impl TagExtra {
     }
 }
 
-//a Public types: Parser and TokenFn
+//a Public types: DocumentMode, Parser and TokenFn
+//tp DocumentMode
+/// The top-level structure of the document that a [Parser] accepts
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentMode {
+    /// A fragment of a document: any number of top-level elements,
+    /// and content outside any element, are permitted (the default)
+    #[default]
+    Fragment,
+    /// A complete document, as per the 'Document' grammar: there
+    /// must be exactly one root element, with only comments outside
+    /// it
+    Strict,
+}
+
 //tp Parser
 /// A parser, using a file position provided
 ///
+/// The parser can be configured before use with namespace prefix
+/// mappings that apply to the whole document (as if they were
+/// declared on an element enclosing the document), and with an
+/// 'auto-declare' mode for prefixes that are not mapped.
+///
+/// By default the document is parsed as a fragment, which may have
+/// many top-level elements; [DocumentMode::Strict] requires a
+/// single root element.
+///
+/// Element content that is embedded in other text can be parsed
+/// with [Parser::next_fragment_event], which does not produce
+/// StartDocument or EndDocument events, so that the events can be
+/// spliced in to another event stream; the content can be at a base
+/// depth (see [Parser::set_base_depth]), as if within an enclosing
+/// element at that depth (see [Parser::set_enclosing_element]), and
+/// names are resolved using the frames already on the
+/// [NamespaceStack] provided.
+///
+/// ```text
+///  let mut parser = Parser::default()
+///      .add_prefix_mapping("svg", "http://www.w3.org/2000/svg")
+///      .set_default_namespace("http://www.w3.org/1999/xhtml");
+/// ```
 pub struct Parser<P>
 where
     P: Posn,
 {
     version: usize,
+    prefix_mappings: Vec<(String, String)>,
+    auto_declare: bool,
+    mode: DocumentMode,
+    root_span: Option<Span<P>>,
+    nested: bool,
+    base_depth: usize,
+    enclosing: Option<String>,
+    enclosing_name: Option<Name>,
+    warnings: Vec<HmlError<P>>,
     pending_eof: bool,
     start_emitted: bool,
     end_emitted: bool,
@@ -47,6 +98,15 @@ where
     fn default() -> Self {
         Parser {
             version: 100,
+            prefix_mappings: Vec::new(),
+            auto_declare: false,
+            mode: DocumentMode::default(),
+            root_span: None,
+            nested: false,
+            base_depth: 0,
+            enclosing: None,
+            enclosing_name: None,
+            warnings: Vec::new(),
             start_emitted: false,
             end_emitted: false,
             finished: false,
@@ -76,6 +136,157 @@ where
         self
     }
 
+    //mp add_prefix_mapping
+    /// Map a namespace prefix to a URI for the whole document; this
+    /// mapping is added to the [NamespaceStack] (in a new stack
+    /// frame) before the first element is parsed, and it may be
+    /// overridden by declarations within the document
+    #[inline]
+    pub fn add_prefix_mapping(mut self, prefix: &str, uri: &str) -> Self {
+        self.prefix_mappings.push((prefix.into(), uri.into()));
+        self
+    }
+
+    //mp set_default_namespace
+    /// Set the default namespace URI for the document - the URI for
+    /// names that have no prefix
+    #[inline]
+    pub fn set_default_namespace(self, uri: &str) -> Self {
+        self.add_prefix_mapping("", uri)
+    }
+
+    //mp set_auto_declare
+    /// Enable or disable auto-declaration of prefixes
+    ///
+    /// If enabled, a prefix that is used in a tag or attribute name
+    /// but is not mapped to a URI is mapped by the parser (for the
+    /// element being parsed) to a URI of 'urn:hml:undeclared:'
+    /// followed by the prefix, and a warning is recorded, rather than
+    /// the parse failing with an unmapped prefix error
+    #[inline]
+    pub fn set_auto_declare(mut self, auto_declare: bool) -> Self {
+        self.auto_declare = auto_declare;
+        self
+    }
+
+    //mp set_mode
+    /// Set the [DocumentMode], which determines whether the document
+    /// must have a single root element
+    #[inline]
+    pub fn set_mode(mut self, mode: DocumentMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    //mp set_base_depth
+    /// Set the base depth for a fragment (see
+    /// [Parser::next_fragment_event]): the fragment is the content of
+    /// an element at this depth, so its top-level tags must be of
+    /// depth `base_depth`+1
+    ///
+    /// The content of a boxed element (such as '##body{') starts
+    /// again at depth 1, so has a base depth of 0
+    #[inline]
+    pub fn set_base_depth(mut self, base_depth: usize) -> Self {
+        self.base_depth = base_depth;
+        self
+    }
+
+    //mp set_enclosing_element
+    /// Set the (possibly prefixed) name of the element that encloses
+    /// a fragment; if the fragment contains a close tag for this
+    /// element (outside of any element of the fragment) then the
+    /// fragment ends there, without reading further tokens
+    ///
+    /// Without an enclosing element, such a close tag is an error
+    #[inline]
+    pub fn set_enclosing_element(mut self, name: &str) -> Self {
+        self.enclosing = Some(name.into());
+        self
+    }
+
+    //mp nested
+    /// Create a parser for a fragment that is part of the document
+    /// being parsed by this parser, such as an included document,
+    /// with its content at `base_depth`
+    ///
+    /// The nested parser has the version and auto-declaration
+    /// setting of this parser. In strict mode, if the fragment is at
+    /// the top level of the document then only one root element is
+    /// permitted across the two (see [Parser::end_nested]), but the
+    /// fragment need not have a root itself. The prefix mappings of
+    /// this parser are not copied, as they are already on the
+    /// [NamespaceStack] (beneath any declarations that override them)
+    /// and so apply to the fragment
+    pub fn nested(&self, base_depth: usize) -> Self {
+        let mode = {
+            if self.tag_stack.is_empty() {
+                self.mode
+            } else {
+                DocumentMode::Fragment
+            }
+        };
+        Self {
+            version: self.version,
+            auto_declare: self.auto_declare,
+            mode,
+            root_span: self.root_span,
+            nested: true,
+            base_depth,
+            ..Self::default()
+        }
+    }
+
+    //mp end_nested
+    /// Update this parser once the fragment of a parser from
+    /// [Parser::nested] has ended, taking its root element (if it had
+    /// one) and its warnings
+    pub fn end_nested(&mut self, mut nested: Self) {
+        if self.root_span.is_none() {
+            self.root_span = nested.root_span;
+        }
+        self.warnings.append(&mut nested.warnings);
+    }
+
+    //mp content_depth
+    /// Get the depth of the content currently being parsed: a tag or
+    /// processing instruction at this depth plus one is part of it
+    ///
+    /// After a processing instruction event this is the depth of the
+    /// content that contains the processing instruction
+    pub fn content_depth(&self) -> usize {
+        self.tag_depth
+    }
+
+    //mp take_warnings
+    /// Take the warnings that have been recorded by the parser so
+    /// far; these are errors that the parser was configured to
+    /// tolerate, such as unmapped prefixes when auto-declaring
+    pub fn take_warnings(&mut self) -> Vec<HmlError<P>> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    //mi auto_declare_prefix
+    /// If auto-declaration is enabled and the prefix is not mapped
+    /// then map it in the topmost namespace stack frame, recording a
+    /// warning for the span
+    fn auto_declare_prefix(&mut self, ns_stack: &mut NamespaceStack, prefix: &str, span: &Span<P>) {
+        if !self.auto_declare {
+            return;
+        }
+        let mapped = ns_stack
+            .find_prefix_id(prefix)
+            .and_then(|p_id| ns_stack.find_mapping(p_id))
+            .is_some();
+        if !mapped {
+            ns_stack.add_ns(prefix, &format!("{}{}", AUTO_DECLARE_URI, prefix));
+            self.warnings.push(HmlError::MarkupError {
+                span: *span,
+                source: MarkupError::unmapped_prefix(prefix),
+            });
+        }
+    }
+
     //mi pop_tag_stack
     /// Pops the tag stack and returns an Event of an end of that element
     fn pop_tag_stack(
@@ -95,6 +306,11 @@ where
         ns_stack: &mut NamespaceStack,
     ) -> HmlResult<Option<Event<P>>, P> {
         if self.tag_stack.is_empty() {
+            if self.mode == DocumentMode::Strict && self.root_span.is_none() && !self.nested {
+                let span = Span::new_at(&self.token_pos);
+                return Err(HmlError::MissingRootElement { span });
+            }
+            ns_stack.pop_frame();
             self.end_emitted = true;
             Ok(None)
         } else {
@@ -112,6 +328,9 @@ where
         ns_stack: &mut NamespaceStack,
         close_tag: CloseTag<P, TagExtra>,
     ) -> HmlResult<Option<Event<P>>, P> {
+        if self.tag_stack.is_empty() {
+            return self.close_enclosing(ns_stack, close_tag);
+        }
         // If there are tags that are close the current element at the top of the stack
         if self.tag_depth > 0 {
             let span = Span::new_at(close_tag.span().start());
@@ -123,6 +342,25 @@ where
         }
     }
 
+    //mi close_enclosing
+    /// Handle a close tag when no element is open, which must close
+    /// the enclosing element of a fragment
+    fn close_enclosing(
+        &mut self,
+        ns_stack: &mut NamespaceStack,
+        close_tag: CloseTag<P, TagExtra>,
+    ) -> HmlResult<Option<Event<P>>, P> {
+        if self.enclosing_name.as_ref() != Some(close_tag.name()) {
+            return Err(HmlError::UnexpectedEndTag {
+                span: *close_tag.span(),
+                name: close_tag.name().to_string(ns_stack),
+            });
+        }
+        self.token_pos = *close_tag.span().end();
+        self.pending_eof = true;
+        Ok(None)
+    }
+
     //mi handle_open_tag
     /// If the OpenTag has a depth <= the current then close the top of the tag stack
     ///
@@ -134,7 +372,12 @@ where
         ns_stack: &mut NamespaceStack,
         open_tag: OpenTag<P, TagExtra>,
     ) -> HmlResult<Option<Event<P>>, P> {
-        if open_tag.extra.depth <= self.tag_depth {
+        if open_tag.extra.depth <= self.tag_depth && self.tag_stack.is_empty() {
+            Err(HmlError::TagOutsideFragment {
+                span: *open_tag.span(),
+                depth: self.tag_depth,
+            })
+        } else if open_tag.extra.depth <= self.tag_depth {
             let span = Span::new_at(open_tag.span().start());
             self.pending_open_tag = Some(open_tag);
             self.pop_tag_stack(ns_stack, &span)
@@ -155,6 +398,24 @@ where
         }
     }
 
+    //mi check_top_level
+    /// In strict mode, check that an element or content at the top
+    /// level (outside any element) is permitted; only the first
+    /// top-level element, the root, is
+    fn check_top_level(&mut self, span: &Span<P>, is_element: bool) -> HmlResult<(), P> {
+        if self.mode != DocumentMode::Strict {
+            return Ok(());
+        }
+        if !is_element {
+            return Err(HmlError::ContentOutsideRoot { span: *span });
+        }
+        if let Some(root) = self.root_span {
+            return Err(HmlError::MultipleRootElements { span: *span, root });
+        }
+        self.root_span = Some(*span);
+        Ok(())
+    }
+
     //mi handle_token
     fn handle_token(
         &mut self,
@@ -167,6 +428,18 @@ where
         if self.start_element_building && !token.is_attribute() {
             self.start_element_building = false;
             self.pending_token = Some(token);
+            let span = *self.tag_stack.last().unwrap().open_tag().span();
+            let prefixes = self
+                .tag_stack
+                .last_mut()
+                .unwrap()
+                .declare_namespaces(ns_stack)?;
+            for (prefix, span) in prefixes.iter() {
+                self.auto_declare_prefix(ns_stack, prefix, span);
+            }
+            if self.tag_stack.len() == 1 {
+                self.check_top_level(&span, true)?;
+            }
             Ok(Some(
                 self.tag_stack
                     .last_mut()
@@ -190,11 +463,13 @@ where
                 }
                 TokenType::TagOpen => {
                     let span = *token.get_span();
+                    let name_span = token.get_spans()[0];
                     let mut args = token.take_contents();
                     let prefix = args.pop_front().unwrap();
                     let name = args.pop_front().unwrap();
                     self.pending_open_tag = Some(OpenTag::new(
                         span,
+                        name_span,
                         prefix,
                         name,
                         TagExtra::new(token.get_depth(), token.get_boxed()),
@@ -206,6 +481,7 @@ where
                     let mut args = token.take_contents();
                     let prefix = args.pop_front().unwrap();
                     let name = args.pop_front().unwrap();
+                    self.auto_declare_prefix(ns_stack, &prefix, &span);
                     let close_tag = CloseTag::new(
                         span,
                         ns_stack,
@@ -216,23 +492,53 @@ where
                     self.pending_close_tag = Some(close_tag);
                     Ok(None)
                 }
+                TokenType::ProcessingInstruction => {
+                    let span = *token.get_span();
+                    let depth = token.get_depth();
+                    if depth <= self.tag_depth && !self.tag_stack.is_empty() {
+                        self.pending_token = Some(token);
+                        return self.pop_tag_stack(ns_stack, &Span::new_at(span.start()));
+                    } else if depth <= self.tag_depth {
+                        let depth = self.tag_depth;
+                        return Err(HmlError::TagOutsideFragment { span, depth });
+                    } else if depth > self.tag_depth + 1 {
+                        return HmlError::unexpected_tag_indent(span, self.tag_depth + 1);
+                    }
+                    let mut args = token.take_contents();
+                    let name = ns_stack.add_name(&args.pop_front().unwrap());
+                    let arg = args.pop_front().filter(|arg| !arg.is_empty());
+                    let data = args.pop_front();
+                    Ok(Some(Event::ProcessingInstruction {
+                        span,
+                        name,
+                        arg,
+                        data,
+                    }))
+                }
                 TokenType::Attribute => {
                     let span = *token.get_span();
+                    let spans = token.get_spans();
+                    let attribute_span = AttributeSpan::new(spans[0], spans[1]);
                     let mut args = token.take_contents();
                     let prefix = args.pop_front().unwrap();
                     let name = args.pop_front().unwrap();
                     let value = args.pop_front().unwrap();
                     if self.start_element_building {
-                        self.tag_stack
-                            .last_mut()
-                            .unwrap()
-                            .add_attribute(span, ns_stack, &prefix, &name, value)?;
+                        self.tag_stack.last_mut().unwrap().add_attribute(
+                            attribute_span,
+                            prefix,
+                            name,
+                            value,
+                        );
                         Ok(None)
                     } else {
                         HmlError::unexpected_attribute(span, &prefix, &name)
                     }
                 }
                 TokenType::Characters => {
+                    if self.tag_stack.is_empty() {
+                        self.check_top_level(token.get_span(), false)?;
+                    }
                     let mut data = token.take_contents();
                     let data = data.pop_front().unwrap();
                     Ok(Some(Event::content(
@@ -242,6 +548,9 @@ where
                     )))
                 }
                 TokenType::RawCharacters => {
+                    if self.tag_stack.is_empty() {
+                        self.check_top_level(token.get_span(), false)?;
+                    }
                     let mut data = token.take_contents();
                     let data = data.pop_front().unwrap();
                     Ok(Some(Event::content(
@@ -259,28 +568,37 @@ where
         }
     }
 
-    //mp next_event
-    /// next_event
-    pub fn next_event<T>(
+    //mi start
+    /// Start parsing: push a namespace frame with the configured
+    /// prefix mappings, and resolve the name of the enclosing element
+    fn start(&mut self, ns_stack: &mut NamespaceStack) -> HmlResult<(), P> {
+        self.start_emitted = true;
+        self.tag_depth = self.base_depth;
+        ns_stack.push_frame();
+        for (prefix, uri) in &self.prefix_mappings {
+            ns_stack.add_ns(prefix, uri);
+        }
+        if let Some(enclosing) = &self.enclosing {
+            let (prefix, name) = enclosing.split_once(':').unwrap_or(("", enclosing));
+            let span = Span::new_at(&self.token_pos);
+            let name = HmlError::map_markup_error(Name::new(ns_stack, prefix, name), &span)?;
+            self.enclosing_name = Some(name);
+        }
+        Ok(())
+    }
+
+    //mi next_content_event
+    /// Get the next event of the content (all but the StartDocument
+    /// and EndDocument events), or None if the content has ended
+    fn next_content_event<T>(
         &mut self,
         ns_stack: &mut NamespaceStack,
-        mut get_token: T,
-    ) -> HmlResult<Event<P>, P>
+        get_token: &mut T,
+    ) -> HmlResult<Option<Event<P>>, P>
     where
         T: FnMut() -> Option<HmlResult<Token<P>, P>>,
     {
-        loop {
-            if !self.start_emitted {
-                self.start_emitted = true;
-                let span = Span::new_at(&self.token_pos);
-                return Ok(Event::start_document(span, self.version));
-            } else if self.finished {
-                return HmlError::no_more_events();
-            } else if self.end_emitted {
-                self.finished = true;
-                let span = Span::new_at(&self.token_pos);
-                return Ok(Event::end_document(span));
-            }
+        while !self.end_emitted {
             if let Some(event) = {
                 if self.pending_eof {
                     self.handle_pending_eof(ns_stack)
@@ -298,8 +616,60 @@ where
                     self.handle_token(ns_stack, token)
                 }
             }? {
-                return Ok(event);
+                return Ok(Some(event));
             }
         }
+        Ok(None)
+    }
+
+    //mp next_event
+    /// next_event
+    pub fn next_event<T>(
+        &mut self,
+        ns_stack: &mut NamespaceStack,
+        mut get_token: T,
+    ) -> HmlResult<Event<P>, P>
+    where
+        T: FnMut() -> Option<HmlResult<Token<P>, P>>,
+    {
+        if !self.start_emitted {
+            self.start(ns_stack)?;
+            let span = Span::new_at(&self.token_pos);
+            return Ok(Event::start_document(span, self.version));
+        } else if self.finished {
+            return HmlError::no_more_events();
+        }
+        if let Some(event) = self.next_content_event(ns_stack, &mut get_token)? {
+            return Ok(event);
+        }
+        self.finished = true;
+        let span = Span::new_at(&self.token_pos);
+        Ok(Event::end_document(span))
+    }
+
+    //mp next_fragment_event
+    /// Get the next event of a fragment of element content, or None
+    /// once the fragment has ended (at the end of the tokens, or at
+    /// the close tag of the enclosing element)
+    ///
+    /// This does not produce StartDocument or EndDocument events; the
+    /// namespace frames already on `ns_stack` apply to the fragment,
+    /// and are left unchanged when it ends
+    pub fn next_fragment_event<T>(
+        &mut self,
+        ns_stack: &mut NamespaceStack,
+        mut get_token: T,
+    ) -> HmlResult<Option<Event<P>>, P>
+    where
+        T: FnMut() -> Option<HmlResult<Token<P>, P>>,
+    {
+        if !self.start_emitted {
+            self.start(ns_stack)?;
+        } else if self.finished {
+            return Ok(None);
+        }
+        let event = self.next_content_event(ns_stack, &mut get_token)?;
+        self.finished = event.is_none();
+        Ok(event)
     }
 }
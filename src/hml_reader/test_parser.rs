@@ -25,6 +25,7 @@ mod test_infrastructure {
         Content(ContentType, &'a str),
         EndE,
         EndD,
+        Error,
         Ignore,
     }
 
@@ -55,9 +56,7 @@ mod test_infrastructure {
             name: &str,
             attrs: &[(&str, &str, &str)],
         ) -> bool {
-            if ns_stack.name_str(tag.name.name) != name {
-                false
-            } else if ns_stack.uri_str(tag.name.uri) != uri {
+            if ns_stack.name_str(tag.name.name) != name || ns_stack.uri_str(tag.name.uri) != uri {
                 false
             } else {
                 self.stack.push(tag.name);
@@ -91,11 +90,7 @@ mod test_infrastructure {
         //fp match_close
         fn match_close(&mut self, _ns_stack: &NamespaceStack, name: &Name) -> bool {
             if let Some(stack_name) = self.stack.pop() {
-                if *name == stack_name {
-                    true
-                } else {
-                    false
-                }
+                *name == stack_name
             } else {
                 false
             }
@@ -126,49 +121,41 @@ mod test_infrastructure {
                             "Expected a StartElement {}:{} with attrs {:?}, got {:?}",
                             ns, name, attrs, t
                         ),
-                        if t.is_err() {
-                            false
-                        } else {
-                            let t = t.unwrap();
+                        if let Ok(t) = t {
                             if let Some(tag) = t.as_start_element() {
                                 self.match_open(ns_stack, tag, ns, name, attrs)
                             } else {
                                 false
                             }
+                        } else {
+                            false
                         },
                     ),
                     Expectation::EndE => (
                         format!("Expected an EndElement got {:?}", t),
-                        if t.is_err() {
-                            false
-                        } else {
-                            let t = t.unwrap();
+                        if let Ok(t) = t {
                             if let Some(name) = t.as_end_element() {
                                 self.match_close(ns_stack, name)
                             } else {
                                 false
                             }
+                        } else {
+                            false
                         },
                     ),
                     Expectation::Content(et, es) => (
                         format!("Expected a Content {:?} got {:?}", et, t),
-                        if t.is_err() {
-                            false
-                        } else {
-                            let t = t.unwrap();
+                        if let Ok(t) = t {
                             if let Some((ct, cs)) = t.as_content() {
-                                if et != ct {
-                                    false
-                                } else if es != cs {
-                                    false
-                                } else {
-                                    true
-                                }
+                                et == ct && es == cs
                             } else {
                                 false
                             }
+                        } else {
+                            false
                         },
                     ),
+                    Expectation::Error => (format!("Expected an error, got {:?}", t), t.is_err()),
                     Expectation::Ignore => (String::new(), true),
                 }
             };
@@ -183,6 +170,17 @@ mod test_infrastructure {
     //a Functions for test
     //fp test_string
     pub fn test_string(text: &str, exp: &[Expectation]) {
+        test_string_with_parser(Parser::default(), text, exp);
+    }
+
+    //fp test_string_with_parser
+    /// Parse the string with the given (configured) parser, returning
+    /// the parser after checking the expectations
+    pub fn test_string_with_parser(
+        mut parser: Parser<LexerPos>,
+        text: &str,
+        exp: &[Expectation],
+    ) -> Parser<LexerPos> {
         let mut expectation = ExpectationState::new(exp);
         let mut namespace = Namespace::new(true);
         let mut namespace_stack = NamespaceStack::new(&mut namespace);
@@ -191,23 +189,16 @@ mod test_infrastructure {
         let lexer = lexer_string.lexer();
         let lexer_parsers = crate::hml_reader::parse_fns();
         let mut lexer_iter = lexer.iter(&lexer_parsers);
-        let mut parser: Parser<lexer_rs::StreamCharPos<lexer_rs::LineColumn>> = Parser::default();
         let mut errors = Vec::new();
         loop {
             let t = parser.next_event(&mut namespace_stack, || lexer_iter.next());
             // println!("{:?}", t);
             let eof = t.is_ok() && t.as_ref().unwrap().is_end_document();
             let is_err = t.is_err();
-            match expectation.check_expectation(&namespace_stack, t) {
-                Err(x) => {
-                    errors.push(x);
-                    if is_err {
-                        break;
-                    }
-                }
-                _ => (),
+            if let Err(x) = expectation.check_expectation(&namespace_stack, t) {
+                errors.push(x);
             }
-            if eof {
+            if is_err || eof {
                 break;
             }
         }
@@ -216,6 +207,108 @@ mod test_infrastructure {
             println!("FAIL: {}", e);
         }
         assert!(errors.is_empty());
+        parser
+    }
+
+    //fp first_error
+    /// Parse a string with the given (configured) parser, returning
+    /// the kind of the first error and the text of its span, if any
+    pub fn first_error(mut parser: Parser<LexerPos>, text: &str) -> Option<(&'static str, String)> {
+        let mut namespace = Namespace::new(true);
+        let mut namespace_stack = NamespaceStack::new(&mut namespace);
+        let lexer_string = StringLexer::default().set_text(text);
+        let lexer = lexer_string.lexer();
+        let lexer_parsers = crate::hml_reader::parse_fns();
+        let mut lexer_iter = lexer.iter(&lexer_parsers);
+        loop {
+            match parser.next_event(&mut namespace_stack, || lexer_iter.next()) {
+                Err(e) => {
+                    let range = e.span().unwrap().byte_range();
+                    return Some((e.kind(), text[range].to_string()));
+                }
+                Ok(e) if e.is_end_document() => {
+                    return None;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    //fp fragment_events
+    /// Parse a fragment with the given (configured) parser, within a
+    /// namespace frame that maps 'svg' to 'urn:svg'; return a
+    /// description of each event and the text after the fragment, or
+    /// the kind of the error and the text of its span
+    pub fn fragment_events(
+        mut parser: Parser<LexerPos>,
+        text: &str,
+    ) -> Result<(Vec<String>, String), (&'static str, String)> {
+        let mut namespace = Namespace::new(true);
+        let mut namespace_stack = NamespaceStack::new(&mut namespace);
+        namespace_stack.push_frame();
+        namespace_stack.add_ns("svg", "urn:svg");
+        let lexer_string = StringLexer::default().set_text(text);
+        let lexer = lexer_string.lexer();
+        let lexer_parsers = crate::hml_reader::parse_fns();
+        let mut lexer_iter = lexer.iter(&lexer_parsers);
+        let mut events = Vec::new();
+        loop {
+            match parser.next_fragment_event(&mut namespace_stack, || lexer_iter.next()) {
+                Err(e) => {
+                    let range = e.span().unwrap().byte_range();
+                    return Err((e.kind(), text[range].to_string()));
+                }
+                Ok(None) => break,
+                Ok(Some(Event::StartElement { tag, .. })) => events.push(format!(
+                    "<{{{}}}{}",
+                    namespace_stack.uri_str(tag.name.uri),
+                    namespace_stack.name_str(tag.name.name)
+                )),
+                Ok(Some(Event::EndElement { .. })) => events.push(">".to_string()),
+                Ok(Some(Event::Content { data, .. })) => events.push(data),
+                Ok(Some(e)) => events.push(format!("{:?}", e.get_type())),
+            }
+        }
+        assert!(parser
+            .next_fragment_event(&mut namespace_stack, || lexer_iter.next())
+            .unwrap()
+            .is_none());
+        let svg = namespace_stack.find_prefix_id("svg").unwrap();
+        assert_eq!(
+            namespace_stack.find_mapping_and_depth(svg).map(|(_, d)| d),
+            Some(1)
+        );
+        let rest = match lexer_iter.next() {
+            Some(Ok(t)) => text[t.get_span().byte_range().start..].to_string(),
+            _ => String::new(),
+        };
+        Ok((events, rest))
+    }
+
+    //fp with_events
+    /// Parse a string, which must be valid, and invoke `f` on the
+    /// namespace stack and the events
+    pub fn with_events<F: FnOnce(&mut NamespaceStack, &[Event])>(text: &str, f: F) {
+        let mut parser = Parser::default();
+        let mut namespace = Namespace::new(true);
+        let mut namespace_stack = NamespaceStack::new(&mut namespace);
+        namespace_stack.add_null_ns();
+        let lexer_string = StringLexer::default().set_text(text);
+        let lexer = lexer_string.lexer();
+        let lexer_parsers = crate::hml_reader::parse_fns();
+        let mut lexer_iter = lexer.iter(&lexer_parsers);
+        let mut events = Vec::new();
+        loop {
+            let e = parser
+                .next_event(&mut namespace_stack, || lexer_iter.next())
+                .unwrap();
+            let eof = e.is_end_document();
+            events.push(e);
+            if eof {
+                break;
+            }
+        }
+        f(&mut namespace_stack, &events);
     }
 
     //zz All done
@@ -226,8 +319,11 @@ mod test_infrastructure {
 #[allow(dead_code)]
 mod tests {
     //a Imports from test_infrastructure
-    use super::test_infrastructure::test_string;
-    use super::test_infrastructure::Expectation::{Content, EndD, EndE, StD, StE};
+    use super::test_infrastructure::Expectation::{Content, EndD, EndE, Error, StD, StE};
+    use super::test_infrastructure::{
+        first_error, fragment_events, test_string, test_string_with_parser, with_events,
+    };
+    use crate::hml_reader::{DocumentMode, Parser};
     use crate::markup::ContentType;
 
     //a Structure tests
@@ -377,6 +473,109 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_attr_duplicate() {
+        test_string("#svg a='1' a='2'", &[StD(100), Error]);
+        test_string(
+            "#svg xmlns='u' xmlns:p='u' a='1' p:a='2'",
+            &[
+                StD(100),
+                StE(
+                    "u",
+                    "svg",
+                    &[
+                        ("http://www.w3.org/2000/xmlns/", "xmlns", "u"),
+                        ("http://www.w3.org/2000/xmlns/", "p", "u"),
+                        ("u", "a", "1"),
+                        ("u", "a", "2"),
+                    ],
+                ),
+                EndE,
+                EndD,
+            ],
+        );
+        test_string(
+            "#svg xmlns:p='http://x' xmlns:q='http://x' p:a='1' q:a='2'",
+            &[StD(100), Error],
+        );
+        test_string(
+            "#svg xmlns:p='http://x' xmlns:q='http://y' p:a='1' q:a='2'",
+            &[
+                StD(100),
+                StE(
+                    "",
+                    "svg",
+                    &[
+                        ("http://www.w3.org/2000/xmlns/", "p", "http://x"),
+                        ("http://www.w3.org/2000/xmlns/", "q", "http://y"),
+                        ("http://x", "a", "1"),
+                        ("http://y", "a", "2"),
+                    ],
+                ),
+                EndE,
+                EndD,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_typed_values() {
+        with_events(
+            "#dvd running_time='101' rating=' 4.5 ' boxed='yes' ids='1 2 3' gap='250ms' ##note \"12\"",
+            |nst, events| {
+                let running_time = nst.add_name("running_time");
+                let rating = nst.add_name("rating");
+                let boxed = nst.add_name("boxed");
+                let ids = nst.add_name("ids");
+                let gap = nst.add_name("gap");
+                let missing = nst.add_name("missing");
+                let dvd = &events[1];
+                assert_eq!(dvd.attribute_value::<usize>(nst, running_time).unwrap(), Some(101));
+                assert_eq!(dvd.attribute_value::<f64>(nst, rating).unwrap(), Some(4.5));
+                assert_eq!(dvd.attribute_bool(nst, boxed).unwrap(), Some(true));
+                assert_eq!(dvd.attribute_list::<u8>(nst, ids).unwrap(), Some(vec![1, 2, 3]));
+                assert_eq!(
+                    dvd.attribute_duration(nst, gap).unwrap(),
+                    Some(std::time::Duration::from_millis(250))
+                );
+                assert_eq!(dvd.attribute_value::<usize>(nst, missing).unwrap(), None);
+                let err = dvd.attribute_bool(nst, running_time).unwrap_err();
+                assert!(matches!(
+                    err,
+                    crate::HmlError::MarkupError {
+                        source: crate::MarkupError::BadAttributeValue { .. },
+                        ..
+                    }
+                ));
+                assert_eq!(events[3].content_value::<u32>().unwrap(), Some(12));
+                assert!(events[3].content_bool().is_err());
+                assert_eq!(events[1].content_value::<u32>().unwrap(), None);
+            },
+        );
+    }
+
+    #[test]
+    fn test_tag_spans() {
+        let text = "#dvd{ title=\"Oz\"  running_time='x' #dvd}";
+        with_events(text, |nst, events| {
+            let spans = events[1].tag_spans().unwrap();
+            assert_eq!(&text[spans.name.byte_range()], "dvd");
+            assert_eq!(spans.attributes.len(), 2);
+            assert_eq!(&text[spans.attributes[0].name.byte_range()], "title");
+            assert_eq!(&text[spans.attributes[0].value.byte_range()], "\"Oz\"");
+            assert_eq!(
+                &text[spans.attributes[1].span().byte_range()],
+                "running_time='x'"
+            );
+            let running_time = nst.add_name("running_time");
+            let err = events[1]
+                .attribute_value::<usize>(nst, running_time)
+                .unwrap_err();
+            assert_eq!(&text[err.span().unwrap().byte_range()], "'x'");
+            assert!(events[2].tag_spans().is_none());
+        });
+    }
+
     //a Namespace tests
     #[test]
     fn test_ns() {
@@ -444,6 +643,208 @@ mod tests {
             ],
         );
     }
+    //a Parser namespace configuration tests
+    #[test]
+    fn test_prefix_mapping() {
+        let parser = Parser::default()
+            .add_prefix_mapping("svg", "http://svg")
+            .set_default_namespace("http://html");
+        test_string_with_parser(
+            parser,
+            "#html ##svg:svg ###svg:rect x='1' ##body",
+            &[
+                StD(100),
+                StE("http://html", "html", &[]),
+                StE("http://svg", "svg", &[]),
+                StE("http://svg", "rect", &[("http://html", "x", "1")]),
+                EndE,
+                EndE,
+                StE("http://html", "body", &[]),
+                EndE,
+                EndE,
+                EndD,
+            ],
+        );
+    }
+    #[test]
+    fn test_auto_declare() {
+        let parser = Parser::default().set_auto_declare(true);
+        let mut parser = test_string_with_parser(
+            parser,
+            "#svg:svg ##svg:rect a:x='1' ##svg:box{ ##svg:box}",
+            &[
+                StD(100),
+                StE("urn:hml:undeclared:svg", "svg", &[]),
+                StE(
+                    "urn:hml:undeclared:svg",
+                    "rect",
+                    &[("urn:hml:undeclared:a", "x", "1")],
+                ),
+                EndE,
+                StE("urn:hml:undeclared:svg", "box", &[]),
+                EndE,
+                EndE,
+                EndD,
+            ],
+        );
+        let warnings = parser.take_warnings();
+        assert_eq!(warnings.len(), 2);
+        assert!(parser.take_warnings().is_empty());
+    }
+    #[test]
+    fn test_declare_after_use() {
+        test_string(
+            "#a p:x='1' xmlns:p='u'",
+            &[
+                StD(100),
+                StE(
+                    "",
+                    "a",
+                    &[("u", "x", "1"), ("http://www.w3.org/2000/xmlns/", "p", "u")],
+                ),
+                EndE,
+                EndD,
+            ],
+        );
+        let parser = Parser::default().set_auto_declare(true);
+        let mut parser = test_string_with_parser(
+            parser,
+            "#a p:x='1' xmlns:p='u'",
+            &[
+                StD(100),
+                StE(
+                    "",
+                    "a",
+                    &[("u", "x", "1"), ("http://www.w3.org/2000/xmlns/", "p", "u")],
+                ),
+                EndE,
+                EndD,
+            ],
+        );
+        assert!(parser.take_warnings().is_empty());
+    }
+    #[test]
+    fn test_no_auto_declare() {
+        test_string("#svg:svg", &[StD(100), Error]);
+    }
+
+    //a Document mode tests
+    #[test]
+    fn test_document_mode() {
+        let strict = || Parser::default().set_mode(DocumentMode::Strict);
+        let fragment = || Parser::default().set_mode(DocumentMode::Fragment);
+        for text in ["#a ##b", "; c\n#a{ #b #a}\n; c", "#a{\n#a} ; c"] {
+            assert_eq!(first_error(strict(), text), None, "{}", text);
+        }
+        for (text, kind, bad) in [
+            ("#a #b", "multiple_root_elements", "#b"),
+            ("#a{ #a} #b{ #b}", "multiple_root_elements", "#b{"),
+            ("\"text\" #a", "content_outside_root", "\"text\""),
+            ("#a{ #a} r\"raw\"", "content_outside_root", "r\"raw\""),
+            ("; just a comment", "missing_root_element", ""),
+        ] {
+            assert_eq!(
+                first_error(strict(), text),
+                Some((kind, bad.to_string())),
+                "{}",
+                text
+            );
+            assert_eq!(first_error(fragment(), text), None, "{}", text);
+        }
+        test_string_with_parser(
+            fragment(),
+            "#a \"x\" #b",
+            &[
+                StD(100),
+                StE("", "a", &[]),
+                Content(ContentType::Interpretable, "x"),
+                EndE,
+                StE("", "b", &[]),
+                EndE,
+                EndD,
+            ],
+        );
+    }
+
+    //a Processing instruction tests
+    #[test]
+    fn test_processing_instructions() {
+        let text = "#?top\n#a{ #?pi \"d\" #b ##?inner v #?after w \"e f\" #a}\n#?end r\"x\"";
+        with_events(text, |nst, events| {
+            let found: Vec<String> = events
+                .iter()
+                .map(|e| match e {
+                    crate::markup::Event::ProcessingInstruction {
+                        span,
+                        name,
+                        arg,
+                        data,
+                    } => format!(
+                        "{}:{:?}:{:?}:{}",
+                        nst.name_str(*name),
+                        arg,
+                        data,
+                        &text[span.byte_range()]
+                    ),
+                    e => format!("{:?}", e.get_type()),
+                })
+                .collect();
+            assert_eq!(
+                found,
+                vec![
+                    "StartDocument",
+                    "top:None:None:#?top",
+                    "StartElement",
+                    "pi:None:Some(\"d\"):#?pi \"d\"",
+                    "StartElement",
+                    "inner:Some(\"v\"):None:##?inner v",
+                    "EndElement",
+                    "after:Some(\"w\"):Some(\"e f\"):#?after w \"e f\"",
+                    "EndElement",
+                    "end:None:Some(\"x\"):#?end r\"x\"",
+                    "EndDocument",
+                ]
+            );
+        });
+        test_string("#a ###?pi", &[StD(100), StE("", "a", &[]), Error]);
+    }
+
+    //a Fragment tests
+    #[test]
+    fn test_fragment() {
+        let events = |parser, text| {
+            fragment_events(parser, text).map(|(events, rest)| (events.join(" "), rest))
+        };
+        let ok = |e: &str, r: &str| Ok((e.to_string(), r.to_string()));
+        assert_eq!(
+            events(Parser::default(), "#a \"x\" ##b #svg:c"),
+            ok("<{}a x <{}b > > <{urn:svg}c >", "")
+        );
+        assert_eq!(
+            events(Parser::default().set_base_depth(2), "; c\n###a ####b ###c"),
+            ok("Comment <{}a <{}b > > <{}c >", "")
+        );
+        assert_eq!(
+            events(
+                Parser::default().set_enclosing_element("svg:g"),
+                "#svg:a \"x\"\n#svg:b{ #c #svg:b}\n##svg:g}\n#after"
+            ),
+            ok("<{urn:svg}a x > <{urn:svg}b <{}c > >", "\n#after")
+        );
+        assert_eq!(
+            events(Parser::default().set_base_depth(2), "###a ##b"),
+            Err(("tag_outside_fragment", "##b".to_string()))
+        );
+        assert_eq!(
+            events(Parser::default().set_enclosing_element("g"), "#a #svg:g}"),
+            Err(("unexpected_end_tag", "#svg:g}".to_string()))
+        );
+        assert_eq!(
+            events(Parser::default(), "#a #a}"),
+            Err(("unexpected_end_tag", "#a}".to_string()))
+        );
+    }
+
     //a Content tests
     #[test]
     fn test_content0() {
@@ -10,8 +10,6 @@ use crate::{Posn, Span};
 /// Hence it includes all of attr="string with spaces"
 ///
 /// Missing are whether characters is escapable or not
-///
-/// and processing instruction
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum TokenType {
     /// ; stuff up to newline
@@ -20,6 +18,9 @@ pub enum TokenType {
     TagOpen,
     /// ###<tag>} Tag close - with depth (number of #)
     TagClose,
+    /// ###?<name> [<quoted string>] Processing instruction - with
+    /// depth (number of #), name and optional (unescaped) data
+    ProcessingInstruction,
     /// attribute [<string>:]<string>=<quoted string>
     Attribute,
     /// Quoted string of raw characters
@@ -40,6 +41,7 @@ where
     span: Span<P>,
     tt: TokenType,
     contents: VecDeque<String>,
+    spans: Vec<Span<P>>,
     depth: usize,
     boxed: bool,
 }
@@ -52,10 +54,12 @@ where
     //fi new
     fn new(span: Span<P>, tt: TokenType, depth: usize, boxed: bool) -> Self {
         let contents = VecDeque::new();
+        let spans = Vec::new();
         Self {
             span,
             tt,
             contents,
+            spans,
             depth,
             boxed,
         }
@@ -67,33 +71,86 @@ where
         self
     }
 
+    //cp add_span
+    /// Add the span of a part of the token (such as a tag name or
+    /// attribute value)
+    pub fn add_span(mut self, span: Span<P>) -> Self {
+        self.spans.push(span);
+        self
+    }
+
     //fp open_boxed
-    pub fn open_boxed(span: Span<P>, ns: String, name: String, depth: usize) -> Self {
+    pub fn open_boxed(
+        span: Span<P>,
+        name_span: Span<P>,
+        ns: String,
+        name: String,
+        depth: usize,
+    ) -> Self {
         Self::new(span, TokenType::TagOpen, depth, true)
             .add_string(ns)
             .add_string(name)
+            .add_span(name_span)
     }
 
     //fp open
-    pub fn open(span: Span<P>, ns: String, name: String, depth: usize) -> Self {
+    pub fn open(span: Span<P>, name_span: Span<P>, ns: String, name: String, depth: usize) -> Self {
         Self::new(span, TokenType::TagOpen, depth, false)
             .add_string(ns)
             .add_string(name)
+            .add_span(name_span)
     }
 
     //fp close
-    pub fn close(span: Span<P>, ns: String, name: String, depth: usize) -> Self {
+    pub fn close(
+        span: Span<P>,
+        name_span: Span<P>,
+        ns: String,
+        name: String,
+        depth: usize,
+    ) -> Self {
         Self::new(span, TokenType::TagClose, depth, false)
             .add_string(ns)
             .add_string(name)
+            .add_span(name_span)
+    }
+
+    //fp processing_instruction
+    /// The contents are the name, the name argument (empty if there
+    /// is none), and the data if there is any
+    pub fn processing_instruction(
+        span: Span<P>,
+        name_span: Span<P>,
+        name: String,
+        arg: Option<String>,
+        data: Option<String>,
+        depth: usize,
+    ) -> Self {
+        let t = Self::new(span, TokenType::ProcessingInstruction, depth, false)
+            .add_string(name)
+            .add_string(arg.unwrap_or_default())
+            .add_span(name_span);
+        match data {
+            Some(data) => t.add_string(data),
+            None => t,
+        }
     }
 
     //fp attribute
-    pub fn attribute(span: Span<P>, ns: String, name: String, value: String) -> Self {
+    pub fn attribute(
+        span: Span<P>,
+        name_span: Span<P>,
+        value_span: Span<P>,
+        ns: String,
+        name: String,
+        value: String,
+    ) -> Self {
         Self::new(span, TokenType::Attribute, 0, false)
             .add_string(ns)
             .add_string(name)
             .add_string(value)
+            .add_span(name_span)
+            .add_span(value_span)
     }
 
     //fp comment
@@ -137,6 +194,14 @@ where
         &self.span
     }
 
+    //mp get_spans
+    /// Get the spans of the parts of the token; for tags this is the
+    /// span of the name, for attributes the spans of the name and
+    /// value
+    pub fn get_spans(&self) -> &[Span<P>] {
+        &self.spans
+    }
+
     //mp get_depth
     pub fn get_depth(&self) -> usize {
         self.depth
@@ -206,6 +271,9 @@ where
                     self.span, self.depth, self.contents[0], self.contents[1]
                 )
             }
+            ProcessingInstruction => {
+                write!(f, "[{:?}]#<{}>?{}", self.span, self.depth, self.contents[0])
+            }
             Attribute => {
                 write!(
                     f,
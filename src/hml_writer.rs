@@ -0,0 +1,489 @@
+//a Documentation
+/*!
+
+# HML writer
+
+This module provides a [Writer] that generates an HML document from
+a stream of markup [Event]s, such as those from an XML reader or an
+HML parser.
+
+Elements are written one per line, with the number of '#' characters
+indicating their depth; when an element with child elements would be
+deeper than the maximum depth (see [Writer::set_max_depth]) it is
+written as a boxed element ('#name{ ... #name}') so that its content
+restarts at a depth of one.
+
+An element that is followed by content or a comment within its
+parent (mixed content, such as '<p>Hello <b>world</b>!</p>') is also
+boxed, so that what follows is not read as part of the element.
+
+Raw content is written as a raw HML string, and content that is
+[ContentType::Interpretable] is already in HML escaped form, and is
+just delimited; whitespace is written as the best HML quoted string
+for the data (see [crate::hml::escape::quote]).
+
+!*/
+
+//a Imports
+use std::io::Write;
+
+use crate::hml::escape;
+use crate::markup::{ContentType, Event};
+use crate::names::NamespaceStack;
+use crate::Posn;
+
+//a Internal types
+//ti Node
+/// Part of the content of an element (or of the document) that is
+/// held until it is known how its enclosing element is written
+#[derive(Debug)]
+enum Node {
+    /// Content as an HML quoted string
+    Content(String),
+    /// The lines of a comment
+    Comment(String),
+    /// A processing instruction, without its hashes
+    Instruction(String),
+    /// A complete element
+    Element(Element),
+}
+
+//ti Element
+/// An element and its content
+#[derive(Debug)]
+struct Element {
+    /// Name of the element, for the close tag if boxed
+    name: String,
+    /// Text of the start tag, without its hashes
+    tag: String,
+    /// Content of the element
+    content: Vec<Node>,
+    /// True if the element must be boxed, as content follows it in
+    /// its parent
+    boxed: bool,
+}
+
+//a Writer
+//tp Writer
+/// A writer of HML documents from markup [Event]s
+#[derive(Debug)]
+pub struct Writer<W: Write> {
+    writer: W,
+    max_depth: usize,
+    keep_whitespace: bool,
+    stack: Vec<Element>,
+    document: Vec<Node>,
+    after_comment: bool,
+}
+
+//ip Writer
+impl<W: Write> Writer<W> {
+    //fp new
+    /// Create a new [Writer] that writes to `writer`
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            max_depth: 3,
+            keep_whitespace: true,
+            stack: Vec::new(),
+            document: Vec::new(),
+            after_comment: false,
+        }
+    }
+
+    //cp set_max_depth
+    /// Set the maximum number of '#' characters for an element with
+    /// child elements before it is boxed; the default is 3
+    pub fn set_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth.max(1);
+        self
+    }
+
+    //cp set_keep_whitespace
+    /// Set whether whitespace content is written (the default), or
+    /// dropped - for example when it is just the formatting between
+    /// the elements of an XML document
+    pub fn set_keep_whitespace(mut self, keep_whitespace: bool) -> Self {
+        self.keep_whitespace = keep_whitespace;
+        self
+    }
+
+    //mp into_inner
+    /// Consume the [Writer], returning the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    //mp flush
+    /// Write out everything that has been held back, as if the
+    /// document ended here, and flush the underlying writer; this is
+    /// used to write a fragment of a document, which has no
+    /// EndDocument event
+    ///
+    /// An error is returned if an element is still open
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        if !self.stack.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "cannot flush the HML writer with elements still open",
+            ));
+        }
+        self.write_document(true)?;
+        self.writer.flush()
+    }
+
+    //mi add_node
+    /// Add a node to the content of the current element, or to the
+    /// document if there is none
+    ///
+    /// Content or a comment that follows an element would be read as
+    /// part of that element (or of its last descendant), so that
+    /// element is boxed to close it
+    fn add_node(&mut self, node: Node) -> std::io::Result<()> {
+        let content = match self.stack.last_mut() {
+            Some(e) => &mut e.content,
+            None => &mut self.document,
+        };
+        if matches!(node, Node::Content(_) | Node::Comment(_)) {
+            if let Some(Node::Element(e)) = content.last_mut() {
+                e.boxed = true;
+            }
+        }
+        content.push(node);
+        if self.stack.is_empty() {
+            self.write_document(false)
+        } else {
+            Ok(())
+        }
+    }
+
+    //mi write_document
+    /// Write out the nodes of the document, except for a last
+    /// element unless `end` is true, as whether that element must be
+    /// boxed is not yet known
+    fn write_document(&mut self, end: bool) -> std::io::Result<()> {
+        let mut n = self.document.len();
+        if !end && matches!(self.document.last(), Some(Node::Element(_))) {
+            n -= 1;
+        }
+        let mut text = String::new();
+        self.after_comment = self.render(&mut text, &self.document[..n], 1, self.after_comment);
+        self.document.drain(..n);
+        self.writer.write_all(text.as_bytes())
+    }
+
+    //mi render
+    /// Render nodes whose elements have `hashes` '#' characters,
+    /// returning true if the last node is a comment
+    ///
+    /// An element is boxed if it must be, or if it has child elements
+    /// and it is at the maximum depth
+    fn render(
+        &self,
+        text: &mut String,
+        nodes: &[Node],
+        hashes: usize,
+        after_comment: bool,
+    ) -> bool {
+        let mut after_comment = after_comment;
+        for node in nodes {
+            match node {
+                Node::Content(literal) => {
+                    text.push_str(if after_comment { "\n" } else { " " });
+                    text.push_str(literal);
+                }
+                Node::Comment(data) => {
+                    for line in data.split('\n') {
+                        text.push_str("\n;");
+                        text.push_str(line);
+                    }
+                }
+                Node::Instruction(pi) => {
+                    text.push_str(&format!("\n{}?{}", "#".repeat(hashes), pi));
+                }
+                Node::Element(e) => {
+                    let has_children = e
+                        .content
+                        .iter()
+                        .any(|n| matches!(n, Node::Element(_) | Node::Instruction(_)));
+                    let boxed = e.boxed || (has_children && hashes >= self.max_depth);
+                    let open = if boxed { "{" } else { "" };
+                    text.push_str(&format!("\n{}{}{}", "#".repeat(hashes), e.tag, open));
+                    let child_hashes = if boxed { 1 } else { hashes + 1 };
+                    self.render(text, &e.content, child_hashes, false);
+                    if boxed {
+                        text.push_str(&format!("\n{}{}}}", "#".repeat(hashes), e.name));
+                    }
+                }
+            }
+            after_comment = matches!(node, Node::Comment(_));
+        }
+        after_comment
+    }
+
+    //mi attribute_value
+    /// Quote an attribute value; HML attribute values are not
+    /// escaped, so a value with newlines or both kinds of quote
+    /// cannot be written
+    fn attribute_value(name: &str, value: &str) -> std::io::Result<String> {
+        if value.contains('\n') || (value.contains('"') && value.contains('\'')) {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("attribute {} value cannot be represented in HML", name),
+            ))
+        } else if value.contains('"') {
+            Ok(format!("'{}'", value))
+        } else {
+            Ok(format!("\"{}\"", value))
+        }
+    }
+
+    //mp write_event
+    /// Write an [Event] to the HML document
+    ///
+    /// An element is written out once it is known how it is to be
+    /// written - that is, when the next event after the top-level
+    /// element that contains it is written
+    pub fn write_event<P: Posn>(
+        &mut self,
+        ns: &NamespaceStack,
+        event: &Event<P>,
+    ) -> std::io::Result<()> {
+        match event {
+            Event::StartDocument { .. } => Ok(()),
+            Event::EndDocument { .. } => {
+                self.write_document(true)?;
+                writeln!(self.writer)?;
+                self.flush()
+            }
+            Event::StartElement { tag, .. } => {
+                let mut text = tag.name.to_string(ns);
+                for a in tag.attributes.attributes() {
+                    let name = if a.is_default_namespace(ns) {
+                        "xmlns".into()
+                    } else {
+                        a.name.to_string(ns)
+                    };
+                    let value = Self::attribute_value(&name, &a.value)?;
+                    text.push_str(&format!(" {}={}", name, value));
+                }
+                self.stack.push(Element {
+                    name: tag.name.to_string(ns),
+                    tag: text,
+                    content: Vec::new(),
+                    boxed: false,
+                });
+                Ok(())
+            }
+            Event::EndElement { .. } => {
+                let e = self.stack.pop().unwrap();
+                self.add_node(Node::Element(e))
+            }
+            Event::Content { ctype, data, .. } => {
+                if *ctype == ContentType::Whitespace && !self.keep_whitespace {
+                    return Ok(());
+                }
+                let multiline = data.contains('\n') || data.contains('"');
+                let literal = match ctype {
+                    ContentType::Interpretable => escape::delimit(data, false, multiline),
+                    ContentType::Raw => escape::delimit(data, true, multiline),
+                    ContentType::Whitespace => escape::quote(data),
+                };
+                self.add_node(Node::Content(literal))
+            }
+            Event::ProcessingInstruction {
+                name, arg, data, ..
+            } => {
+                let mut text = ns.name_str(*name).to_string();
+                if let Some(arg) = arg {
+                    text.push_str(&format!(" {arg}"));
+                }
+                if let Some(data) = data {
+                    text.push_str(&format!(" {}", escape::quote(data)));
+                }
+                self.add_node(Node::Instruction(text))
+            }
+            Event::Comment { data, .. } => self.add_node(Node::Comment(data.clone())),
+        }
+    }
+}
+
+//a Tests
+#[cfg(test)]
+mod test {
+    use super::Writer;
+    use crate::hml_reader::Parser;
+    use crate::markup::Event;
+    use crate::names::{Namespace, NamespaceStack};
+
+    use lexer_rs::{Lexer, LexerOfString, LineColumn, StreamCharPos};
+
+    type LexerPos = StreamCharPos<LineColumn>;
+
+    //fi rewrite
+    /// Parse HML text and write it out again as HML
+    fn rewrite(text: &str, max_depth: usize) -> String {
+        let mut namespace = Namespace::new(true);
+        let mut namespace_stack = NamespaceStack::new(&mut namespace);
+        let lexer_string: LexerOfString<LexerPos, _, _> = LexerOfString::default().set_text(text);
+        let lexer = lexer_string.lexer();
+        let lexer_parsers = crate::hml_reader::parse_fns();
+        let mut lexer_iter = lexer.iter(&lexer_parsers);
+        let mut parser = Parser::default();
+        let mut writer = Writer::new(Vec::new()).set_max_depth(max_depth);
+        loop {
+            let event: Event<LexerPos> = parser
+                .next_event(&mut namespace_stack, || lexer_iter.next())
+                .unwrap();
+            writer.write_event(&namespace_stack, &event).unwrap();
+            if event.is_end_document() {
+                break;
+            }
+        }
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    //fi from_xml
+    /// Parse XML text and write it out as HML
+    fn from_xml(text: &str, f: impl FnOnce(Writer<Vec<u8>>) -> Writer<Vec<u8>>) -> String {
+        let mut namespace = Namespace::new(true);
+        let mut namespace_stack = NamespaceStack::new(&mut namespace);
+        let lexer_string: LexerOfString<LexerPos, _, _> = LexerOfString::default().set_text(text);
+        let lexer = lexer_string.lexer();
+        let lexer_parsers = crate::xml_reader::parse_fns();
+        let mut lexer_iter = lexer.iter(&lexer_parsers);
+        let mut parser = crate::xml_reader::Parser::default();
+        let mut writer = f(Writer::new(Vec::new()));
+        loop {
+            let event: Event<LexerPos> = parser
+                .next_event(&mut namespace_stack, || lexer_iter.next())
+                .unwrap();
+            writer.write_event(&namespace_stack, &event).unwrap();
+            if event.is_end_document() {
+                break;
+            }
+        }
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    //fi to_xml
+    /// Parse HML text and write it out as XML
+    fn to_xml(text: &str) -> String {
+        let mut namespace = Namespace::new(true);
+        let mut namespace_stack = NamespaceStack::new(&mut namespace);
+        let lexer_string: LexerOfString<LexerPos, _, _> = LexerOfString::default().set_text(text);
+        let lexer = lexer_string.lexer();
+        let lexer_parsers = crate::hml_reader::parse_fns();
+        let mut lexer_iter = lexer.iter(&lexer_parsers);
+        let mut parser = Parser::default();
+        let mut writer = crate::xml_writer::Writer::new(Vec::new());
+        loop {
+            let event: Event<LexerPos> = parser
+                .next_event(&mut namespace_stack, || lexer_iter.next())
+                .unwrap();
+            writer.write_event(&namespace_stack, &event).unwrap();
+            if event.is_end_document() {
+                break;
+            }
+        }
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_boxing() {
+        let text = r##"#a ##b ###c ####d "deep" ###e "leaf" ##f r"raw \ text""##;
+        let hml = rewrite(text, 3);
+        assert_eq!(
+            hml,
+            "\n#a\n##b\n###c{\n#d \"deep\"\n###c}\n###e \"leaf\"\n##f r\"raw \\ text\"\n"
+        );
+        assert_eq!(rewrite(&hml, 3), hml);
+        let hml = rewrite(text, 1);
+        assert_eq!(
+            hml,
+            "\n#a{\n#b{\n#c{\n#d \"deep\"\n#c}\n#e \"leaf\"\n#b}\n#f r\"raw \\ text\"\n#a}\n"
+        );
+        assert_eq!(rewrite(&hml, 1), hml);
+    }
+
+    #[test]
+    fn test_attributes_and_comments() {
+        let text = "#a xmlns='urn:a' xmlns:p=\"urn:p\" p:x='say \"hi\"'\n; a comment\n\"text\" ##b";
+        let hml = rewrite(text, 3);
+        assert_eq!(
+            hml,
+            "\n#a xmlns=\"urn:a\" xmlns:p=\"urn:p\" p:x='say \"hi\"'\n; a comment\n\"text\"\n##b\n"
+        );
+        assert_eq!(rewrite(&hml, 3), hml);
+    }
+
+    #[test]
+    fn test_mixed_content() {
+        let xml =
+            r#"<p>Hello <b>world</b>!<i>a <b>x</b><!--c--></i>.<br/><![CDATA[say "\n"]]></p>"#;
+        let hml = from_xml(xml, |w| w);
+        assert_eq!(
+            hml,
+            "\n#p \"Hello \"\n##b{ \"world\"\n##b} \"!\"\n##i{ \"a \"\n#b{ \"x\"\n#b}\n;c\n##i} \".\"\n##br{\n##br} r#\"say \"\\n\"\"#\n"
+        );
+        assert_eq!(
+            to_xml(&hml),
+            format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>{}\n", xml)
+        );
+    }
+
+    #[test]
+    fn test_whitespace() {
+        let xml = "<a>\n  <b> </b>\n</a>";
+        assert_eq!(
+            from_xml(xml, |w| w),
+            "\n#a r#\"\n  \"#\n##b{ \" \"\n##b} r#\"\n\"#\n"
+        );
+        assert_eq!(
+            from_xml(xml, |w| w.set_keep_whitespace(false)),
+            "\n#a\n##b\n"
+        );
+    }
+
+    #[test]
+    fn test_raw_content() {
+        let mut namespace = Namespace::new(true);
+        let namespace_stack = NamespaceStack::new(&mut namespace);
+        let span = crate::Span::new(0_usize, 0_usize);
+        let mut writer = Writer::new(Vec::new());
+        for data in ["a\\b", "bell\x07", "two\nlines"] {
+            let event = Event::content_raw(span, data.to_string());
+            writer.write_event(&namespace_stack, &event).unwrap();
+        }
+        writer
+            .write_event(&namespace_stack, &Event::end_document(span))
+            .unwrap();
+        let hml = String::from_utf8(writer.into_inner()).unwrap();
+        assert_eq!(hml, " r\"a\\b\" r\"bell\x07\" r#\"two\nlines\"#\n");
+    }
+
+    #[cfg(feature = "xml")]
+    #[test]
+    fn test_from_xml() {
+        let xml = r#"<?xml version="1.0"?>
+<!--c-->
+<a xmlns="urn:a"><b x="1">Fish &amp; "chips"<![CDATA[x\y]]></b><c/></a>"#;
+        let mut namespace = Namespace::new(true);
+        let mut namespace_stack = NamespaceStack::new(&mut namespace);
+        let reader = xml::reader::ParserConfig::new()
+            .ignore_comments(false)
+            .cdata_to_characters(false)
+            .create_reader(xml.as_bytes());
+        let mut writer = Writer::new(Vec::new());
+        for x in reader {
+            let span = crate::Span::new(0_usize, 0_usize);
+            let event = Event::from_xml_reader(&mut namespace_stack, span, x.unwrap()).unwrap();
+            writer.write_event(&namespace_stack, &event).unwrap();
+        }
+        let hml = String::from_utf8(writer.into_inner()).unwrap();
+        assert_eq!(
+            hml,
+            "\n;c\n#a xmlns=\"urn:a\"\n##b x=\"1\" #\"Fish & \"chips\"\"# r\"x\\y\"\n##c\n"
+        );
+    }
+}
@@ -0,0 +1,401 @@
+//a Documentation
+/*!
+
+# HTML writer
+
+This module provides a [Writer] that generates an HTML5 document from
+a stream of markup [Event]s, such as those from an HML parser.
+
+The output follows the HTML syntax rather than XML: void elements
+(such as 'br' and 'link') are written without an end tag, the
+content of raw text elements ('script' and 'style') is not escaped,
+and boolean attributes (such as 'disabled') whose value is empty or
+their own name are written as just the attribute name. The document
+starts with '<!DOCTYPE html>' (see [Writer::set_doctype]).
+
+Processing instructions have no meaning in HTML, so they are not
+written. A void element may not contain content, except for comments
+and whitespace, which are written after its tag.
+
+If minification is enabled (with [Writer::set_minify]) then comments
+are dropped, and runs of whitespace in text are collapsed to a single
+space (or dropped, between elements), except within 'pre',
+'textarea', 'script' and 'style' elements.
+
+Text is escaped using [crate::escape]; content that is
+[ContentType::Interpretable] is in HML escaped form, and is unescaped
+first.
+
+!*/
+
+//a Imports
+use std::borrow::Cow;
+use std::io::Write;
+
+use crate::escape::{escape_attribute, escape_text, Quote};
+use crate::hml::escape::Escapable;
+use crate::markup::{ContentType, Event};
+use crate::names::NamespaceStack;
+use crate::Posn;
+
+//a Constants
+//ci VOID_ELEMENTS
+/// Elements that have no content, and hence no end tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+//ci RAW_TEXT_ELEMENTS
+/// Elements whose content is not parsed for character references or
+/// markup, up to their end tag
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+//ci PREFORMATTED_ELEMENTS
+/// Elements whose whitespace is significant, in addition to the raw
+/// text elements
+const PREFORMATTED_ELEMENTS: &[&str] = &["pre", "textarea"];
+
+//ci BOOLEAN_ATTRIBUTES
+/// Attributes whose presence indicates true, whatever their value
+const BOOLEAN_ATTRIBUTES: &[&str] = &[
+    "allowfullscreen",
+    "async",
+    "autofocus",
+    "autoplay",
+    "checked",
+    "controls",
+    "default",
+    "defer",
+    "disabled",
+    "formnovalidate",
+    "hidden",
+    "inert",
+    "ismap",
+    "itemscope",
+    "loop",
+    "multiple",
+    "muted",
+    "nomodule",
+    "novalidate",
+    "open",
+    "playsinline",
+    "readonly",
+    "required",
+    "reversed",
+    "selected",
+];
+
+//a Internal types
+//ti OpenElement
+/// An element that has been started but not yet ended
+#[derive(Debug)]
+struct OpenElement {
+    /// Name of the element, for the end tag
+    name: String,
+    /// True if the element is a void element, which cannot have content
+    void: bool,
+    /// True if the content of the element is raw text
+    raw_text: bool,
+    /// True if whitespace in the element must be preserved
+    preformatted: bool,
+}
+
+//fi invalid_data
+/// Create an error for an event that cannot be written as HTML
+fn invalid_data(reason: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, reason)
+}
+
+//fi collapse_whitespace
+/// Collapse each run of whitespace in text to a single space
+fn collapse_whitespace(s: &str) -> Cow<'_, str> {
+    let mut last_ws = false;
+    let needs_collapse = s.chars().any(|ch| {
+        let ws = ch.is_ascii_whitespace();
+        let collapse = ws && (last_ws || ch != ' ');
+        last_ws = ws;
+        collapse
+    });
+    if !needs_collapse {
+        return Cow::Borrowed(s);
+    }
+    let mut r = String::with_capacity(s.len());
+    let mut last_ws = false;
+    for ch in s.chars() {
+        if ch.is_ascii_whitespace() {
+            if !last_ws {
+                r.push(' ');
+            }
+            last_ws = true;
+        } else {
+            r.push(ch);
+            last_ws = false;
+        }
+    }
+    Cow::Owned(r)
+}
+
+//a Writer
+//tp Writer
+/// A writer of HTML5 documents from markup [Event]s
+#[derive(Debug)]
+pub struct Writer<W: Write> {
+    writer: W,
+    doctype: bool,
+    minify: bool,
+    stack: Vec<OpenElement>,
+}
+
+//ip Writer
+impl<W: Write> Writer<W> {
+    //fp new
+    /// Create a new [Writer] that writes to `writer`
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            doctype: true,
+            minify: false,
+            stack: Vec::new(),
+        }
+    }
+
+    //cp set_doctype
+    /// Set whether '<!DOCTYPE html>' is written at the start of the
+    /// document (the default)
+    pub fn set_doctype(mut self, doctype: bool) -> Self {
+        self.doctype = doctype;
+        self
+    }
+
+    //cp set_minify
+    /// Set whether the output is minified, by removing comments and
+    /// collapsing whitespace that is not significant
+    pub fn set_minify(mut self, minify: bool) -> Self {
+        self.minify = minify;
+        self
+    }
+
+    //mp into_inner
+    /// Consume the [Writer], returning the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    //mi preformatted
+    /// Return true if whitespace must be preserved at this point
+    fn preformatted(&self) -> bool {
+        self.stack.iter().any(|e| e.preformatted)
+    }
+
+    //mi start_content
+    /// Check that the current element may have content, and return
+    /// true if its content is raw text
+    fn start_content(&self) -> std::io::Result<bool> {
+        match self.stack.last() {
+            Some(e) if e.void => Err(invalid_data(format!(
+                "void element {} cannot have content",
+                e.name
+            ))),
+            Some(e) => Ok(e.raw_text),
+            None => Ok(false),
+        }
+    }
+
+    //mi write_text
+    /// Write text content (which is not HML escaped)
+    fn write_text(&mut self, text: &str) -> std::io::Result<()> {
+        if self.start_content()? {
+            let name = &self.stack.last().unwrap().name;
+            let end_tag = format!("</{}", name);
+            if text.to_ascii_lowercase().contains(&end_tag) {
+                return Err(invalid_data(format!(
+                    "content of {} element contains '{}'",
+                    name, end_tag
+                )));
+            }
+            return self.writer.write_all(text.as_bytes());
+        }
+        let text = {
+            if self.minify && !self.preformatted() {
+                collapse_whitespace(text)
+            } else {
+                Cow::Borrowed(text)
+            }
+        };
+        self.writer.write_all(escape_text(&text).as_bytes())
+    }
+
+    //mp write_event
+    /// Write an [Event] to the HTML document
+    pub fn write_event<P: Posn>(
+        &mut self,
+        ns: &NamespaceStack,
+        event: &Event<P>,
+    ) -> std::io::Result<()> {
+        match event {
+            Event::StartDocument { .. } => {
+                if self.doctype {
+                    self.writer.write_all(b"<!DOCTYPE html>")?;
+                    if !self.minify {
+                        writeln!(self.writer)?;
+                    }
+                }
+                Ok(())
+            }
+            Event::EndDocument { .. } => {
+                if !self.minify {
+                    writeln!(self.writer)?;
+                }
+                self.writer.flush()
+            }
+            Event::StartElement { tag, .. } => {
+                self.start_content()?;
+                let name = tag.name.to_string(ns);
+                let html_name = {
+                    if tag.name.has_prefix() {
+                        ""
+                    } else {
+                        ns.name_str(tag.name.name)
+                    }
+                };
+                write!(self.writer, "<{}", name)?;
+                for a in tag.attributes.attributes() {
+                    let name = if a.is_default_namespace(ns) {
+                        "xmlns".into()
+                    } else {
+                        a.name.to_string(ns)
+                    };
+                    let boolean = a.value.is_empty()
+                        || (BOOLEAN_ATTRIBUTES.contains(&name.as_str())
+                            && a.value.eq_ignore_ascii_case(&name));
+                    if boolean {
+                        write!(self.writer, " {}", name)?;
+                    } else {
+                        let value = escape_attribute(&a.value, Quote::Double);
+                        write!(self.writer, " {}=\"{}\"", name, value)?;
+                    }
+                }
+                self.writer.write_all(b">")?;
+                let raw_text = RAW_TEXT_ELEMENTS.contains(&html_name);
+                self.stack.push(OpenElement {
+                    void: VOID_ELEMENTS.contains(&html_name),
+                    raw_text,
+                    preformatted: raw_text || PREFORMATTED_ELEMENTS.contains(&html_name),
+                    name,
+                });
+                Ok(())
+            }
+            Event::EndElement { .. } => {
+                let Some(e) = self.stack.pop() else {
+                    return Err(invalid_data(
+                        "end element without a start element".to_string(),
+                    ));
+                };
+                if e.void {
+                    Ok(())
+                } else {
+                    write!(self.writer, "</{}>", e.name)
+                }
+            }
+            Event::Content { ctype, data, .. } => match ctype {
+                ContentType::Whitespace if self.minify && !self.preformatted() => Ok(()),
+                ContentType::Whitespace if self.stack.last().is_some_and(|e| e.void) => {
+                    self.writer.write_all(data.as_bytes())
+                }
+                ContentType::Interpretable => {
+                    let text = Escapable::new(data).map_err(|e| invalid_data(e.to_string()))?;
+                    self.write_text(text.as_ref())
+                }
+                _ => self.write_text(data),
+            },
+            Event::ProcessingInstruction { .. } => Ok(()),
+            Event::Comment { data, .. } => {
+                if self.minify {
+                    return Ok(());
+                }
+                if data.contains("-->") || data.contains("--!>") || data.starts_with('>') {
+                    return Err(invalid_data(format!(
+                        "comment cannot be written in HTML: {}",
+                        data
+                    )));
+                }
+                write!(self.writer, "<!--{}-->", data)
+            }
+        }
+    }
+}
+
+//a Tests
+#[cfg(test)]
+mod test {
+    use super::Writer;
+    use crate::hml_reader::Parser;
+    use crate::markup::Event;
+    use crate::names::{Namespace, NamespaceStack};
+
+    use lexer_rs::{Lexer, LexerOfString, LineColumn, StreamCharPos};
+
+    type LexerPos = StreamCharPos<LineColumn>;
+
+    //fi to_html
+    /// Parse HML text and write it out as HTML
+    fn to_html(text: &str, minify: bool) -> std::io::Result<String> {
+        let mut namespace = Namespace::new(true);
+        let mut namespace_stack = NamespaceStack::new(&mut namespace);
+        let lexer_string: LexerOfString<LexerPos, _, _> = LexerOfString::default().set_text(text);
+        let lexer = lexer_string.lexer();
+        let lexer_parsers = crate::hml_reader::parse_fns();
+        let mut lexer_iter = lexer.iter(&lexer_parsers);
+        let mut parser = Parser::default();
+        let mut writer = Writer::new(Vec::new()).set_minify(minify);
+        loop {
+            let event: Event<LexerPos> = parser
+                .next_event(&mut namespace_stack, || lexer_iter.next())
+                .unwrap();
+            writer.write_event(&namespace_stack, &event)?;
+            if event.is_end_document() {
+                break;
+            }
+        }
+        Ok(String::from_utf8(writer.into_inner()).unwrap())
+    }
+
+    #[test]
+    fn test_html() {
+        let text = r##"#html
+##head{
+#title "Fish & chips"
+#link  rel="stylesheet" href="a.css?x=1&y=2"
+#script async="async" "if (a < b && c) {}"
+##head}
+##body{
+; A comment
+#p "Some   text" ##br
+; After the break
+##input type="checkbox" checked="" value=""
+#pre "  keep\n  this  "
+#textarea "<a> & b"
+##body}
+"##;
+        assert_eq!(
+            to_html(text, false).unwrap(),
+            r#"<!DOCTYPE html>
+<html><head><title>Fish &amp; chips</title><link rel="stylesheet" href="a.css?x=1&amp;y=2"><script async>if (a < b && c) {}</script></head><body><!-- A comment--><p>Some   text<br><!-- After the break--><input type="checkbox" checked value></p><pre>  keep
+  this  </pre><textarea>&lt;a&gt; &amp; b</textarea></body></html>
+"#
+        );
+        assert_eq!(
+            to_html(text, true).unwrap(),
+            r#"<!DOCTYPE html><html><head><title>Fish &amp; chips</title><link rel="stylesheet" href="a.css?x=1&amp;y=2"><script async>if (a < b && c) {}</script></head><body><p>Some text<br><input type="checkbox" checked value></p><pre>  keep
+  this  </pre><textarea>&lt;a&gt; &amp; b</textarea></body></html>"#
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(to_html(r#"#br "text""#, false).is_err());
+        assert!(to_html(r#"#p ##img ###b"#, false).is_err());
+        assert!(to_html(r#"#script "a = '</SCRIPT>'""#, false).is_err());
+    }
+}
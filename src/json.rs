@@ -0,0 +1,835 @@
+//a Documentation
+/*!
+
+# JSON representation of markup
+
+This module converts streams of markup [Event]s to and from JSON, for
+applications that consume JSON rather than XML.
+
+A document is a JSON array of nodes; each node is one of:
+
+* an element, as an object with the element name in `"element"`, the
+  prefix (if any) in `"prefix"` (when reading, the name may instead
+  be given as 'prefix:name' in `"element"`), the namespace URI (if not the null
+  namespace) in `"uri"`, an `"attributes"` object (if the element has
+  any attributes) mapping attribute names (with any prefix, as
+  'prefix:name') to values, and a `"children"` array of nodes
+
+* a string, which is character content of the enclosing element
+
+* an object `{"cdata": "..."}` for raw character content
+
+* an object `{"comment": "..."}` for a comment (only written if
+  enabled with [Writer::set_include_comments])
+
+* an object `{"pi": "name", "arg": "...", "data": "..."}` for a
+  processing instruction (only written if enabled with
+  [Writer::set_include_pis]); `"arg"` and `"data"` are optional
+
+For example:
+
+```text
+[{"element": "svg", "uri": "http://www.w3.org/2000/svg",
+  "attributes": {"xmlns": "http://www.w3.org/2000/svg", "width": "10"},
+  "children": [{"element": "title", "children": ["A square"]}]}]
+```
+
+Namespaces are declared through 'xmlns' attributes, as in XML; when
+reading JSON the `"uri"` of an element is informational, and the
+element's namespace is determined from its prefix and the namespace
+declarations in scope.
+
+Character content in a JSON string is the plain text (rather than the
+HML escaped form of [ContentType::Interpretable] content events).
+
+!*/
+
+//a Imports
+use std::io::Write;
+
+use thiserror::Error;
+
+use crate::hml::escape;
+use crate::markup::{ContentType, Event};
+use crate::names::{Attributes, Name, NamespaceStack, Tag};
+use crate::{MarkupError, Posn, Span};
+
+//a Error
+//tp Error
+/// An error in reading a JSON document; each error carries the byte
+/// range within the JSON text that is in error
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The text is not valid JSON
+    #[error("JSON syntax error: {reason}")]
+    Syntax {
+        /// Why the text is invalid
+        reason: String,
+        /// Byte offset of the start of the error
+        start: usize,
+        /// Byte offset of the end of the error
+        end: usize,
+    },
+    /// The JSON is valid but does not represent a document
+    #[error("bad document structure: {reason}")]
+    Structure {
+        /// What was expected
+        reason: String,
+        /// Byte offset of the start of the JSON value in error
+        start: usize,
+        /// Byte offset of the end of the JSON value in error
+        end: usize,
+    },
+    /// A markup error such as an unmapped prefix
+    #[error("markup error {source}")]
+    Markup {
+        /// The error
+        source: MarkupError,
+        /// Byte offset of the start of the JSON value in error
+        start: usize,
+        /// Byte offset of the end of the JSON value in error
+        end: usize,
+    },
+    /// Attempt to read beyond the end of the document
+    #[error("Attempt to read beyond end of document")]
+    BeyondEndOfDocument,
+}
+
+//ip Error
+impl Error {
+    //fi syntax
+    fn syntax<T>(reason: &str, start: usize, end: usize) -> Result<T> {
+        Err(Self::Syntax {
+            reason: reason.to_string(),
+            start,
+            end,
+        })
+    }
+
+    //fi structure
+    fn structure<T>(reason: &str, node: &Node) -> Result<T> {
+        Err(Self::Structure {
+            reason: reason.to_string(),
+            start: node.start,
+            end: node.end,
+        })
+    }
+
+    //mp byte_range
+    /// Get the byte range of the text in error
+    pub fn byte_range(&self) -> std::ops::Range<usize> {
+        match self {
+            Self::Syntax { start, end, .. } => *start..*end,
+            Self::Structure { start, end, .. } => *start..*end,
+            Self::Markup { start, end, .. } => *start..*end,
+            Self::BeyondEndOfDocument => 0..0,
+        }
+    }
+}
+
+/// The result type for reading JSON documents
+pub type Result<T> = std::result::Result<T, Error>;
+
+//a JSON strings
+//fp quote
+/// Quote a string for JSON, escaping quotes, backslashes and control
+/// characters
+pub fn quote(s: &str) -> String {
+    let mut r = String::with_capacity(s.len() + 2);
+    r.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => r.push_str("\\\""),
+            '\\' => r.push_str("\\\\"),
+            '\n' => r.push_str("\\n"),
+            '\r' => r.push_str("\\r"),
+            '\t' => r.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => r.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => r.push(ch),
+        }
+    }
+    r.push('"');
+    r
+}
+
+//a JSON values
+//ti Value
+/// A JSON value
+#[derive(Debug)]
+enum Value {
+    Null,
+    Bool,
+    Number,
+    String(String),
+    Array(Vec<Node>),
+    Object(Vec<(String, Node)>),
+}
+
+//ti Node
+/// A JSON value with its byte range in the text
+#[derive(Debug)]
+struct Node {
+    value: Value,
+    start: usize,
+    end: usize,
+}
+
+//ii Node
+impl Node {
+    //mi get
+    /// Get a member of an object
+    fn get(&self, key: &str) -> Option<&Node> {
+        match &self.value {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    //mi take
+    /// Remove a member from an object
+    fn take(&mut self, key: &str) -> Option<Node> {
+        match &mut self.value {
+            Value::Object(members) => {
+                let n = members.iter().position(|(k, _)| k == key)?;
+                Some(members.remove(n).1)
+            }
+            _ => None,
+        }
+    }
+
+    //mi as_str
+    fn as_str(&self) -> Option<&str> {
+        match &self.value {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    //mi get_str
+    /// Get a member of an object that must be a string (if present)
+    fn get_str(&self, key: &str) -> Result<Option<&str>> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(n) => match n.as_str() {
+                Some(s) => Ok(Some(s)),
+                None => Error::structure(&format!("'{key}' must be a string"), n),
+            },
+        }
+    }
+}
+
+//ti JsonParser
+/// A parser of JSON text into [Node]s
+struct JsonParser<'a> {
+    text: &'a str,
+    ofs: usize,
+}
+
+//ii JsonParser
+impl<'a> JsonParser<'a> {
+    //mi skip_whitespace
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.ofs..];
+        self.ofs += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    //mi peek
+    fn peek(&self) -> Option<char> {
+        self.text[self.ofs..].chars().next()
+    }
+
+    //mi error_here
+    /// Return a syntax error for the character at the current offset
+    fn error_here<T>(&self, reason: &str) -> Result<T> {
+        let end = self.ofs + self.peek().map_or(0, |c| c.len_utf8());
+        Error::syntax(reason, self.ofs, end)
+    }
+
+    //mi expect
+    fn expect(&mut self, ch: char) -> Result<()> {
+        self.skip_whitespace();
+        if self.peek() == Some(ch) {
+            self.ofs += 1;
+            Ok(())
+        } else {
+            self.error_here(&format!("expected '{ch}'"))
+        }
+    }
+
+    //mi hex4
+    fn hex4(&mut self) -> Result<u32> {
+        let start = self.ofs;
+        let digits = self.text.get(start..start + 4).unwrap_or("");
+        match u32::from_str_radix(digits, 16) {
+            Ok(n) if digits.len() == 4 && !digits.starts_with('+') => {
+                self.ofs += 4;
+                Ok(n)
+            }
+            _ => Error::syntax("expected four hex digits", start - 2, start),
+        }
+    }
+
+    //mi string
+    /// Parse a string, given the offset is at the opening quote
+    fn string(&mut self) -> Result<String> {
+        let start = self.ofs;
+        self.ofs += 1;
+        let mut s = String::new();
+        loop {
+            let Some(ch) = self.peek() else {
+                return Error::syntax("unterminated string", start, self.ofs);
+            };
+            let ch_start = self.ofs;
+            self.ofs += ch.len_utf8();
+            match ch {
+                '"' => return Ok(s),
+                '\\' => {
+                    let Some(esc) = self.peek() else {
+                        return Error::syntax("unterminated string", start, self.ofs);
+                    };
+                    self.ofs += esc.len_utf8();
+                    match esc {
+                        '"' | '\\' | '/' => s.push(esc),
+                        'b' => s.push('\u{8}'),
+                        'f' => s.push('\u{c}'),
+                        'n' => s.push('\n'),
+                        'r' => s.push('\r'),
+                        't' => s.push('\t'),
+                        'u' => {
+                            let mut n = self.hex4()?;
+                            if (0xd800..0xdc00).contains(&n)
+                                && self.text[self.ofs..].starts_with("\\u")
+                            {
+                                self.ofs += 2;
+                                let low = self.hex4()?;
+                                n = 0x10000
+                                    + ((n - 0xd800) << 10)
+                                    + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            match char::from_u32(n) {
+                                Some(c) => s.push(c),
+                                None => {
+                                    return Error::syntax("bad unicode escape", ch_start, self.ofs)
+                                }
+                            }
+                        }
+                        _ => return Error::syntax("bad escape", ch_start, self.ofs),
+                    }
+                }
+                ch if (ch as u32) < 0x20 => {
+                    return Error::syntax("control character in string", ch_start, self.ofs);
+                }
+                ch => s.push(ch),
+            }
+        }
+    }
+
+    //mi value
+    /// Parse a value
+    fn value(&mut self) -> Result<Node> {
+        self.skip_whitespace();
+        let start = self.ofs;
+        let value = match self.peek() {
+            None => return Error::syntax("expected a value", start, start),
+            Some('"') => Value::String(self.string()?),
+            Some('[') => {
+                self.ofs += 1;
+                let mut elements = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(']') {
+                    self.ofs += 1;
+                } else {
+                    loop {
+                        elements.push(self.value()?);
+                        self.skip_whitespace();
+                        match self.peek() {
+                            Some(',') => self.ofs += 1,
+                            Some(']') => {
+                                self.ofs += 1;
+                                break;
+                            }
+                            _ => return self.error_here("expected ',' or ']'"),
+                        }
+                    }
+                }
+                Value::Array(elements)
+            }
+            Some('{') => {
+                self.ofs += 1;
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some('}') {
+                    self.ofs += 1;
+                } else {
+                    loop {
+                        self.skip_whitespace();
+                        if self.peek() != Some('"') {
+                            return self.error_here("expected a string key");
+                        }
+                        let key = self.string()?;
+                        self.expect(':')?;
+                        members.push((key, self.value()?));
+                        self.skip_whitespace();
+                        match self.peek() {
+                            Some(',') => self.ofs += 1,
+                            Some('}') => {
+                                self.ofs += 1;
+                                break;
+                            }
+                            _ => return self.error_here("expected ',' or '}'"),
+                        }
+                    }
+                }
+                Value::Object(members)
+            }
+            Some(_) => {
+                let rest = &self.text[start..];
+                let n = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || "+-.".contains(c)))
+                    .unwrap_or(rest.len());
+                self.ofs += n;
+                match &rest[..n] {
+                    "null" => Value::Null,
+                    "true" | "false" => Value::Bool,
+                    s if s.parse::<f64>().is_ok() => Value::Number,
+                    _ => return Error::syntax("expected a value", start, self.ofs.max(start + 1)),
+                }
+            }
+        };
+        Ok(Node {
+            value,
+            start,
+            end: self.ofs,
+        })
+    }
+
+    //fi parse
+    /// Parse a complete JSON text
+    fn parse(text: &'a str) -> Result<Node> {
+        let mut parser = Self { text, ofs: 0 };
+        let node = parser.value()?;
+        parser.skip_whitespace();
+        if parser.ofs < text.len() {
+            return Error::syntax("unexpected text after value", parser.ofs, text.len());
+        }
+        Ok(node)
+    }
+}
+
+//a Writer
+//tp Writer
+/// A writer of markup [Event]s as a JSON document
+#[derive(Debug)]
+pub struct Writer<W: Write> {
+    writer: W,
+    include_comments: bool,
+    include_pis: bool,
+    keep_whitespace: bool,
+    /// For each open array (the document and each element's
+    /// children), true if it has no entries yet
+    first: Vec<bool>,
+}
+
+//ip Writer
+impl<W: Write> Writer<W> {
+    //fp new
+    /// Create a new [Writer] that writes to `writer`
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            include_comments: false,
+            include_pis: false,
+            keep_whitespace: true,
+            first: Vec::new(),
+        }
+    }
+
+    //cp set_include_comments
+    /// Set whether comments are written; by default they are not
+    pub fn set_include_comments(mut self, include_comments: bool) -> Self {
+        self.include_comments = include_comments;
+        self
+    }
+
+    //cp set_include_pis
+    /// Set whether processing instructions are written; by default
+    /// they are not
+    pub fn set_include_pis(mut self, include_pis: bool) -> Self {
+        self.include_pis = include_pis;
+        self
+    }
+
+    //cp set_keep_whitespace
+    /// Set whether whitespace content is written (the default), or
+    /// dropped - for example when it is just the formatting between
+    /// the elements of an XML document
+    pub fn set_keep_whitespace(mut self, keep_whitespace: bool) -> Self {
+        self.keep_whitespace = keep_whitespace;
+        self
+    }
+
+    //mp into_inner
+    /// Consume the [Writer], returning the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    //mi separator
+    /// Write a separator before a new entry in the current array
+    fn separator(&mut self) -> std::io::Result<()> {
+        match self.first.last_mut() {
+            Some(first) if *first => {
+                *first = false;
+                Ok(())
+            }
+            Some(_) => self.writer.write_all(b", "),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "event outside of the document",
+            )),
+        }
+    }
+
+    //mp write_event
+    /// Write an [Event] to the JSON document
+    pub fn write_event<P: Posn>(
+        &mut self,
+        ns: &NamespaceStack,
+        event: &Event<P>,
+    ) -> std::io::Result<()> {
+        match event {
+            Event::StartDocument { .. } => {
+                self.first.push(true);
+                self.writer.write_all(b"[")
+            }
+            Event::EndDocument { .. } => {
+                self.first.pop();
+                self.writer.write_all(b"]\n")?;
+                self.writer.flush()
+            }
+            Event::StartElement { tag, .. } => {
+                self.separator()?;
+                let name = &tag.name;
+                write!(
+                    self.writer,
+                    "{{\"element\": {}",
+                    quote(ns.name_str(name.name))
+                )?;
+                if name.has_prefix() {
+                    write!(
+                        self.writer,
+                        ", \"prefix\": {}",
+                        quote(ns.prefix_str(name.prefix))
+                    )?;
+                }
+                let uri = ns.uri_str(name.uri);
+                if !uri.is_empty() {
+                    write!(self.writer, ", \"uri\": {}", quote(uri))?;
+                }
+                if !tag.attributes.is_empty() {
+                    self.writer.write_all(b", \"attributes\": {")?;
+                    for (i, a) in tag.attributes.iter().enumerate() {
+                        let name = if a.is_default_namespace(ns) {
+                            "xmlns".to_string()
+                        } else {
+                            a.name.to_string(ns)
+                        };
+                        let sep = if i == 0 { "" } else { ", " };
+                        write!(self.writer, "{}{}: {}", sep, quote(&name), quote(&a.value))?;
+                    }
+                    self.writer.write_all(b"}")?;
+                }
+                self.first.push(true);
+                self.writer.write_all(b", \"children\": [")
+            }
+            Event::EndElement { .. } => {
+                self.first.pop();
+                self.writer.write_all(b"]}")
+            }
+            Event::Content { ctype, data, .. } => {
+                if *ctype == ContentType::Whitespace && !self.keep_whitespace {
+                    return Ok(());
+                }
+                self.separator()?;
+                match ctype {
+                    ContentType::Raw => write!(self.writer, "{{\"cdata\": {}}}", quote(data)),
+                    ContentType::Whitespace => self.writer.write_all(quote(data).as_bytes()),
+                    ContentType::Interpretable => {
+                        let text = escape::Escapable::new(data)
+                            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                        self.writer.write_all(quote(text.as_ref()).as_bytes())
+                    }
+                }
+            }
+            Event::ProcessingInstruction {
+                name, arg, data, ..
+            } => {
+                if !self.include_pis {
+                    return Ok(());
+                }
+                self.separator()?;
+                write!(self.writer, "{{\"pi\": {}", quote(ns.name_str(*name)))?;
+                if let Some(arg) = arg {
+                    write!(self.writer, ", \"arg\": {}", quote(arg))?;
+                }
+                if let Some(data) = data {
+                    write!(self.writer, ", \"data\": {}", quote(data))?;
+                }
+                self.writer.write_all(b"}")
+            }
+            Event::Comment { data, .. } => {
+                if !self.include_comments {
+                    return Ok(());
+                }
+                self.separator()?;
+                write!(self.writer, "{{\"comment\": {}}}", quote(data))
+            }
+        }
+    }
+}
+
+//a Reader
+//ti Frame
+/// An element whose children are being read
+struct Frame {
+    name: Name,
+    end: usize,
+    children: std::vec::IntoIter<Node>,
+}
+
+//tp Reader
+/// A reader of markup [Event]s from a JSON document
+///
+/// The spans of the events are byte offsets within the JSON text
+pub struct Reader {
+    len: usize,
+    started: bool,
+    finished: bool,
+    top: std::vec::IntoIter<Node>,
+    stack: Vec<Frame>,
+}
+
+//ip Reader
+impl Reader {
+    //fp new
+    /// Create a new [Reader] for a JSON text, returning an error if
+    /// it is not valid JSON or is not an array
+    pub fn new(text: &str) -> Result<Self> {
+        let node = JsonParser::parse(text)?;
+        let Value::Array(nodes) = node.value else {
+            return Error::structure("a document must be an array of nodes", &node);
+        };
+        Ok(Self {
+            len: text.len(),
+            started: false,
+            finished: false,
+            top: nodes.into_iter(),
+            stack: Vec::new(),
+        })
+    }
+
+    //mi markup
+    /// Map a markup result to a [Result] for the JSON node
+    fn markup<T>(result: crate::MarkupResult<T>, node: &Node) -> Result<T> {
+        result.map_err(|source| Error::Markup {
+            source,
+            start: node.start,
+            end: node.end,
+        })
+    }
+
+    //mi split_name
+    /// Split a 'prefix:name' string
+    fn split_name(name: &str) -> (&str, &str) {
+        name.split_once(':').unwrap_or(("", name))
+    }
+
+    //mi element
+    /// Start an element from its JSON node
+    fn element(&mut self, ns: &mut NamespaceStack, mut node: Node) -> Result<Event<usize>> {
+        let span = Span::new(node.start, node.end);
+        let children = match node.take("children") {
+            None => Vec::new(),
+            Some(Node {
+                value: Value::Array(children),
+                ..
+            }) => children,
+            Some(n) => return Error::structure("'children' must be an array", &n),
+        };
+        let Some(name) = node.get_str("element")? else {
+            return Error::structure("an element must have an 'element' name", &node);
+        };
+        let (prefix, name) = match node.get_str("prefix")? {
+            Some(prefix) => (prefix, name),
+            None => Self::split_name(name),
+        };
+        ns.push_frame();
+        let mut attributes = Attributes::default();
+        if let Some(attrs) = node.get("attributes") {
+            let Value::Object(members) = &attrs.value else {
+                return Error::structure("'attributes' must be an object", attrs);
+            };
+            for (key, value) in members {
+                let Some(v) = value.as_str() else {
+                    return Error::structure("attribute values must be strings", value);
+                };
+                let (a_prefix, a_name) = Self::split_name(key);
+                Self::markup(attributes.add(ns, a_prefix, a_name, v.to_string()), value)?;
+            }
+        }
+        let tag = Self::markup(Tag::new(ns, prefix, name, attributes), &node)?;
+        self.stack.push(Frame {
+            name: tag.name,
+            end: node.end,
+            children: children.into_iter(),
+        });
+        Ok(Event::start_element(span, tag))
+    }
+
+    //mi node
+    /// Get the event for a node of the document
+    fn node(&mut self, ns: &mut NamespaceStack, node: Node) -> Result<Event<usize>> {
+        let span = Span::new(node.start, node.end);
+        if let Value::String(s) = &node.value {
+            let data = escape::escape(s, true).into_owned();
+            return Ok(Event::content_int(span, data));
+        }
+        if !matches!(node.value, Value::Object(_)) {
+            return Error::structure("a node must be a string or an object", &node);
+        }
+        if node.get("element").is_some() {
+            self.element(ns, node)
+        } else if let Some(data) = node.get_str("cdata")? {
+            Ok(Event::content_raw(span, data.to_string()))
+        } else if let Some(data) = node.get_str("comment")? {
+            let lengths = data.split('\n').map(|l| l.len()).collect();
+            Ok(Event::comment(span, data.to_string(), lengths))
+        } else if let Some(name) = node.get_str("pi")? {
+            let name = ns.add_name(name);
+            let arg = node.get_str("arg")?.map(|s| s.to_string());
+            let data = node.get_str("data")?.map(|s| s.to_string());
+            Ok(Event::ProcessingInstruction {
+                span,
+                name,
+                arg,
+                data,
+            })
+        } else {
+            Error::structure(
+                "a node object must have 'element', 'cdata', 'comment' or 'pi'",
+                &node,
+            )
+        }
+    }
+
+    //mp next_event
+    /// Get the next [Event] of the document, using the
+    /// [NamespaceStack] to resolve names
+    pub fn next_event(&mut self, ns: &mut NamespaceStack) -> Result<Event<usize>> {
+        if !self.started {
+            self.started = true;
+            return Ok(Event::start_document(Span::new(0, 0), 100));
+        }
+        if let Some(frame) = self.stack.last_mut() {
+            if let Some(node) = frame.children.next() {
+                return self.node(ns, node);
+            }
+            let frame = self.stack.pop().unwrap();
+            ns.pop_frame();
+            let span = Span::new(frame.end, frame.end);
+            return Ok(Event::end_element(span, frame.name));
+        }
+        if let Some(node) = self.top.next() {
+            return self.node(ns, node);
+        }
+        if self.finished {
+            return Err(Error::BeyondEndOfDocument);
+        }
+        self.finished = true;
+        Ok(Event::end_document(Span::new(self.len, self.len)))
+    }
+}
+
+//a Tests
+#[cfg(test)]
+mod test {
+    use super::{quote, Error, Reader, Writer};
+    use crate::markup::Event;
+    use crate::names::{Namespace, NamespaceStack};
+
+    //fi round_trip
+    /// Read a JSON document and write it out again
+    fn round_trip(json: &str, comments: bool) -> String {
+        let mut namespace = Namespace::new(true);
+        let mut ns = NamespaceStack::new(&mut namespace);
+        let mut reader = Reader::new(json).unwrap();
+        let mut writer = Writer::new(Vec::new())
+            .set_include_comments(comments)
+            .set_include_pis(comments);
+        loop {
+            let event: Event<usize> = reader.next_event(&mut ns).unwrap();
+            writer.write_event(&ns, &event).unwrap();
+            if event.is_end_document() {
+                break;
+            }
+        }
+        assert!(matches!(
+            reader.next_event(&mut ns),
+            Err(Error::BeyondEndOfDocument)
+        ));
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("plain"), r#""plain""#);
+        assert_eq!(quote("a\"b\\c\nd\u{1}"), r#""a\"b\\c\nd\u0001""#);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let json = r#"[{"comment": " c "}, {"element": "svg", "uri": "urn:svg", "attributes": {"xmlns": "urn:svg", "xmlns:x": "urn:x", "x:id": "1"}, "children": [{"element": "title", "prefix": "x", "uri": "urn:x", "children": ["A \"square\"\n☺", {"cdata": "raw \\"}]}, {"pi": "style", "data": "d"}, {"element": "rect", "uri": "urn:svg", "children": []}]}]
+"#;
+        assert_eq!(round_trip(json, true), json);
+        assert_eq!(
+            round_trip(json, false),
+            json.replace(r#"{"comment": " c "}, "#, "")
+                .replace(r#", {"pi": "style", "data": "d"}"#, "")
+        );
+        // Whitespace, qualified element names and missing children are accepted
+        assert_eq!(
+            round_trip(" [ {\"element\" : \"x:a\", \"attributes\": {\"xmlns:x\": \"urn:x\"}} ] ", false),
+            "[{\"element\": \"a\", \"prefix\": \"x\", \"uri\": \"urn:x\", \"attributes\": {\"xmlns:x\": \"urn:x\"}, \"children\": []}]\n"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut namespace = Namespace::new(true);
+        let mut ns = NamespaceStack::new(&mut namespace);
+        for (json, bad) in [
+            (r#"[{"element": "a""#, ""),
+            (r#"[{"element": "a"} x]"#, "x"),
+            (r#"[{"element": "a", 1}]"#, "1"),
+            (r#"["\q"]"#, r"\q"),
+            (r#"{"element": "a"}"#, r#"{"element": "a"}"#),
+        ] {
+            let err = Reader::new(json).err().unwrap();
+            assert_eq!(&json[err.byte_range()], bad, "{json}: {err}");
+        }
+        for (json, bad) in [
+            (r#"[{"element": "a", "children": [1]}]"#, "1"),
+            (r#"[{"element": "a", "attributes": {"b": 2}}]"#, "2"),
+            (r#"[{"element": "p:a"}]"#, r#"{"element": "p:a"}"#),
+            (r#"[{"other": "a"}]"#, r#"{"other": "a"}"#),
+        ] {
+            let mut reader = Reader::new(json).unwrap();
+            let err = loop {
+                if let Err(e) = reader.next_event(&mut ns) {
+                    break e;
+                }
+            };
+            assert_eq!(&json[err.byte_range()], bad, "{json}: {err}");
+        }
+    }
+}
@@ -55,8 +55,8 @@ element that is Unicode text. In HML this must be presented as a
 quoted string: quoted strings can simply be "<text>"; standard Rust
 escapes '\n', '\x52' '\u{1234D};', '\\' will be expanded as per-Rust;
 a raw string can be used with r"<text>", which has no expansion
-performed. These single-quoted strings *must not* include any newlines
-- i.e. they must appear on a single line in the HML
+performed. These single-quoted strings *must not* include any
+newlines; i.e. they must appear on a single line in the HML
 document. Multi-line content can be generated using #"..."#,
 #"""..."""#, or raw variants r#"..."#.
 
@@ -151,7 +151,7 @@ Misc[N] := <Comment> | <ProcessingInstruction[N]> | <Declaration[N]>
 
 Comment := ';' (<Char> - <Newline>)*
 
-ProcessingInstruction[N] := '#'{N} '?' <Name> [<QuotedString>]
+ProcessingInstruction[N] := '#'{N} '?' <Name> [<Name>] [<QuotedString>]
 
 Declaration[N] := '#'{N} '!'<Type> <Name> [<QuotedString>] [DeclarationContent[N+1]*
 
@@ -175,6 +175,23 @@ QuotedString :=   '"'     <EscapedContent without newlines> '"'
 
 ```
 
+The HML parser enforces the 'Document' production only when it is
+set to strict mode (hml_reader::DocumentMode::Strict); by default it
+parses a fragment, which may have any number of top-level elements
+and content outside any element.
+
+The name argument of a processing instruction (such as 'NAME' in
+'#?if NAME') is kept apart from the content of its quoted string, as
+the 'arg' and 'data' of the markup::Event; XML, which has no such
+argument, sees them as one data string separated by a space.
+
+A processing instruction such as '##?include "regs.hml"' may be
+expanded in place, at its depth, to the content of another document,
+by reading events through an hml_reader::Includer. Conditional
+sections ('#?if NAME', '#?else', '#?endif'), variable definitions
+('#?define NAME "value"') and '${NAME}' substitutions are applied by
+passing the events through a markup::Preprocessor.
+
 ## HML quoted string notes
 
 A raw quoted string is not parsed for escape sequences.
@@ -227,3 +244,21 @@ pub mod markup;
 
 // Expose hml_reader::{Parser, parse_fns}
 pub mod hml_reader;
+
+// Expose sources::{Sources, FileId, FilePosn, SourceLoader}
+pub mod sources;
+
+// Expose xml_reader::{Parser, parse_fns}
+pub mod xml_reader;
+
+// Expose hml_writer::Writer
+pub mod hml_writer;
+
+// Expose html_writer::Writer
+pub mod html_writer;
+
+// Expose json::{Reader, Writer}
+pub mod json;
+
+// Expose xml_writer::Writer
+pub mod xml_writer;
@@ -12,6 +12,14 @@ streams.
 
 //a Imports
 mod event;
+mod normalize;
+mod preprocess;
+mod spans;
+mod validate;
 
 //a Exports
-pub use event::{ContentType, Event, EventType};
+pub use event::{ContentOptions, ContentType, Event, EventType};
+pub use normalize::NamespaceNormalizer;
+pub use preprocess::Preprocessor;
+pub use spans::{AttributeSpan, TagSpans};
+pub use validate::Validator;
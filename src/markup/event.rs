@@ -1,7 +1,13 @@
 //a Imports
+use std::str::FromStr;
+use std::time::Duration;
+
 use lexer_rs::{PosnInCharStream, StreamCharSpan};
 
-use crate::names::{NSNameId, Name, NamespaceStack, Tag};
+use super::TagSpans;
+use crate::names::value;
+use crate::names::{Attribute, NSNameId, Name, NamespaceStack, Tag};
+use crate::{HmlError, HmlResult, MarkupError, MarkupResult};
 
 //a Content
 //tp ContentType
@@ -31,6 +37,77 @@ pub enum ContentType {
     Whitespace,
 }
 
+//tp ContentOptions
+/// Options for how each [ContentType] of content is written as XML
+///
+/// By default raw content is written as CDATA sections, whitespace
+/// is written as is, and interpretable content (which is in HML
+/// escaped form) is unescaped before it is escaped for XML
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentOptions {
+    raw_as_cdata: bool,
+    keep_whitespace: bool,
+    unescape: bool,
+}
+
+//ip Default for ContentOptions
+impl Default for ContentOptions {
+    fn default() -> Self {
+        Self {
+            raw_as_cdata: true,
+            keep_whitespace: true,
+            unescape: true,
+        }
+    }
+}
+
+//ip ContentOptions
+impl ContentOptions {
+    //cp set_raw_as_cdata
+    /// Set whether raw content is written as a CDATA section (the
+    /// default), or as escaped character data
+    pub fn set_raw_as_cdata(mut self, raw_as_cdata: bool) -> Self {
+        self.raw_as_cdata = raw_as_cdata;
+        self
+    }
+
+    //cp set_keep_whitespace
+    /// Set whether whitespace content is written (the default), or
+    /// dropped
+    pub fn set_keep_whitespace(mut self, keep_whitespace: bool) -> Self {
+        self.keep_whitespace = keep_whitespace;
+        self
+    }
+
+    //cp set_unescape
+    /// Set whether interpretable content has its HML escape sequences
+    /// resolved before being written (the default), or is written
+    /// with them as they are
+    pub fn set_unescape(mut self, unescape: bool) -> Self {
+        self.unescape = unescape;
+        self
+    }
+
+    //ap raw_as_cdata
+    /// Return true if raw content is written as a CDATA section
+    pub fn raw_as_cdata(&self) -> bool {
+        self.raw_as_cdata
+    }
+
+    //ap keep_whitespace
+    /// Return true if whitespace content is written
+    pub fn keep_whitespace(&self) -> bool {
+        self.keep_whitespace
+    }
+
+    //ap unescape
+    /// Return true if interpretable content is unescaped before it
+    /// is written
+    pub fn unescape(&self) -> bool {
+        self.unescape
+    }
+}
+
 //a Event
 //tp Event
 /// A markup event
@@ -45,7 +122,8 @@ pub enum EventType {
     StartElement,
     /// The end of an element
     EndElement,
-    /// One [String] of content for an element; will always be within an element
+    /// One [String] of content for an element; this is always within
+    /// an element, except in a document fragment
     Content,
     /// A processing instruction
     ProcessingInstruction,
@@ -84,6 +162,9 @@ where
         span: StreamCharSpan<P>,
         /// The actual tag (prefix, URI, name, attributes)
         tag: Tag,
+        /// The spans of the tag name and attributes, if the source
+        /// of the event provides them
+        spans: Option<TagSpans<P>>,
     },
 
     /// Denotes an end of an XML element.
@@ -111,6 +192,10 @@ where
         span: StreamCharSpan<P>,
         /// A NSNameId within the namespace that is the name of the processing instruction
         name: NSNameId,
+        /// The name argument of an HML processing instruction, if
+        /// any (such as 'NAME' in '#?if NAME'); XML processing
+        /// instructions have none
+        arg: Option<String>,
         /// An optional value for the processing instruction
         data: Option<String>,
     },
@@ -148,7 +233,22 @@ where
     //fp start_element
     /// Create a StartElement event with a given [Tag]
     pub fn start_element(span: StreamCharSpan<P>, tag: Tag) -> Self {
-        Self::StartElement { span, tag }
+        Self::StartElement {
+            span,
+            tag,
+            spans: None,
+        }
+    }
+
+    //fp start_element_with_spans
+    /// Create a StartElement event with a given [Tag] and the spans
+    /// of its name and attributes
+    pub fn start_element_with_spans(span: StreamCharSpan<P>, tag: Tag, spans: TagSpans<P>) -> Self {
+        Self::StartElement {
+            span,
+            tag,
+            spans: Some(spans),
+        }
     }
 
     //fp end_element
@@ -239,6 +339,26 @@ where
         }
     }
 
+    //mp tag_spans
+    /// Return the [TagSpans] of a StartElement event, if it has them
+    pub fn tag_spans(&self) -> Option<&TagSpans<P>> {
+        match self {
+            Self::StartElement { spans, .. } => spans.as_ref(),
+            _ => None,
+        }
+    }
+
+    //mp attribute_span
+    /// Get the span of the value of the n'th attribute of a
+    /// StartElement event; if the event does not have [TagSpans]
+    /// then this is the span of the event
+    pub fn attribute_span(&self, n: usize) -> &StreamCharSpan<P> {
+        self.tag_spans()
+            .and_then(|s| s.attribute(n))
+            .map(|a| &a.value)
+            .unwrap_or_else(|| self.borrow_span())
+    }
+
     //mp as_end_element
     /// Return Some(Name) if the [Event] is an EndElement
     /// event; else return None
@@ -259,6 +379,23 @@ where
         }
     }
 
+    //mp pi_data
+    /// Return the data of a processing instruction as XML would
+    /// represent it - its argument and data separated by a space if
+    /// both are present; return None for other events, or if the
+    /// processing instruction has neither
+    pub fn pi_data(&self) -> Option<String> {
+        match self {
+            Self::ProcessingInstruction {
+                arg: Some(arg),
+                data: Some(data),
+                ..
+            } => Some(format!("{arg} {data}")),
+            Self::ProcessingInstruction { arg, data, .. } => arg.clone().or_else(|| data.clone()),
+            _ => None,
+        }
+    }
+
     //mp is_start_document
     /// Return true if the Event is a StartDocument event
     pub fn is_start_document(&self) -> bool {
@@ -270,6 +407,139 @@ where
     pub fn is_end_document(&self) -> bool {
         matches!(self, Self::EndDocument { .. })
     }
+
+    //mp with_attribute
+    /// If the [Event] is a StartElement with an attribute of the
+    /// given local name then invoke `f` on it and return Some of the
+    /// result; if it is not a StartElement, or it has no such
+    /// attribute, then return Ok(None)
+    ///
+    /// An error from `f` is returned as an [HmlError::MarkupError]
+    /// with the span of the attribute value (see
+    /// [Self::attribute_span]); so is an error if more than one
+    /// attribute has the local name (see
+    /// [crate::names::Attributes::position_unique_local]), with the
+    /// span of the start element
+    pub fn with_attribute<T, F>(
+        &self,
+        ns: &NamespaceStack,
+        name: NSNameId,
+        f: F,
+    ) -> HmlResult<Option<T>, P>
+    where
+        F: FnOnce(&Attribute) -> MarkupResult<T>,
+    {
+        let Self::StartElement { tag, span, .. } = self else {
+            return Ok(None);
+        };
+        let position = tag.attributes.position_unique_local(ns, name);
+        let Some(n) = HmlError::map_markup_error(position, span)? else {
+            return Ok(None);
+        };
+        let attr = &tag.attributes.attributes()[n];
+        HmlError::map_markup_error(f(attr), self.attribute_span(n)).map(Some)
+    }
+
+    //mp attribute_value
+    /// Parse the value of an attribute of a StartElement using
+    /// [FromStr] (see [Attribute::parse_value])
+    pub fn attribute_value<T>(&self, ns: &NamespaceStack, name: NSNameId) -> HmlResult<Option<T>, P>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.with_attribute(ns, name, |a| a.parse_value(ns))
+    }
+
+    //mp attribute_bool
+    /// Get the value of an attribute of a StartElement as a boolean
+    /// (see [Attribute::as_bool])
+    pub fn attribute_bool(
+        &self,
+        ns: &NamespaceStack,
+        name: NSNameId,
+    ) -> HmlResult<Option<bool>, P> {
+        self.with_attribute(ns, name, |a| a.as_bool(ns))
+    }
+
+    //mp attribute_list
+    /// Parse the value of an attribute of a StartElement as a
+    /// whitespace-separated list (see [Attribute::parse_list])
+    pub fn attribute_list<T>(
+        &self,
+        ns: &NamespaceStack,
+        name: NSNameId,
+    ) -> HmlResult<Option<Vec<T>>, P>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.with_attribute(ns, name, |a| a.parse_list(ns))
+    }
+
+    //mp attribute_duration
+    /// Get the value of an attribute of a StartElement as a
+    /// [Duration] (see [Attribute::as_duration])
+    pub fn attribute_duration(
+        &self,
+        ns: &NamespaceStack,
+        name: NSNameId,
+    ) -> HmlResult<Option<Duration>, P> {
+        self.with_attribute(ns, name, |a| a.as_duration(ns))
+    }
+
+    //mp with_content
+    /// If the [Event] is Content then invoke `f` on its data and
+    /// return Some of the result, else return Ok(None)
+    ///
+    /// A failure from `f` is returned as an [HmlError::MarkupError]
+    /// with the span of the content
+    pub fn with_content<T, F>(&self, f: F) -> HmlResult<Option<T>, P>
+    where
+        F: FnOnce(&str) -> Result<T, String>,
+    {
+        let Self::Content { span, data, .. } = self else {
+            return Ok(None);
+        };
+        let result = f(data).map_err(|reason| MarkupError::bad_content(data, reason));
+        HmlError::map_markup_error(result, span).map(Some)
+    }
+
+    //mp content_value
+    /// Parse the data of a Content event using [FromStr], ignoring
+    /// leading and trailing whitespace
+    pub fn content_value<T>(&self) -> HmlResult<Option<T>, P>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.with_content(value::parse_value)
+    }
+
+    //mp content_bool
+    /// Get the data of a Content event as a boolean (as per
+    /// [Attribute::as_bool])
+    pub fn content_bool(&self) -> HmlResult<Option<bool>, P> {
+        self.with_content(value::parse_bool)
+    }
+
+    //mp content_list
+    /// Parse the data of a Content event as a whitespace-separated
+    /// list using [FromStr]
+    pub fn content_list<T>(&self) -> HmlResult<Option<Vec<T>>, P>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.with_content(value::parse_list)
+    }
+
+    //mp content_duration
+    /// Get the data of a Content event as a [Duration] (as per
+    /// [Attribute::as_duration])
+    pub fn content_duration(&self) -> HmlResult<Option<Duration>, P> {
+        self.with_content(value::parse_duration)
+    }
 }
 
 //a If xml_rs is included
@@ -279,14 +549,117 @@ impl<P> Event<P>
 where
     P: PosnInCharStream,
 {
+    //fp from_xml_reader
+    /// Create an [Event] from an [xml::reader::XmlEvent], resolving
+    /// names using (and updating) the [NamespaceStack]
+    ///
+    /// A start element pushes a namespace frame, and its end element
+    /// pops it; namespace mappings of the XML element that are not
+    /// already in scope are added as 'xmlns' attributes if the
+    /// namespace uses them, or directly to the stack otherwise.
+    ///
+    /// Character data is converted to HML escaped form, so that it is
+    /// [ContentType::Interpretable]; CDATA sections become raw
+    /// content.
+    pub fn from_xml_reader(
+        ns_stack: &mut NamespaceStack,
+        span: StreamCharSpan<P>,
+        event: xml::reader::XmlEvent,
+    ) -> MarkupResult<Self> {
+        use xml::reader::XmlEvent;
+        match event {
+            XmlEvent::StartDocument { version, .. } => {
+                let version = match version {
+                    xml::common::XmlVersion::Version10 => 100,
+                    _ => 110,
+                };
+                Ok(Self::start_document(span, version))
+            }
+            XmlEvent::EndDocument => Ok(Self::end_document(span)),
+            XmlEvent::StartElement {
+                name,
+                attributes,
+                namespace,
+            } => {
+                ns_stack.push_frame();
+                let mut tag_attributes = crate::names::Attributes::default();
+                for (prefix, uri) in namespace.0.iter() {
+                    if prefix == "xml" || prefix == "xmlns" {
+                        continue;
+                    }
+                    let in_scope = ns_stack
+                        .find_prefix_id(prefix)
+                        .and_then(|p| ns_stack.find_mapping(p))
+                        .is_some_and(|u| ns_stack.uri_str(u) == uri);
+                    if in_scope {
+                        continue;
+                    }
+                    if ns_stack.uses_xmlns() {
+                        let (prefix, name) = if prefix.is_empty() {
+                            ("", "xmlns")
+                        } else {
+                            ("xmlns", prefix.as_str())
+                        };
+                        tag_attributes.add(ns_stack, prefix, name, uri.to_string())?;
+                    } else {
+                        ns_stack.add_ns(prefix, uri);
+                    }
+                }
+                for a in attributes {
+                    let prefix = a.name.prefix.as_deref().unwrap_or("");
+                    tag_attributes.add(ns_stack, prefix, &a.name.local_name, a.value)?;
+                }
+                let prefix = name.prefix.as_deref().unwrap_or("");
+                let tag = Tag::new(ns_stack, prefix, &name.local_name, tag_attributes)?;
+                Ok(Self::start_element(span, tag))
+            }
+            XmlEvent::EndElement { name } => {
+                let prefix = name.prefix.as_deref().unwrap_or("");
+                let name = Name::new(ns_stack, prefix, &name.local_name)?;
+                ns_stack.pop_frame();
+                Ok(Self::end_element(span, name))
+            }
+            XmlEvent::ProcessingInstruction { name, data } => {
+                let name = ns_stack.add_name(&name);
+                Ok(Self::ProcessingInstruction {
+                    span,
+                    name,
+                    arg: None,
+                    data,
+                })
+            }
+            XmlEvent::Comment(data) => {
+                let lengths = data.split('\n').map(|l| l.len()).collect();
+                Ok(Self::comment(span, data, lengths))
+            }
+            XmlEvent::CData(data) => Ok(Self::content_raw(span, data)),
+            XmlEvent::Characters(data) => {
+                let data = crate::hml::escape::escape(&data, true).into_owned();
+                Ok(Self::content_int(span, data))
+            }
+            XmlEvent::Whitespace(data) => Ok(Self::content_ws(span, data)),
+        }
+    }
+
     //mp as_xml_writer
     /// Get an [xml::writer::XmlEvent<'a>] from this Name
+    ///
+    /// Raw content becomes a CDATA section; other content is
+    /// provided as characters as it is, so interpretable content is
+    /// left in HML escaped form, as the event borrows the data. Use
+    /// [Event::write_xml] to honour [ContentOptions].
+    ///
+    /// An EndDocument event has no equivalent, and yields None.
+    ///
+    /// A processing instruction with both an argument and data cannot
+    /// be borrowed as one XML data string, and is an error; such an
+    /// event can only be written with [Event::write_xml].
     pub fn as_xml_writer<'a>(
         &'a self,
         ns: &'a NamespaceStack,
-    ) -> Option<xml::writer::XmlEvent<'a>> {
+    ) -> xml::writer::Result<Option<xml::writer::XmlEvent<'a>>> {
         use Event::*;
-        match self {
+        Ok(match self {
             StartDocument { version, .. } => {
                 let version = if *version == 100 {
                     xml::common::XmlVersion::Version10
@@ -303,22 +676,73 @@ where
                 let name = tag.name.as_xml_name(ns);
                 let mut x = xml::writer::XmlEvent::start_element(name);
                 for a in tag.attributes.attributes() {
-                    let attr_name = a.name.as_xml_name(ns);
+                    let attr_name = if a.is_default_namespace(ns) {
+                        xml::name::Name::local("xmlns")
+                    } else {
+                        a.name.as_xml_name(ns)
+                    };
                     x = x.attr(attr_name, &a.value);
                 }
                 Some(x.into())
             }
             EndElement { .. } => Some(xml::writer::XmlEvent::end_element().into()),
             EndDocument { .. } => None,
+            Content {
+                ctype: ContentType::Raw,
+                data,
+                ..
+            } => Some(xml::writer::XmlEvent::cdata(data)),
             Content { data, .. } => Some(xml::writer::XmlEvent::characters(data)),
-            ProcessingInstruction { name, data, .. } => {
-                let name = ns.name_str(*name);
-                Some(xml::writer::XmlEvent::processing_instruction(
-                    name,
-                    data.as_ref().map(|x| x.as_str()),
-                ))
-            }
+            ProcessingInstruction {
+                arg: Some(_),
+                data: Some(_),
+                ..
+            } => return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "a processing instruction with an argument and data must be written with write_xml",
+            )
+            .into()),
+            ProcessingInstruction {
+                name, arg, data, ..
+            } => Some(xml::writer::XmlEvent::processing_instruction(
+                ns.name_str(*name),
+                arg.as_deref().or(data.as_deref()),
+            )),
             Comment { data, .. } => Some(xml::writer::XmlEvent::comment(data)),
+        })
+    }
+
+    //mp write_xml
+    /// Write this event to an [xml::writer::EventWriter], with
+    /// content written as the [ContentOptions] require
+    pub fn write_xml<W: std::io::Write>(
+        &self,
+        ns: &NamespaceStack,
+        options: &ContentOptions,
+        writer: &mut xml::writer::EventWriter<W>,
+    ) -> xml::writer::Result<()> {
+        use xml::writer::XmlEvent;
+        if let Event::ProcessingInstruction { name, .. } = self {
+            let data = self.pi_data();
+            let name = ns.name_str(*name);
+            return writer.write(XmlEvent::processing_instruction(name, data.as_deref()));
+        }
+        let Event::Content { ctype, data, .. } = self else {
+            return match self.as_xml_writer(ns)? {
+                Some(x) => writer.write(x),
+                None => Ok(()),
+            };
+        };
+        match ctype {
+            ContentType::Raw if options.raw_as_cdata() => writer.write(XmlEvent::cdata(data)),
+            ContentType::Whitespace if !options.keep_whitespace() => Ok(()),
+            ContentType::Interpretable if options.unescape() => {
+                let text = crate::hml::escape::Escapable::new(data).map_err(|e| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())
+                })?;
+                writer.write(XmlEvent::characters(text.as_ref()))
+            }
+            _ => writer.write(XmlEvent::characters(data)),
         }
     }
 
@@ -362,11 +786,11 @@ where
             }),
             EndDocument { .. } => Some(xml::reader::XmlEvent::EndDocument),
             Content { data, .. } => Some(xml::reader::XmlEvent::Characters(data.clone())),
-            ProcessingInstruction { name, data, .. } => {
+            ProcessingInstruction { name, .. } => {
                 let name = ns.name_str(*name);
                 Some(xml::reader::XmlEvent::ProcessingInstruction {
                     name: name.to_string(),
-                    data: data.clone(),
+                    data: self.pi_data(),
                 })
             }
             Comment { data, .. } => Some(xml::reader::XmlEvent::Comment(data.to_string())),
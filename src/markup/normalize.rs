@@ -0,0 +1,521 @@
+//a Imports
+use std::collections::{HashMap, HashSet};
+
+use lexer_rs::PosnInCharStream;
+
+use super::{AttributeSpan, Event};
+use crate::names::{Attribute, Attributes, NSNameId, NSPrefixId, NSUriId, Name, NamespaceStack};
+use crate::{HmlError, HmlResult, MarkupError, MarkupResult};
+
+//a Constants
+/// The URI that all namespace declaration attributes belong to
+const XMLNS_URI: &str = "http://www.w3.org/2000/xmlns/";
+
+//a Internal types
+//ti XmlnsIds
+/// The ids within the namespace that are required to recognise and
+/// create namespace declarations
+struct XmlnsIds {
+    /// The 'xmlns' prefix
+    prefix: NSPrefixId,
+    /// The 'xml' prefix, which never requires declaration
+    xml_prefix: NSPrefixId,
+    /// The XMLNS URI
+    uri: NSUriId,
+    /// The 'xmlns' name, used for default namespace declarations
+    name: NSNameId,
+}
+
+//ii XmlnsIds
+impl XmlnsIds {
+    //fp new
+    fn new(ns_stack: &mut NamespaceStack) -> Self {
+        let prefix = ns_stack.add_prefix("xmlns");
+        let xml_prefix = ns_stack.add_prefix("xml");
+        let uri = ns_stack.add_uri(XMLNS_URI);
+        let name = ns_stack.add_name("xmlns");
+        Self {
+            prefix,
+            xml_prefix,
+            uri,
+            name,
+        }
+    }
+
+    //mp is_declaration
+    /// Return true if the name is that of a namespace declaration
+    /// attribute (xmlns or xmlns:<prefix>)
+    fn is_declaration(&self, name: &Name) -> bool {
+        name.uri == self.uri
+            || name.prefix == self.prefix
+            || (name.prefix.is_none() && name.name == self.name)
+    }
+
+    //mp required_mapping
+    /// Return the prefix to URI mapping that must be in scope for an
+    /// element or attribute name
+    ///
+    /// An unprefixed element name requires the default namespace to
+    /// be its URI (which may be none); an unprefixed attribute is not
+    /// in any namespace in XML, and so requires no mapping. A
+    /// prefixed name with no URI cannot be declared, and is an error.
+    fn required_mapping(
+        &self,
+        ns_stack: &NamespaceStack,
+        name: &Name,
+        is_element: bool,
+    ) -> MarkupResult<Option<(NSPrefixId, NSUriId)>> {
+        if name.prefix.is_none() {
+            if is_element {
+                Ok(Some((name.prefix, name.uri)))
+            } else {
+                Ok(None)
+            }
+        } else if name.prefix == self.xml_prefix {
+            Ok(None)
+        } else if name.uri.is_none() {
+            Err(MarkupError::unmapped_prefix(
+                ns_stack.prefix_str(name.prefix),
+            ))
+        } else {
+            Ok(Some((name.prefix, name.uri)))
+        }
+    }
+
+    //mp declaration
+    /// Create the namespace declaration attribute for a mapping
+    fn declaration(
+        &self,
+        ns_stack: &mut NamespaceStack,
+        prefix: NSPrefixId,
+        uri: NSUriId,
+    ) -> Attribute {
+        let value = ns_stack.uri_str(uri).to_string();
+        let name = {
+            if prefix.is_none() {
+                Name {
+                    prefix,
+                    uri: self.uri,
+                    name: self.name,
+                }
+            } else {
+                let prefix_name = ns_stack.prefix_str(prefix).to_string();
+                Name {
+                    prefix: self.prefix,
+                    uri: self.uri,
+                    name: ns_stack.add_name(&prefix_name),
+                }
+            }
+        };
+        Attribute { name, value }
+    }
+}
+
+//ti ElementInfo
+/// The position of an element within the document tree
+struct ElementInfo {
+    parent: Option<usize>,
+    depth: usize,
+}
+
+//a NamespaceNormalizer
+//tp NamespaceNormalizer
+/// A [NamespaceNormalizer] takes a complete stream of markup
+/// [Event]s whose names carry prefixes and URIs, but whose namespace
+/// declarations may be missing, redundant or inconsistent (for
+/// example if the events were built programmatically, or merged from
+/// different documents), and rewrites the stream so that each
+/// element's names are correctly declared.
+///
+/// All existing namespace declaration attributes are removed; a
+/// declaration for each prefix to URI mapping is then added to the
+/// lowest element that contains every use of that mapping (within
+/// each top-level element).
+///
+/// If a prefix is used for more than one URI in the document then
+/// the first URI found keeps the prefix, and the others are given
+/// new prefixes (such as `svg1`, or `ns1` for default namespaces)
+/// added to the [Namespace](crate::names::Namespace); the names of
+/// the elements and attributes are renamed accordingly. An
+/// unprefixed element without a URI always keeps the empty prefix.
+///
+/// # Example
+///
+/// ```text
+///  let mut normalizer = NamespaceNormalizer::default();
+///  for e in events {
+///      normalizer.add_event(e);
+///  }
+///  let events = normalizer.normalize(&mut namespace_stack)?;
+/// ```
+#[derive(Debug)]
+pub struct NamespaceNormalizer<P>
+where
+    P: PosnInCharStream,
+{
+    events: Vec<Event<P>>,
+}
+
+//ip Default for NamespaceNormalizer
+impl<P> Default for NamespaceNormalizer<P>
+where
+    P: PosnInCharStream,
+{
+    fn default() -> Self {
+        Self { events: Vec::new() }
+    }
+}
+
+//ip NamespaceNormalizer
+impl<P> NamespaceNormalizer<P>
+where
+    P: PosnInCharStream,
+{
+    //mp add_event
+    /// Add the next event of the stream to the normalizer
+    pub fn add_event(&mut self, event: Event<P>) {
+        self.events.push(event);
+    }
+
+    //mi lca
+    /// Find the lowest common ancestor of two elements, if they are
+    /// in the same tree
+    fn lca(elements: &[ElementInfo], mut a: usize, mut b: usize) -> Option<usize> {
+        while elements[a].depth > elements[b].depth {
+            a = elements[a].parent?;
+        }
+        while elements[b].depth > elements[a].depth {
+            b = elements[b].parent?;
+        }
+        while a != b {
+            a = elements[a].parent?;
+            b = elements[b].parent?;
+        }
+        Some(a)
+    }
+
+    //mp normalize
+    /// Consume the normalizer, returning the events with namespace
+    /// declarations rewritten
+    ///
+    /// An error is returned if an element or attribute name has a
+    /// prefix but no URI, as no declaration can be written for it
+    pub fn normalize(mut self, ns_stack: &mut NamespaceStack) -> HmlResult<Vec<Event<P>>, P> {
+        let ids = XmlnsIds::new(ns_stack);
+
+        // Find the element tree and every mapping used by each element
+        let mut elements = Vec::new();
+        let mut uses = Vec::new();
+        let mut stack = Vec::new();
+        for event in &self.events {
+            match event {
+                Event::StartElement { span, tag, spans } => {
+                    let e = elements.len();
+                    elements.push(ElementInfo {
+                        parent: stack.last().copied(),
+                        depth: stack.len(),
+                    });
+                    let mapping = ids.required_mapping(ns_stack, &tag.name, true);
+                    let mapping = mapping.map_err(|source| {
+                        let span = spans.as_ref().map_or(*span, |s| s.name);
+                        HmlError::MarkupError { span, source }
+                    })?;
+                    if let Some((p, u)) = mapping {
+                        uses.push((p, u, e));
+                    }
+                    for (i, a) in tag.attributes.attributes().iter().enumerate() {
+                        if ids.is_declaration(&a.name) {
+                            continue;
+                        }
+                        let mapping = ids.required_mapping(ns_stack, &a.name, false);
+                        let mapping = mapping.map_err(|source| {
+                            let attribute = spans.as_ref().and_then(|s| s.attribute(i));
+                            let span = attribute.map_or(*span, |s| s.name);
+                            HmlError::MarkupError { span, source }
+                        })?;
+                        if let Some((p, u)) = mapping {
+                            uses.push((p, u, e));
+                        }
+                    }
+                    stack.push(e);
+                }
+                Event::EndElement { .. } => {
+                    stack.pop();
+                }
+                _ => (),
+            }
+        }
+
+        // Each prefix is owned by one URI; other uses of the prefix are renamed
+        let mut owner: HashMap<NSPrefixId, NSUriId> = HashMap::new();
+        if uses.iter().any(|(p, u, _)| p.is_none() && u.is_none()) {
+            owner.insert(NSPrefixId::none(), NSUriId::none());
+        }
+        for (p, u, _) in &uses {
+            owner.entry(*p).or_insert(*u);
+        }
+        let mut used_prefixes: HashSet<NSPrefixId> = owner.keys().copied().collect();
+        used_prefixes.insert(ids.prefix);
+        used_prefixes.insert(ids.xml_prefix);
+        let mut renames: HashMap<(NSPrefixId, NSUriId), NSPrefixId> = HashMap::new();
+        for (p, u, _) in &uses {
+            if owner[p] == *u || renames.contains_key(&(*p, *u)) {
+                continue;
+            }
+            let base = {
+                if p.is_none() {
+                    "ns".to_string()
+                } else {
+                    ns_stack.prefix_str(*p).to_string()
+                }
+            };
+            let mut n = 1;
+            let new_prefix = loop {
+                let candidate = format!("{}{}", base, n);
+                match ns_stack.find_prefix_id(&candidate) {
+                    Some(id) if used_prefixes.contains(&id) => (),
+                    _ => break ns_stack.add_prefix(&candidate),
+                }
+                n += 1;
+            };
+            used_prefixes.insert(new_prefix);
+            renames.insert((*p, *u), new_prefix);
+        }
+
+        // Place each (renamed) mapping at the lowest common ancestor
+        // of its uses within each tree
+        let mut placements: Vec<(NSPrefixId, NSUriId, usize)> = Vec::new();
+        for (p, u, e) in &uses {
+            let p = renames.get(&(*p, *u)).copied().unwrap_or(*p);
+            if p.is_none() && u.is_none() {
+                continue;
+            }
+            let mut placed = false;
+            for (pp, pu, pe) in placements.iter_mut() {
+                if *pp == p && *pu == *u {
+                    if let Some(lca) = Self::lca(&elements, *pe, *e) {
+                        *pe = lca;
+                        placed = true;
+                        break;
+                    }
+                }
+            }
+            if !placed {
+                placements.push((p, *u, *e));
+            }
+        }
+        let mut declarations: Vec<Vec<(NSPrefixId, NSUriId)>> = vec![vec![]; elements.len()];
+        for (p, u, e) in placements {
+            declarations[e].push((p, u));
+        }
+
+        // Rewrite the names and the declarations of the elements
+        let rename = |name: &mut Name| {
+            if let Some(prefix) = renames.get(&(name.prefix, name.uri)) {
+                name.prefix = *prefix;
+            }
+        };
+        let mut e = 0;
+        let mut name_stack = Vec::new();
+        for event in self.events.iter_mut() {
+            match event {
+                Event::StartElement { span, tag, spans } => {
+                    rename(&mut tag.name);
+                    let mut attributes = Attributes::default();
+                    let mut attribute_spans = Vec::new();
+                    for (p, u) in &declarations[e] {
+                        attributes.push(ids.declaration(ns_stack, *p, *u));
+                        attribute_spans.push(AttributeSpan::new(*span, *span));
+                    }
+                    let old_attributes = std::mem::take(&mut tag.attributes).take();
+                    for (i, mut a) in old_attributes.into_iter().enumerate() {
+                        if ids.is_declaration(&a.name) {
+                            continue;
+                        }
+                        if a.name.has_prefix() {
+                            rename(&mut a.name);
+                        }
+                        attributes.push(a);
+                        if let Some(spans) = spans {
+                            let attribute_span = spans.attribute(i).copied();
+                            attribute_spans
+                                .push(attribute_span.unwrap_or(AttributeSpan::new(*span, *span)));
+                        }
+                    }
+                    tag.attributes = attributes;
+                    if let Some(spans) = spans {
+                        spans.attributes = attribute_spans;
+                    }
+                    name_stack.push(tag.name);
+                    e += 1;
+                }
+                Event::EndElement { name, .. } => {
+                    if let Some(n) = name_stack.pop() {
+                        *name = n;
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(self.events)
+    }
+}
+
+//a Test
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::names::{Namespace, Tag};
+    type Span = crate::Span<usize>;
+
+    //fi start
+    fn start(name: Name, attrs: &[(Name, &str)]) -> Event<usize> {
+        let mut attributes = Attributes::default();
+        for (name, value) in attrs {
+            attributes.push(Attribute {
+                name: *name,
+                value: value.to_string(),
+            });
+        }
+        Event::start_element(Span::new_at(&0), Tag { name, attributes })
+    }
+
+    //fi end
+    fn end(name: Name) -> Event<usize> {
+        Event::end_element(Span::new_at(&0), name)
+    }
+
+    //fi describe
+    /// Describe the start and end elements as strings
+    fn describe(ns: &NamespaceStack, events: &[Event<usize>]) -> Vec<String> {
+        let mut result = Vec::new();
+        for e in events {
+            match e {
+                Event::StartElement { tag, .. } => {
+                    let mut s = tag.name.to_string(ns);
+                    for a in tag.attributes.attributes() {
+                        s += &format!(" {}={}", a.name.to_string(ns), a.value);
+                    }
+                    result.push(s);
+                }
+                Event::EndElement { name, .. } => {
+                    result.push(format!("/{}", name.to_string(ns)));
+                }
+                _ => (),
+            }
+        }
+        result
+    }
+
+    //ft test_placement
+    #[test]
+    fn test_placement() {
+        let mut namespace = Namespace::new(true);
+        let mut ns = NamespaceStack::new(&mut namespace);
+        ns.add_ns("svg", "http://svg");
+        ns.add_ns("x", "http://x");
+        let doc = Name::new(&mut ns, "", "doc").unwrap();
+        let b = Name::new(&mut ns, "", "b").unwrap();
+        let c = Name::new(&mut ns, "", "c").unwrap();
+        let rect = Name::new(&mut ns, "svg", "rect").unwrap();
+        let circle = Name::new(&mut ns, "svg", "circle").unwrap();
+        let x_attr = Name::new(&mut ns, "x", "attr").unwrap();
+        let xmlns_svg = Name::new(&mut ns, "xmlns", "svg").unwrap();
+
+        let mut normalizer = NamespaceNormalizer::default();
+        for e in [
+            start(doc, &[]),
+            start(b, &[]),
+            start(rect, &[(xmlns_svg, "http://svg")]),
+            end(rect),
+            start(circle, &[(x_attr, "1")]),
+            end(circle),
+            end(b),
+            start(c, &[(x_attr, "2")]),
+            end(c),
+            end(doc),
+        ] {
+            normalizer.add_event(e);
+        }
+        let events = normalizer.normalize(&mut ns).unwrap();
+        assert_eq!(
+            describe(&ns, &events),
+            vec![
+                "doc xmlns:x=http://x",
+                "b xmlns:svg=http://svg",
+                "svg:rect",
+                "/svg:rect",
+                "svg:circle x:attr=1",
+                "/svg:circle",
+                "/b",
+                "c x:attr=2",
+                "/c",
+                "/doc"
+            ]
+        );
+    }
+
+    //ft test_clash
+    #[test]
+    fn test_clash() {
+        let mut namespace = Namespace::new(true);
+        let mut ns = NamespaceStack::new(&mut namespace);
+        ns.push_frame();
+        ns.add_ns("a", "http://a1");
+        let doc = Name::new(&mut ns, "", "doc").unwrap();
+        let a1 = Name::new(&mut ns, "a", "e").unwrap();
+        ns.add_ns("a", "http://a2");
+        let a2 = Name::new(&mut ns, "a", "e").unwrap();
+        ns.add_ns("", "http://default");
+        let d = Name::new(&mut ns, "", "d").unwrap();
+        let unused = Name::new(&mut ns, "xmlns", "unused").unwrap();
+
+        let mut normalizer = NamespaceNormalizer::default();
+        for e in [
+            start(doc, &[(unused, "http://unused")]),
+            start(a1, &[]),
+            end(a1),
+            start(a2, &[]),
+            start(d, &[]),
+            end(d),
+            end(a2),
+            end(doc),
+        ] {
+            normalizer.add_event(e);
+        }
+        let events = normalizer.normalize(&mut ns).unwrap();
+        assert_eq!(
+            describe(&ns, &events),
+            vec![
+                "doc",
+                "a:e xmlns:a=http://a1",
+                "/a:e",
+                "a1:e xmlns:a1=http://a2",
+                "ns1:d xmlns:ns1=http://default",
+                "/ns1:d",
+                "/a1:e",
+                "/doc"
+            ]
+        );
+    }
+
+    //ft test_unmapped_prefix
+    #[test]
+    fn test_unmapped_prefix() {
+        let mut namespace = Namespace::new(true);
+        let mut ns = NamespaceStack::new(&mut namespace);
+        let doc = Name::new(&mut ns, "", "doc").unwrap();
+        let unmapped = Name {
+            prefix: ns.add_prefix("q"),
+            uri: NSUriId::none(),
+            name: ns.add_name("attr"),
+        };
+        for (element, attrs) in [(unmapped, vec![]), (doc, vec![(unmapped, "1")])] {
+            let mut normalizer = NamespaceNormalizer::default();
+            normalizer.add_event(start(element, &attrs));
+            normalizer.add_event(end(element));
+            let e = normalizer.normalize(&mut ns).unwrap_err();
+            assert_eq!(e.kind(), "unmapped_prefix");
+        }
+    }
+}
@@ -0,0 +1,447 @@
+//a Imports
+use std::collections::HashMap;
+
+use super::{ContentType, Event};
+use crate::names::NamespaceStack;
+use crate::{HmlError, HmlResult, Posn, Span};
+
+//a Internal types
+//ti Conditional
+/// A conditional section that has been started by an 'if' directive
+/// but not yet ended by its 'endif'
+#[derive(Debug)]
+struct Conditional<P>
+where
+    P: Posn,
+{
+    /// Span of the 'if' directive
+    span: Span<P>,
+    /// Number of open elements at the 'if', which its 'else' and
+    /// 'endif' must match
+    depth: usize,
+    /// True if the content around the conditional is output
+    enclosing_active: bool,
+    /// True if the variable of the 'if' is defined
+    condition: bool,
+    /// True if the 'else' has been seen
+    in_else: bool,
+}
+
+//ii Conditional
+impl<P> Conditional<P>
+where
+    P: Posn,
+{
+    //mi is_active
+    /// Return true if the current section of the conditional is output
+    fn is_active(&self) -> bool {
+        self.enclosing_active && (self.condition != self.in_else)
+    }
+}
+
+//a Preprocessor
+//tp Preprocessor
+/// A [Preprocessor] applies conditional sections and variable
+/// substitution to a stream of markup [Event]s, so that variants of
+/// a document can be generated from the same source
+///
+/// The preprocessor is driven by processing instructions, which in
+/// HML are:
+///
+/// ```text
+///  #?define NAME "value"   ; define (or redefine) a variable
+///  #?if NAME               ; start a section output only if NAME is defined
+///  #?else                  ; start a section output only if it is not
+///  #?endif                 ; end the conditional
+/// ```
+///
+/// The directives may appear at any depth, but the 'else' and
+/// 'endif' of a conditional must be at the same depth (within the
+/// same element) as its 'if'; conditionals may be nested. The
+/// directives are removed from the event stream, as are the events
+/// of sections that are not output (a 'define' within such a section
+/// has no effect).
+///
+/// Variables may also be supplied with [Preprocessor::set_variable].
+/// Each '${NAME}' in an attribute value or in interpretable content
+/// is replaced by the value of the variable NAME (which must be
+/// defined), and '$${' is replaced by '${'; raw and whitespace
+/// content are not changed.
+///
+/// ```text
+///  let mut preprocessor = Preprocessor::default().set_variable("PRODUCT", "pro");
+///  loop {
+///      let event = parser.next_event(&mut namespace_stack, || lexer_iter.next())?;
+///      let end = event.is_end_document();
+///      if let Some(event) = preprocessor.process_event(&namespace_stack, event)? {
+///          ...
+///      }
+///      if end { break; }
+///  }
+/// ```
+#[derive(Debug)]
+pub struct Preprocessor<P>
+where
+    P: Posn,
+{
+    variables: HashMap<String, String>,
+    depth: usize,
+    conditionals: Vec<Conditional<P>>,
+}
+
+//ip Default for Preprocessor
+impl<P> Default for Preprocessor<P>
+where
+    P: Posn,
+{
+    fn default() -> Self {
+        Self {
+            variables: HashMap::new(),
+            depth: 0,
+            conditionals: Vec::new(),
+        }
+    }
+}
+
+//ip Preprocessor
+impl<P> Preprocessor<P>
+where
+    P: Posn,
+{
+    //cp set_variable
+    /// Define a variable before preprocessing
+    pub fn set_variable(mut self, name: &str, value: &str) -> Self {
+        self.define(name, value);
+        self
+    }
+
+    //mp define
+    /// Define (or redefine) a variable
+    pub fn define(&mut self, name: &str, value: &str) {
+        self.variables.insert(name.into(), value.into());
+    }
+
+    //ap variable
+    /// Get the value of a variable, if it is defined
+    pub fn variable(&self, name: &str) -> Option<&str> {
+        self.variables.get(name).map(|s| s.as_str())
+    }
+
+    //mi is_active
+    /// Return true if events at this point are output
+    fn is_active(&self) -> bool {
+        self.conditionals.last().map_or(true, |c| c.is_active())
+    }
+
+    //mi bad_directive
+    /// Create an error for a directive that is not valid
+    fn bad_directive<T>(span: Span<P>, reason: &'static str) -> HmlResult<T, P> {
+        Err(HmlError::BadDirective { span, reason })
+    }
+
+    //mi matching_conditional
+    /// Get the conditional that an 'else' or 'endif' belongs to,
+    /// which must be at the same depth
+    fn matching_conditional(
+        &mut self,
+        span: Span<P>,
+        reason: &'static str,
+    ) -> HmlResult<&mut Conditional<P>, P> {
+        match self.conditionals.last_mut() {
+            Some(c) if c.depth == self.depth => Ok(c),
+            _ => Self::bad_directive(span, reason),
+        }
+    }
+
+    //mi directive
+    /// Handle a directive, returning false if the processing
+    /// instruction is not a directive
+    ///
+    /// The variable name of a directive is the argument of the
+    /// processing instruction, and the value of a 'define' is its data
+    fn directive(
+        &mut self,
+        span: Span<P>,
+        name: &str,
+        arg: Option<&str>,
+        data: Option<&str>,
+    ) -> HmlResult<bool, P> {
+        match name {
+            "define" => {
+                let Some(arg) = arg else {
+                    return Self::bad_directive(span, "'define' requires a variable name");
+                };
+                if self.is_active() {
+                    self.define(arg, data.unwrap_or(""));
+                }
+            }
+            "if" => {
+                let Some(arg) = arg else {
+                    return Self::bad_directive(span, "'if' requires a variable name");
+                };
+                if data.is_some() {
+                    return Self::bad_directive(span, "'if' takes just a variable name");
+                }
+                self.conditionals.push(Conditional {
+                    span,
+                    depth: self.depth,
+                    enclosing_active: self.is_active(),
+                    condition: self.variables.contains_key(arg),
+                    in_else: false,
+                });
+            }
+            "else" | "endif" if arg.is_some() || data.is_some() => {
+                return Self::bad_directive(span, "'else' and 'endif' take no arguments");
+            }
+            "else" => {
+                let c = self.matching_conditional(span, "'else' without an 'if' at its depth")?;
+                if c.in_else {
+                    return Self::bad_directive(span, "a second 'else' for an 'if'");
+                }
+                c.in_else = true;
+            }
+            "endif" => {
+                self.matching_conditional(span, "'endif' without an 'if' at its depth")?;
+                self.conditionals.pop();
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    //mi substitute
+    /// Substitute the variables in a string, escaping their values
+    /// for HML if required; return None if there are no
+    /// substitutions
+    fn substitute(&self, span: Span<P>, s: &str, escape: bool) -> HmlResult<Option<String>, P> {
+        if !s.contains("${") {
+            return Ok(None);
+        }
+        let mut r = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(n) = rest.find('$') {
+            r.push_str(&rest[..n]);
+            rest = &rest[n + 1..];
+            if let Some(after) = rest.strip_prefix("${") {
+                r.push_str("${");
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('{') {
+                let Some(end) = after.find('}') else {
+                    return Self::bad_directive(span, "a '${' substitution has no closing '}'");
+                };
+                let name = &after[..end];
+                let Some(value) = self.variables.get(name) else {
+                    let name = name.into();
+                    return Err(HmlError::UndefinedVariable { span, name });
+                };
+                if escape {
+                    r.push_str(&crate::hml::escape::escape(value, true));
+                } else {
+                    r.push_str(value);
+                }
+                rest = &after[end + 1..];
+            } else {
+                r.push('$');
+            }
+        }
+        r.push_str(rest);
+        Ok(Some(r))
+    }
+
+    //mp process_event
+    /// Process the next event of the stream, returning it (with any
+    /// substitutions) if it is output, or None if it is a directive or
+    /// within a section that is not output
+    ///
+    /// An error is returned for a directive that is not valid, an
+    /// undefined variable, or a conditional that is not closed within
+    /// the element (or document) that it started in; the latter has
+    /// the span of the 'if' directive
+    pub fn process_event(
+        &mut self,
+        ns: &NamespaceStack,
+        mut event: Event<P>,
+    ) -> HmlResult<Option<Event<P>>, P> {
+        match &mut event {
+            Event::ProcessingInstruction {
+                span,
+                name,
+                arg,
+                data,
+            } if self.directive(*span, ns.name_str(*name), arg.as_deref(), data.as_deref())? => {
+                return Ok(None);
+            }
+            Event::StartElement { .. } => {
+                self.depth += 1;
+            }
+            Event::EndElement { .. } | Event::EndDocument { .. } => {
+                if let Some(c) = self.conditionals.last() {
+                    if c.depth >= self.depth {
+                        return Err(HmlError::UnclosedConditional { span: c.span });
+                    }
+                }
+                self.depth = self.depth.saturating_sub(1);
+            }
+            _ => (),
+        }
+        if !self.is_active() {
+            return Ok(None);
+        }
+        match &mut event {
+            Event::StartElement {
+                span, tag, spans, ..
+            } => {
+                for (i, a) in tag.attributes.attributes_mut().iter_mut().enumerate() {
+                    let value_span = {
+                        match spans.as_ref().and_then(|s| s.attribute(i)) {
+                            Some(s) => s.value,
+                            None => *span,
+                        }
+                    };
+                    if let Some(value) = self.substitute(value_span, &a.value, false)? {
+                        a.value = value;
+                    }
+                }
+            }
+            Event::Content {
+                span,
+                ctype: ContentType::Interpretable,
+                data,
+            } => {
+                if let Some(value) = self.substitute(*span, data, true)? {
+                    *data = value;
+                }
+            }
+            _ => (),
+        }
+        Ok(Some(event))
+    }
+}
+
+//a Tests
+#[cfg(test)]
+mod test {
+    use super::Preprocessor;
+    use crate::hml_reader::Parser;
+    use crate::markup::Event;
+    use crate::names::{Namespace, NamespaceStack};
+
+    use lexer_rs::{Lexer, LexerOfString, LineColumn, StreamCharPos, UserPosn};
+
+    type LexerPos = StreamCharPos<LineColumn>;
+
+    //fi preprocess
+    /// Parse HML text and preprocess it with a variable PRO defined,
+    /// returning a description of the events, or the kind and line of
+    /// the error
+    fn preprocess(text: &str) -> Result<String, (&'static str, usize)> {
+        let mut namespace = Namespace::new(true);
+        let mut namespace_stack = NamespaceStack::new(&mut namespace);
+        let lexer_string: LexerOfString<LexerPos, _, _> = LexerOfString::default().set_text(text);
+        let lexer = lexer_string.lexer();
+        let lexer_parsers = crate::hml_reader::parse_fns();
+        let mut lexer_iter = lexer.iter(&lexer_parsers);
+        let mut parser = Parser::default();
+        let mut preprocessor = Preprocessor::default().set_variable("PRO", "pro");
+        let mut events = Vec::new();
+        loop {
+            let event = parser
+                .next_event(&mut namespace_stack, || lexer_iter.next())
+                .unwrap();
+            let end = event.is_end_document();
+            match preprocessor.process_event(&namespace_stack, event) {
+                Err(e) => return Err((e.kind(), e.span().unwrap().start().line())),
+                Ok(Some(Event::StartElement { tag, .. })) => {
+                    let mut s = format!("<{}", namespace_stack.name_str(tag.name.name));
+                    for a in tag.attributes.attributes() {
+                        s += &format!(" {}", a.value);
+                    }
+                    events.push(s);
+                }
+                Ok(Some(Event::EndElement { .. })) => events.push(">".into()),
+                Ok(Some(Event::Content { data, .. })) => events.push(data),
+                Ok(Some(Event::ProcessingInstruction { data, .. })) => {
+                    events.push(format!("?{:?}", data))
+                }
+                _ => (),
+            }
+            if end {
+                return Ok(events.join(" "));
+            }
+        }
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let text = r#"#config
+##?if PRO
+##feature name="${PRO}-a"
+##?else
+##feature name="basic"
+##?endif
+##?if LITE
+##lite
+###?if PRO
+###lite_pro
+###?endif
+##?else
+##full
+###?if PRO ###?else ###not_pro ###?endif
+##?endif
+##?other "x"
+"#;
+        assert_eq!(
+            preprocess(text).unwrap(),
+            "<config <feature pro-a > <full > ?Some(\"x\") >"
+        );
+    }
+
+    #[test]
+    fn test_variables() {
+        let text = r##"#a
+##?define SIZE #"10 "kB""#
+##b size="${SIZE}" "Size: ${SIZE} for ${PRO} $${NOT} $ {}"
+##?define PRO "basic"
+##?define DIR r"C:\dir"
+##c dir="${DIR}" "${PRO} ${DIR}" r"${PRO}"
+##?if NONE ##?define PRO "none" ##?endif
+##d "${PRO}"
+"##;
+        assert_eq!(
+            preprocess(text).unwrap(),
+            r#"<a <b 10 "kB" Size: 10 "kB" for pro ${NOT} $ {} > <c C:\dir basic C:\\dir ${PRO} > <d basic > >"#
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            preprocess("#a\n##?if PRO\n##b\n"),
+            Err(("unclosed_conditional", 2))
+        );
+        assert_eq!(
+            preprocess("#a\n##?if PRO\n##b\n###?endif\n"),
+            Err(("bad_directive", 4))
+        );
+        assert_eq!(
+            preprocess("#a\n##b\n###?if PRO\n##c\n##?endif\n"),
+            Err(("unclosed_conditional", 3))
+        );
+        assert_eq!(
+            preprocess("#?if PRO\n#a\n"),
+            Err(("unclosed_conditional", 1))
+        );
+        assert_eq!(preprocess("#a\n##?else"), Err(("bad_directive", 2)));
+        assert_eq!(
+            preprocess("#a ##?if PRO\n##?else\n##?else"),
+            Err(("bad_directive", 3))
+        );
+        assert_eq!(preprocess("#a ##?if"), Err(("bad_directive", 1)));
+        assert_eq!(preprocess("#a\n\"${X}\""), Err(("undefined_variable", 2)));
+        assert_eq!(preprocess("#a\n\"${X\""), Err(("bad_directive", 2)));
+        assert_eq!(
+            preprocess("#a\n##b\n   x=\"${X}\""),
+            Err(("undefined_variable", 3))
+        );
+    }
+}
@@ -0,0 +1,76 @@
+//a Imports
+use lexer_rs::{PosnInCharStream, StreamCharSpan};
+
+//a AttributeSpan
+//tp AttributeSpan
+/// The spans of the name and value of an attribute within a source
+/// stream
+#[derive(Debug, Clone, Copy)]
+pub struct AttributeSpan<P>
+where
+    P: PosnInCharStream,
+{
+    /// The span of the (possibly prefixed) attribute name
+    pub name: StreamCharSpan<P>,
+    /// The span of the attribute value, including its quotes
+    pub value: StreamCharSpan<P>,
+}
+
+//ip AttributeSpan
+impl<P> AttributeSpan<P>
+where
+    P: PosnInCharStream,
+{
+    //fp new
+    /// Create a new [AttributeSpan]
+    pub fn new(name: StreamCharSpan<P>, value: StreamCharSpan<P>) -> Self {
+        Self { name, value }
+    }
+
+    //ap span
+    /// Get the span of the whole attribute, from the start of its
+    /// name to the end of its value
+    pub fn span(&self) -> StreamCharSpan<P> {
+        StreamCharSpan::new(*self.name.start(), *self.value.end())
+    }
+}
+
+//a TagSpans
+//tp TagSpans
+/// The spans of the parts of a start element tag: the tag name, and
+/// the name and value of each attribute
+///
+/// This is a side-table for the [crate::names::Tag] of a StartElement
+/// event; the attribute spans are in the same order as the
+/// attributes of the tag
+#[derive(Debug, Clone)]
+pub struct TagSpans<P>
+where
+    P: PosnInCharStream,
+{
+    /// The span of the (possibly prefixed) tag name
+    pub name: StreamCharSpan<P>,
+    /// The spans of the attributes, in the order of the attributes
+    pub attributes: Vec<AttributeSpan<P>>,
+}
+
+//ip TagSpans
+impl<P> TagSpans<P>
+where
+    P: PosnInCharStream,
+{
+    //fp new
+    /// Create a new [TagSpans] with no attribute spans
+    pub fn new(name: StreamCharSpan<P>) -> Self {
+        Self {
+            name,
+            attributes: Vec::new(),
+        }
+    }
+
+    //ap attribute
+    /// Get the spans of the n'th attribute, if known
+    pub fn attribute(&self, n: usize) -> Option<&AttributeSpan<P>> {
+        self.attributes.get(n)
+    }
+}
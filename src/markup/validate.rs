@@ -0,0 +1,256 @@
+//a Imports
+use super::{ContentType, Event};
+use crate::names::{Name, NamespaceStack};
+use crate::{HmlError, HmlResult, Posn, Span};
+
+//a Internal types
+//ti OpenElement
+/// An element that has been started but not yet ended
+#[derive(Debug)]
+struct OpenElement<P>
+where
+    P: Posn,
+{
+    /// Span of the StartElement event
+    span: Span<P>,
+    /// Name of the element, which the EndElement must match
+    name: Name,
+}
+
+//a Validator
+//tp Validator
+/// A [Validator] checks that a stream of markup [Event]s is
+/// well-formed, whatever its source (a parser, an XML reader, or
+/// events built by application code), passing each event through
+/// unchanged
+///
+/// The stream must start with a StartDocument event and end with an
+/// EndDocument event; StartElement and EndElement events must be
+/// properly nested, with matching names; and there must be exactly
+/// one root element, with all content (other than whitespace)
+/// within it. Comments and processing instructions may appear
+/// anywhere within the document.
+///
+/// The first violation is returned as an [HmlError] with the span of
+/// the offending event (or, for an element that is not closed, the
+/// span of its StartElement).
+///
+/// # Example
+///
+/// ```text
+///  let mut validator = Validator::default();
+///  loop {
+///      let event = parser.next_event(&mut namespace_stack, || lexer_iter.next())?;
+///      let event = validator.check_event(&namespace_stack, event)?;
+///      ...
+///  }
+/// ```
+#[derive(Debug)]
+pub struct Validator<P>
+where
+    P: Posn,
+{
+    started: bool,
+    finished: bool,
+    root: Option<Span<P>>,
+    stack: Vec<OpenElement<P>>,
+    last_posn: P,
+}
+
+//ip Default for Validator
+impl<P> Default for Validator<P>
+where
+    P: Posn,
+{
+    fn default() -> Self {
+        Self {
+            started: false,
+            finished: false,
+            root: None,
+            stack: Vec::new(),
+            last_posn: P::default(),
+        }
+    }
+}
+
+//ip Validator
+impl<P> Validator<P>
+where
+    P: Posn,
+{
+    //mi unexpected
+    /// Create an error for an event that is not valid at this point
+    fn unexpected<T>(event: &Event<P>, reason: &'static str) -> HmlResult<T, P> {
+        Err(HmlError::UnexpectedEvent {
+            span: *event.borrow_span(),
+            event: event.get_type(),
+            reason,
+        })
+    }
+
+    //mp check_event
+    /// Check the next event of the stream, returning it if it is
+    /// valid at this point, or an error describing the violation
+    pub fn check_event(&mut self, ns: &NamespaceStack, event: Event<P>) -> HmlResult<Event<P>, P> {
+        let span = *event.borrow_span();
+        if self.finished {
+            return Self::unexpected(&event, "the document has ended");
+        }
+        if !self.started {
+            if !event.is_start_document() {
+                return Self::unexpected(&event, "the document has not started");
+            }
+            self.started = true;
+            self.last_posn = *span.end();
+            return Ok(event);
+        }
+        match &event {
+            Event::StartDocument { .. } => {
+                return Self::unexpected(&event, "the document has already started");
+            }
+            Event::EndDocument { .. } => {
+                if let Some(top) = self.stack.last() {
+                    return Err(HmlError::UnclosedElement {
+                        span: top.span,
+                        name: top.name.to_string(ns),
+                    });
+                }
+                if self.root.is_none() {
+                    return Err(HmlError::MissingRootElement { span });
+                }
+                self.finished = true;
+            }
+            Event::StartElement { tag, .. } => {
+                if let (Some(root), true) = (self.root, self.stack.is_empty()) {
+                    return Err(HmlError::MultipleRootElements { span, root });
+                }
+                self.root = Some(span);
+                self.stack.push(OpenElement {
+                    span,
+                    name: tag.name,
+                });
+            }
+            Event::EndElement { name, .. } => {
+                let Some(top) = self.stack.last() else {
+                    let name = name.to_string(ns);
+                    return Err(HmlError::UnexpectedEndTag { span, name });
+                };
+                if top.name != *name {
+                    return Err(HmlError::MismatchedEndTag {
+                        span,
+                        expected: top.name.to_string(ns),
+                        found: name.to_string(ns),
+                    });
+                }
+                self.stack.pop();
+            }
+            Event::Content { ctype, .. } => {
+                if self.stack.is_empty() && *ctype != ContentType::Whitespace {
+                    return Err(HmlError::ContentOutsideRoot { span });
+                }
+            }
+            Event::ProcessingInstruction { .. } | Event::Comment { .. } => (),
+        }
+        self.last_posn = *span.end();
+        Ok(event)
+    }
+
+    //mp is_complete
+    /// Return true if the EndDocument event has been checked, so the
+    /// stream is complete
+    pub fn is_complete(&self) -> bool {
+        self.finished
+    }
+
+    //mp finish
+    /// Check that the stream is complete, once it has no more
+    /// events; if it is not then this returns an error at the end of
+    /// the last event
+    pub fn finish(&self) -> HmlResult<(), P> {
+        if self.finished {
+            Ok(())
+        } else {
+            Err(HmlError::UnexpectedEOF {
+                span: Span::new_at(&self.last_posn),
+            })
+        }
+    }
+
+    //mp depth
+    /// Get the number of elements that are currently open
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+}
+
+//a Tests
+#[cfg(test)]
+mod test {
+    use super::Validator;
+    use crate::markup::Event;
+    use crate::names::{Name, Namespace, NamespaceStack, Tag};
+
+    type Span = crate::Span<usize>;
+
+    //fi check
+    /// Build a stream of events from a description, where '<x' starts
+    /// element x, '>x' ends it, '$' and '/' start and end the
+    /// document, ' ' is whitespace, and other characters are content;
+    /// event n has span n..n+1. Return the kind of error and the
+    /// start of its span, if the stream is not well-formed
+    fn check(desc: &str) -> Option<(&'static str, usize)> {
+        let mut namespace = Namespace::new(true);
+        let mut ns = NamespaceStack::new(&mut namespace);
+        let mut validator = Validator::default();
+        let mut chars = desc.chars();
+        let mut n = 0;
+        while let Some(ch) = chars.next() {
+            let span = Span::new(n, n + 1);
+            let event = match ch {
+                '$' => Event::start_document(span, 100),
+                '/' => Event::end_document(span),
+                '<' => {
+                    let name = chars.next().unwrap().to_string();
+                    Event::start_element(
+                        span,
+                        Tag::new(&mut ns, "", &name, Default::default()).unwrap(),
+                    )
+                }
+                '>' => {
+                    let name = chars.next().unwrap().to_string();
+                    Event::end_element(span, Name::new(&mut ns, "", &name).unwrap())
+                }
+                ' ' => Event::content_ws(span, " ".to_string()),
+                _ => Event::content_int(span, ch.to_string()),
+            };
+            n += 1;
+            if let Err(e) = validator.check_event(&ns, event) {
+                return Some((e.kind(), e.span().unwrap().byte_range().start));
+            }
+        }
+        validator
+            .finish()
+            .err()
+            .map(|e| (e.kind(), e.span().unwrap().byte_range().start))
+    }
+
+    #[test]
+    fn test_valid() {
+        assert_eq!(check("$ <ax<b>b y >a /"), None);
+        assert_eq!(check("$<a<a<a>a>a>a/"), None);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(check("<a>a/"), Some(("unexpected_event", 0)));
+        assert_eq!(check("$$<a>a/"), Some(("unexpected_event", 1)));
+        assert_eq!(check("$<a>a/<b"), Some(("unexpected_event", 4)));
+        assert_eq!(check("$/"), Some(("missing_root_element", 1)));
+        assert_eq!(check("$<a<b>a>b/"), Some(("mismatched_end_tag", 3)));
+        assert_eq!(check("$<a>a>b/"), Some(("unexpected_end_tag", 3)));
+        assert_eq!(check("$<a<b>b/"), Some(("unclosed_element", 1)));
+        assert_eq!(check("$<a>a<b>b/"), Some(("multiple_root_elements", 3)));
+        assert_eq!(check("$x<a>a/"), Some(("content_outside_root", 1)));
+        assert_eq!(check("$<a>a"), Some(("unexpected_eof", 3)));
+    }
+}
@@ -34,11 +34,12 @@ mod name;
 mod namespace;
 mod namespace_stack;
 mod tag;
+pub(crate) mod value;
 
 //a Exports
 pub use attribute::{Attribute, Attributes};
 pub use ids::{NSMap, NSNameId, NSPrefixId, NSUriId};
 pub use name::Name;
 pub use namespace::Namespace;
-pub use namespace_stack::NamespaceStack;
+pub use namespace_stack::{NamespaceEvent, NamespaceStack};
 pub use tag::Tag;
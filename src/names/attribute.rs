@@ -1,6 +1,10 @@
 //a Imports
-use super::{Name, NamespaceStack};
-use crate::MarkupResult;
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::value;
+use super::{NSNameId, Name, NamespaceStack};
+use crate::{MarkupError, MarkupResult};
 
 //a Attribute
 //tp Attribute
@@ -27,12 +31,10 @@ impl Attribute {
     ) -> MarkupResult<Self> {
         if ns_stack.uses_xmlns() {
             if prefix.is_empty() && name == "xmlns" {
-                println!("Add ns '' to be {}", value);
                 ns_stack.add_ns("", &value);
                 let name = Name::new(ns_stack, name, name)?;
                 return Ok(Self { name, value });
             } else if prefix == "xmlns" {
-                println!("Add ns {} to be value {}", name, value);
                 ns_stack.add_ns(name, &value);
             }
         }
@@ -40,6 +42,67 @@ impl Attribute {
         Ok(Self { name, value })
     }
 
+    //mp is_default_namespace
+    /// Return true if the attribute is a default namespace
+    /// declaration ('xmlns=...'), which the [NamespaceStack] names
+    /// 'xmlns:xmlns'
+    pub fn is_default_namespace(&self, ns_stack: &NamespaceStack) -> bool {
+        self.name.has_prefix()
+            && ns_stack.prefix_str(self.name.prefix) == "xmlns"
+            && ns_stack.name_str(self.name.name) == "xmlns"
+    }
+
+    //mi convert
+    /// Convert the value of the attribute, mapping a failure to a
+    /// [MarkupError] that names the attribute
+    fn convert<T, F>(&self, ns_stack: &NamespaceStack, f: F) -> MarkupResult<T>
+    where
+        F: FnOnce(&str) -> Result<T, String>,
+    {
+        f(&self.value).map_err(|reason| {
+            MarkupError::bad_attribute_value(&self.name.to_string(ns_stack), &self.value, reason)
+        })
+    }
+
+    //mp parse_value
+    /// Parse the value of the attribute (ignoring leading and
+    /// trailing whitespace) using [FromStr]; this can be used for
+    /// integers, floats, and enumerations that implement [FromStr]
+    pub fn parse_value<T>(&self, ns_stack: &NamespaceStack) -> MarkupResult<T>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.convert(ns_stack, value::parse_value)
+    }
+
+    //mp as_bool
+    /// Get the value of the attribute as a boolean; 'true', 'yes',
+    /// 'on' and '1' are true, and 'false', 'no', 'off' and '0' are
+    /// false (ignoring case)
+    pub fn as_bool(&self, ns_stack: &NamespaceStack) -> MarkupResult<bool> {
+        self.convert(ns_stack, value::parse_bool)
+    }
+
+    //mp parse_list
+    /// Parse the value of the attribute as a whitespace-separated
+    /// list of values using [FromStr]
+    pub fn parse_list<T>(&self, ns_stack: &NamespaceStack) -> MarkupResult<Vec<T>>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.convert(ns_stack, value::parse_list)
+    }
+
+    //mp as_duration
+    /// Get the value of the attribute as a [Duration]; this is a
+    /// number with an optional unit of 'ns', 'us', 'ms', 's', 'm' or
+    /// 'h' (seconds if there is no unit)
+    pub fn as_duration(&self, ns_stack: &NamespaceStack) -> MarkupResult<Duration> {
+        self.convert(ns_stack, value::parse_duration)
+    }
+
     //zz All done
 }
 
@@ -47,6 +110,13 @@ impl Attribute {
 //tp Attributes
 /// A list of attributes in the order in which they appear in the
 /// markup stream
+///
+/// Attributes are identified by their URI and local name, as per
+/// XML namespaces; two attributes with the same URI and local name
+/// are duplicates even if they use different prefixes, and
+/// [Attributes::add] rejects them. An unprefixed attribute is in no
+/// namespace, so it is never a duplicate of a prefixed one (see
+/// [Name::same_attribute_name])
 #[derive(Debug, Default)]
 pub struct Attributes {
     //
@@ -61,9 +131,18 @@ impl Attributes {
         self.attributes.is_empty()
     }
 
+    //mp len
+    /// Returns the number of attributes in the list
+    pub fn len(&self) -> usize {
+        self.attributes.len()
+    }
+
     //mp add
     /// Add a prefix/name and value to the [Attributes] list, using
     /// the [NamespaceStack] to resolve the prefix into a URI
+    ///
+    /// Returns an error if the name is illegal, or if the list
+    /// already contains an attribute with the same URI and local name
     pub fn add(
         &mut self,
         ns_stack: &mut NamespaceStack,
@@ -71,9 +150,179 @@ impl Attributes {
         name: &str,
         value: String,
     ) -> MarkupResult<()> {
+        let attribute = Attribute::new(ns_stack, prefix, name, value)?;
+        self.add_attribute(ns_stack, attribute)
+    }
+
+    //mp add_attribute
+    /// Add an already-resolved [Attribute] to the end of the
+    /// [Attributes] list, returning an error if the list already
+    /// contains an attribute with the same URI and local name
+    pub fn add_attribute(
+        &mut self,
+        ns_stack: &NamespaceStack,
+        attribute: Attribute,
+    ) -> MarkupResult<()> {
+        if self.position(&attribute.name).is_some() {
+            Err(MarkupError::duplicate_attribute(
+                &attribute.name.to_string(ns_stack),
+            ))
+        } else {
+            self.attributes.push(attribute);
+            Ok(())
+        }
+    }
+
+    //mp push
+    /// Add an already-resolved [Attribute] to the end of the
+    /// [Attributes] list, without checking for duplicates
+    pub fn push(&mut self, attribute: Attribute) {
+        self.attributes.push(attribute);
+    }
+
+    //mp position
+    /// Find the index of the attribute with the same URI and local
+    /// name as `name`, if there is one; unprefixed names have no URI
+    pub fn position(&self, name: &Name) -> Option<usize> {
         self.attributes
-            .push(Attribute::new(ns_stack, prefix, name, value)?);
-        Ok(())
+            .iter()
+            .position(|a| a.name.same_attribute_name(name))
+    }
+
+    //mp get
+    /// Borrow the attribute with the same URI and local name as
+    /// `name`, if there is one
+    pub fn get(&self, name: &Name) -> Option<&Attribute> {
+        self.position(name).map(|i| &self.attributes[i])
+    }
+
+    //mp get_local
+    /// Borrow the first attribute whose local name is `name`,
+    /// whatever its namespace (see
+    /// [Attributes::position_unique_local] for a lookup that rejects
+    /// an ambiguous local name)
+    pub fn get_local(&self, name: NSNameId) -> Option<&Attribute> {
+        self.position_local(name).map(|i| &self.attributes[i])
+    }
+
+    //mp position_local
+    /// Find the index of the first attribute whose local name is
+    /// `name`, whatever its namespace
+    pub fn position_local(&self, name: NSNameId) -> Option<usize> {
+        self.attributes.iter().position(|a| a.name.name == name)
+    }
+
+    //mp position_unique_local
+    /// Find the index of the attribute whose local name is `name`,
+    /// whatever its namespace, returning an error if more than one
+    /// attribute has that local name (such as 'a:x' and 'b:x')
+    pub fn position_unique_local(
+        &self,
+        ns_stack: &NamespaceStack,
+        name: NSNameId,
+    ) -> MarkupResult<Option<usize>> {
+        let mut matching =
+            (0..self.attributes.len()).filter(|i| self.attributes[*i].name.name == name);
+        let first = matching.next();
+        match matching.next() {
+            Some(i) => Err(MarkupError::ambiguous_attribute(
+                &self.attributes[i].name.to_string(ns_stack),
+            )),
+            None => Ok(first),
+        }
+    }
+
+    //mi get_unique_local
+    /// Borrow the attribute whose local name is `name`, if there is
+    /// one, returning an error if there is more than one
+    fn get_unique_local(
+        &self,
+        ns_stack: &NamespaceStack,
+        name: NSNameId,
+    ) -> MarkupResult<Option<&Attribute>> {
+        Ok(self
+            .position_unique_local(ns_stack, name)?
+            .map(|i| &self.attributes[i]))
+    }
+
+    //mp remove
+    /// Remove the attribute with the same URI and local name as
+    /// `name`, if there is one, preserving the order of the rest
+    pub fn remove(&mut self, name: &Name) -> Option<Attribute> {
+        self.position(name).map(|i| self.attributes.remove(i))
+    }
+
+    //mp get_value
+    /// Parse the value of the attribute with the local name using
+    /// [FromStr] (see [Attribute::parse_value]), returning None if
+    /// there is no such attribute
+    ///
+    /// An error is returned if more than one attribute has the local
+    /// name; to select one of them by its namespace, use
+    /// [Attributes::get] and the methods of [Attribute]
+    pub fn get_value<T>(&self, ns_stack: &NamespaceStack, name: NSNameId) -> MarkupResult<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.get_unique_local(ns_stack, name)?
+            .map(|a| a.parse_value(ns_stack))
+            .transpose()
+    }
+
+    //mp get_bool
+    /// Get the value of the attribute with the local name as a
+    /// boolean (see [Attribute::as_bool]), returning None if there is
+    /// no such attribute, and an error if there is more than one (see
+    /// [Attributes::get_value])
+    pub fn get_bool(
+        &self,
+        ns_stack: &NamespaceStack,
+        name: NSNameId,
+    ) -> MarkupResult<Option<bool>> {
+        self.get_unique_local(ns_stack, name)?
+            .map(|a| a.as_bool(ns_stack))
+            .transpose()
+    }
+
+    //mp get_list
+    /// Parse the value of the attribute with the local name as a
+    /// whitespace-separated list (see [Attribute::parse_list]),
+    /// returning None if there is no such attribute, and an error if
+    /// there is more than one (see [Attributes::get_value])
+    pub fn get_list<T>(
+        &self,
+        ns_stack: &NamespaceStack,
+        name: NSNameId,
+    ) -> MarkupResult<Option<Vec<T>>>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.get_unique_local(ns_stack, name)?
+            .map(|a| a.parse_list(ns_stack))
+            .transpose()
+    }
+
+    //mp get_duration
+    /// Get the value of the attribute with the local name as a
+    /// [Duration] (see [Attribute::as_duration]), returning None if
+    /// there is no such attribute, and an error if there is more than
+    /// one (see [Attributes::get_value])
+    pub fn get_duration(
+        &self,
+        ns_stack: &NamespaceStack,
+        name: NSNameId,
+    ) -> MarkupResult<Option<Duration>> {
+        self.get_unique_local(ns_stack, name)?
+            .map(|a| a.as_duration(ns_stack))
+            .transpose()
+    }
+
+    //mp iter
+    /// Iterate over the attributes in the order they were added
+    pub fn iter(&self) -> std::slice::Iter<'_, Attribute> {
+        self.attributes.iter()
     }
 
     //mp steal
@@ -94,5 +343,133 @@ impl Attributes {
         &self.attributes
     }
 
+    //ap attributes_mut
+    /// Mutably borrow the [Attribute] vec, to change attribute values
+    pub fn attributes_mut(&mut self) -> &mut [Attribute] {
+        &mut self.attributes
+    }
+
     //zz All done
 }
+
+//ip IntoIterator for &Attributes
+impl<'a> IntoIterator for &'a Attributes {
+    type Item = &'a Attribute;
+    type IntoIter = std::slice::Iter<'a, Attribute>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+//a Test
+#[cfg(test)]
+mod test {
+    use crate::names::{Attributes, Name, Namespace, NamespaceStack};
+    use crate::MarkupError;
+    #[test]
+    fn test_attributes() {
+        let mut ns = Namespace::new(true);
+        let mut nst = NamespaceStack::new(&mut ns);
+        let mut attributes = Attributes::default();
+        attributes
+            .add(&mut nst, "xmlns", "p", "http://x".into())
+            .unwrap();
+        attributes
+            .add(&mut nst, "xmlns", "q", "http://x".into())
+            .unwrap();
+        attributes.add(&mut nst, "", "title", "a".into()).unwrap();
+        attributes.add(&mut nst, "p", "a", "1".into()).unwrap();
+        assert_eq!(attributes.len(), 4);
+
+        assert!(matches!(
+            attributes.add(&mut nst, "", "title", "b".into()),
+            Err(MarkupError::DuplicateAttribute { .. })
+        ));
+        assert!(matches!(
+            attributes.add(&mut nst, "q", "a", "2".into()),
+            Err(MarkupError::DuplicateAttribute { .. })
+        ));
+        assert_eq!(attributes.len(), 4);
+
+        let qa = Name::new(&mut nst, "q", "a").unwrap();
+        assert_eq!(attributes.get(&qa).unwrap().value, "1");
+        assert_eq!(attributes.get_local(qa.name).unwrap().value, "1");
+        let title = nst.add_name("title");
+        assert_eq!(attributes.get_local(title).unwrap().value, "a");
+
+        let names: Vec<String> = attributes.iter().map(|a| a.name.to_string(&nst)).collect();
+        assert_eq!(names, vec!["xmlns:p", "xmlns:q", "title", "p:a"]);
+
+        let removed = attributes.remove(&qa).unwrap();
+        assert_eq!(removed.value, "1");
+        assert!(attributes.get(&qa).is_none());
+        assert_eq!((&attributes).into_iter().count(), 3);
+    }
+
+    #[test]
+    fn test_default_namespace_attributes() {
+        let mut ns = Namespace::new(true);
+        let mut nst = NamespaceStack::new(&mut ns);
+        let mut attributes = Attributes::default();
+        attributes.add(&mut nst, "", "xmlns", "u".into()).unwrap();
+        attributes.add(&mut nst, "xmlns", "p", "u".into()).unwrap();
+        attributes.add(&mut nst, "", "a", "1".into()).unwrap();
+        attributes.add(&mut nst, "p", "a", "2".into()).unwrap();
+        assert_eq!(attributes.len(), 4);
+        assert!(matches!(
+            attributes.add(&mut nst, "", "a", "3".into()),
+            Err(MarkupError::DuplicateAttribute { .. })
+        ));
+        let pa = Name::new(&mut nst, "p", "a").unwrap();
+        assert_eq!(attributes.get(&pa).unwrap().value, "2");
+    }
+
+    #[test]
+    fn test_typed_attributes() {
+        let mut ns = Namespace::new(true);
+        let mut nst = NamespaceStack::new(&mut ns);
+        let mut attributes = Attributes::default();
+        attributes.add(&mut nst, "", "n", "101".into()).unwrap();
+        attributes.add(&mut nst, "", "b", "Off".into()).unwrap();
+        let n = nst.add_name("n");
+        let b = nst.add_name("b");
+        let c = nst.add_name("c");
+        assert_eq!(attributes.get_value::<u32>(&nst, n).unwrap(), Some(101));
+        assert_eq!(attributes.get_bool(&nst, b).unwrap(), Some(false));
+        assert_eq!(
+            attributes.get_list::<u32>(&nst, n).unwrap(),
+            Some(vec![101])
+        );
+        assert_eq!(attributes.get_value::<u32>(&nst, c).unwrap(), None);
+        assert!(matches!(
+            attributes.get_bool(&nst, n),
+            Err(MarkupError::BadAttributeValue { .. })
+        ));
+        assert!(matches!(
+            attributes.get_duration(&nst, b),
+            Err(MarkupError::BadAttributeValue { .. })
+        ));
+
+        attributes
+            .add(&mut nst, "xmlns", "p", "urn:p".into())
+            .unwrap();
+        attributes
+            .add(&mut nst, "xmlns", "q", "urn:q".into())
+            .unwrap();
+        attributes.add(&mut nst, "p", "x", "1".into()).unwrap();
+        attributes.add(&mut nst, "q", "x", "2".into()).unwrap();
+        let x = nst.add_name("x");
+        assert!(matches!(
+            attributes.get_value::<u32>(&nst, x),
+            Err(MarkupError::AmbiguousAttribute { .. })
+        ));
+        assert!(matches!(
+            attributes.get_bool(&nst, x),
+            Err(MarkupError::AmbiguousAttribute { .. })
+        ));
+        let qx = Name::new(&mut nst, "q", "x").unwrap();
+        let value = attributes.get(&qx).map(|a| a.parse_value::<u32>(&nst));
+        assert_eq!(value.unwrap().unwrap(), 2);
+    }
+}
@@ -98,6 +98,24 @@ impl Name {
         }
     }
 
+    //mp same_expanded_name
+    /// Return true if the two names have the same URI and local name
+    /// (the XML 'expanded name'), whatever their prefixes
+    pub fn same_expanded_name(&self, other: &Name) -> bool {
+        self.uri == other.uri && self.name == other.name
+    }
+
+    //mp same_attribute_name
+    /// Return true if the two names are the same attribute name: as
+    /// per XML namespaces an unprefixed attribute is in no namespace
+    /// (whatever the default namespace), so the URIs are compared
+    /// only for prefixed names
+    pub fn same_attribute_name(&self, other: &Name) -> bool {
+        self.name == other.name
+            && self.has_prefix() == other.has_prefix()
+            && (!self.has_prefix() || self.uri == other.uri)
+    }
+
     //ap has_prefix
     /// Returns true if the name has a prefix
     pub fn has_prefix(&self) -> bool {
@@ -122,7 +122,7 @@ impl Namespace {
 
     //mp find_or_add_uri
     /// Find a URI within the Namespace; if it is not found then add it
-    fn find_or_add_uri(&mut self, uri: &str) -> NSUriId {
+    pub(crate) fn find_or_add_uri(&mut self, uri: &str) -> NSUriId {
         if let Some(id) = self.find_uri(uri) {
             id
         } else {
@@ -90,11 +90,45 @@ impl NamespaceStackFrame {
 
     //mp iter_mappings
     /// Iterate over the mappings defined in this stack frame (prefix to URI)
-    fn iter_mappings(&self) -> NamespaceStackFrameIter {
+    fn iter_mappings(&self) -> NamespaceStackFrameIter<'_> {
         NamespaceStackFrameIter::new(self)
     }
 }
 
+//tp NamespaceEvent
+/// A [NamespaceEvent] reports a prefix to URI mapping being added to
+/// a [NamespaceStack]; these are recorded by the stack if requested
+/// with [NamespaceStack::record_ns_events], so that a client can log
+/// namespace declarations, or lint or reject redeclarations.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NamespaceEvent {
+    /// The mapping that was added
+    pub map: NSMap,
+    /// The depth of the stack frame that the mapping was added to;
+    /// the base frame of the stack has depth 0
+    pub depth: usize,
+    /// If the prefix was already mapped then the URI it was mapped
+    /// to, and the depth of the frame that mapping was in
+    pub shadowed: Option<(NSUriId, usize)>,
+}
+
+//ip NamespaceEvent
+impl NamespaceEvent {
+    //mp is_shadowing
+    /// Return true if the mapping hides an earlier mapping of the same
+    /// prefix (in this or a lower stack frame)
+    pub fn is_shadowing(&self) -> bool {
+        self.shadowed.is_some()
+    }
+
+    //mp is_redeclaration
+    /// Return true if the mapping replaces a mapping of the same
+    /// prefix in the same stack frame
+    pub fn is_redeclaration(&self) -> bool {
+        matches!(self.shadowed, Some((_, depth)) if depth == self.depth)
+    }
+}
+
 //ti NamespaceStackIterator
 pub struct NamespaceStackIterator<'ns, 'b> {
     stack: &'b NamespaceStack<'ns>,
@@ -182,6 +216,7 @@ impl<'ns, 'b> Iterator for NamespaceStackIterator<'ns, 'b> {
 pub struct NamespaceStack<'ns> {
     namespaces: &'ns mut Namespace,
     frames: Vec<NamespaceStackFrame>,
+    ns_events: Option<Vec<NamespaceEvent>>,
 }
 
 //ip NamespaceStack
@@ -191,7 +226,12 @@ impl<'ns> NamespaceStack<'ns> {
     /// [Namespace] for its lifetime
     pub fn new(namespaces: &'ns mut Namespace) -> Self {
         let frames = vec![NamespaceStackFrame::default()];
-        let mut s = Self { namespaces, frames };
+        let ns_events = None;
+        let mut s = Self {
+            namespaces,
+            frames,
+            ns_events,
+        };
         if s.uses_xmlns() {
             s.add_default_xmls();
         } else {
@@ -225,6 +265,45 @@ impl<'ns> NamespaceStack<'ns> {
         self.add_ns("xml", "http://www.w3.org/XML/1998/namespace");
     }
 
+    //mp record_ns_events
+    /// Enable or disable recording of [NamespaceEvent]s for every
+    /// mapping subsequently added to the stack; disabling recording
+    /// discards any events that have not been taken
+    pub fn record_ns_events(&mut self, enable: bool) {
+        if !enable {
+            self.ns_events = None;
+        } else if self.ns_events.is_none() {
+            self.ns_events = Some(Vec::new());
+        }
+    }
+
+    //mp take_ns_events
+    /// Take the [NamespaceEvent]s recorded since the last call, in
+    /// the order the mappings were added
+    pub fn take_ns_events(&mut self) -> Vec<NamespaceEvent> {
+        match &mut self.ns_events {
+            Some(events) => std::mem::take(events),
+            None => Vec::new(),
+        }
+    }
+
+    //mi record_ns_event
+    /// Record a mapping that is about to be added to the topmost
+    /// stack frame, if recording is enabled
+    fn record_ns_event(&mut self, map: NSMap) {
+        if self.ns_events.is_some() {
+            let depth = self.frames.len() - 1;
+            let shadowed = self.find_mapping_and_depth(map.prefix_id());
+            if let Some(events) = &mut self.ns_events {
+                events.push(NamespaceEvent {
+                    map,
+                    depth,
+                    shadowed,
+                });
+            }
+        }
+    }
+
     //mp push_frame
     /// Push a new stack frame on to the [NamespaceStack]
     pub fn push_frame(&mut self) {
@@ -254,12 +333,17 @@ impl<'ns> NamespaceStack<'ns> {
     //mp add_mapping_by_id
     /// Add a mapping of NSPrefixId -> NSUriId to the topmost stack frame
     pub fn add_mapping_by_id(&mut self, map: NSMap) {
+        self.record_ns_event(map);
         self.frames.last_mut().unwrap().add_mapping_by_id(map)
     }
 
     //mp add_mapping_by_id_if_unset
     /// Add a mapping if it does not exist *in the topmost stack fram*
     pub fn add_mapping_by_id_if_unset(&mut self, map: NSMap) -> bool {
+        let top = self.frames.last().unwrap();
+        if top.find_mapping(map.prefix_id()).is_none() {
+            self.record_ns_event(map);
+        }
         self.frames
             .last_mut()
             .unwrap()
@@ -272,10 +356,21 @@ impl<'ns> NamespaceStack<'ns> {
     ///
     /// Returns `None` if there is no mapping on the stack at all
     pub fn find_mapping(&self, prefix_id: NSPrefixId) -> Option<NSUriId> {
+        self.find_mapping_and_depth(prefix_id)
+            .map(|(uri_id, _)| uri_id)
+    }
+
+    //mp find_mapping_and_depth
+    /// Find a mapping of an [NSPrefixId] at the highest level of the
+    /// stack that it exists, and return it with the depth of that
+    /// stack frame
+    ///
+    /// Returns `None` if there is no mapping on the stack at all
+    pub fn find_mapping_and_depth(&self, prefix_id: NSPrefixId) -> Option<(NSUriId, usize)> {
         let n = self.frames.len();
         for i in 0..n {
             if let Some(uri_id) = self.frames[n - 1 - i].find_mapping(prefix_id) {
-                return Some(*uri_id);
+                return Some((*uri_id, n - 1 - i));
             }
         }
         None
@@ -327,6 +422,24 @@ impl<'ns> NamespaceStack<'ns> {
         self.namespaces.find_or_add_name(name)
     }
 
+    //mp add_prefix
+    /// Add a prefix string to the [Namespace]; if it is already in
+    /// the [Namespace] then it is not added but the current
+    /// NSPrefixId is used.
+    ///
+    /// This does not map the prefix to any URI
+    pub fn add_prefix(&mut self, prefix: &str) -> NSPrefixId {
+        self.namespaces.find_or_add_prefix(prefix)
+    }
+
+    //mp add_uri
+    /// Add a URI string to the [Namespace]; if it is already in the
+    /// [Namespace] then it is not added but the current NSUriId is
+    /// used.
+    pub fn add_uri(&mut self, uri: &str) -> NSUriId {
+        self.namespaces.find_or_add_uri(uri)
+    }
+
     //mp add_ns
     /// Add a prefix -> URI mapping to the [Namespace]
     pub fn add_ns(&mut self, prefix: &str, uri: &str) -> NSMap {
@@ -472,4 +585,40 @@ mod test {
         let pid = nst.find_prefix_id("fred").unwrap(); // Note not None any more
         assert_eq!(nst.find_mapping(pid), None);
     }
+    #[test]
+    fn test_ns_events() {
+        let mut ns = Namespace::new(true);
+        let mut nst = NamespaceStack::new(&mut ns);
+        nst.add_ns("fred", "http://fred.com");
+        assert!(nst.take_ns_events().is_empty());
+
+        nst.record_ns_events(true);
+        nst.push_frame();
+        let fred2 = nst.add_ns("fred", "http://fred2.com");
+        let jim = nst.add_ns("jim", "http://jim.com");
+        nst.add_ns_if_unset("jim", "http://NOTjim.com");
+        let jim2 = nst.add_ns("jim", "http://jim2.com");
+
+        let events = nst.take_ns_events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].map, fred2);
+        assert_eq!(events[0].depth, 1);
+        assert!(events[0].is_shadowing());
+        assert!(!events[0].is_redeclaration());
+        assert_eq!(
+            nst.uri_str(events[0].shadowed.unwrap().0),
+            "http://fred.com"
+        );
+        assert_eq!(events[0].shadowed.unwrap().1, 0);
+        assert_eq!(events[1].map, jim);
+        assert!(!events[1].is_shadowing());
+        assert_eq!(events[2].map, jim2);
+        assert!(events[2].is_redeclaration());
+        assert!(nst.take_ns_events().is_empty());
+
+        nst.record_ns_events(false);
+        nst.add_ns("bob", "http://bob.com");
+        nst.record_ns_events(true);
+        assert!(nst.take_ns_events().is_empty());
+    }
 }
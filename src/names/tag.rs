@@ -1,5 +1,8 @@
 //a Imports
-use super::{Attributes, Name, NamespaceStack};
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::{Attributes, NSNameId, Name, NamespaceStack};
 use crate::MarkupResult;
 
 //a Tag
@@ -31,4 +34,52 @@ impl Tag {
         let name = Name::new(ns_stack, ns, name)?;
         Ok(Self { name, attributes })
     }
+
+    //mp get_value
+    /// Parse the value of an attribute of the tag using [FromStr]
+    /// (see [Attributes::get_value])
+    pub fn get_value<T>(&self, ns_stack: &NamespaceStack, name: NSNameId) -> MarkupResult<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.attributes.get_value(ns_stack, name)
+    }
+
+    //mp get_bool
+    /// Get the value of an attribute of the tag as a boolean (see
+    /// [Attributes::get_bool])
+    pub fn get_bool(
+        &self,
+        ns_stack: &NamespaceStack,
+        name: NSNameId,
+    ) -> MarkupResult<Option<bool>> {
+        self.attributes.get_bool(ns_stack, name)
+    }
+
+    //mp get_list
+    /// Parse the value of an attribute of the tag as a list (see
+    /// [Attributes::get_list])
+    pub fn get_list<T>(
+        &self,
+        ns_stack: &NamespaceStack,
+        name: NSNameId,
+    ) -> MarkupResult<Option<Vec<T>>>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.attributes.get_list(ns_stack, name)
+    }
+
+    //mp get_duration
+    /// Get the value of an attribute of the tag as a [Duration] (see
+    /// [Attributes::get_duration])
+    pub fn get_duration(
+        &self,
+        ns_stack: &NamespaceStack,
+        name: NSNameId,
+    ) -> MarkupResult<Option<Duration>> {
+        self.attributes.get_duration(ns_stack, name)
+    }
 }
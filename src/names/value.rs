@@ -0,0 +1,93 @@
+//a Imports
+use std::str::FromStr;
+use std::time::Duration;
+
+//a Value conversions
+//fp parse_value
+/// Parse a (whitespace-trimmed) string value using [FromStr],
+/// returning the reason for failure as a String
+pub(crate) fn parse_value<T>(s: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    s.trim().parse::<T>().map_err(|e| e.to_string())
+}
+
+//fp parse_bool
+/// Parse a boolean value; 'true', 'yes', 'on' and '1' are true, and
+/// 'false', 'no', 'off' and '0' are false (ignoring case)
+pub(crate) fn parse_bool(s: &str) -> Result<bool, String> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err("expected a boolean (true/false, yes/no, on/off, 1/0)".into()),
+    }
+}
+
+//fp parse_list
+/// Parse a whitespace-separated list of values using [FromStr]
+pub(crate) fn parse_list<T>(s: &str) -> Result<Vec<T>, String>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    s.split_whitespace()
+        .map(|item| {
+            item.parse::<T>()
+                .map_err(|e| format!("list item '{}': {}", item, e))
+        })
+        .collect()
+}
+
+//fp parse_duration
+/// Parse a duration, which is a non-negative number with an optional
+/// unit of 'ns', 'us', 'ms', 's', 'm' or 'h'; without a unit the
+/// number is in seconds
+pub(crate) fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let number = s.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = &s[number.len()..];
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| "expected a number with an optional unit".to_string())?;
+    let secs = match unit.trim() {
+        "ns" => number / 1.0E9,
+        "us" => number / 1.0E6,
+        "ms" => number / 1.0E3,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(format!("unknown duration unit '{}'", unit)),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|e| e.to_string())
+}
+
+//a Test
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_values() {
+        assert_eq!(parse_value::<u32>(" 101 "), Ok(101));
+        assert!(parse_value::<u32>("-1").is_err());
+        assert_eq!(parse_value::<f32>("1.5"), Ok(1.5));
+        assert_eq!(parse_bool("Yes"), Ok(true));
+        assert_eq!(parse_bool("0"), Ok(false));
+        assert!(parse_bool("maybe").is_err());
+        assert_eq!(parse_list::<i32>(" 1 2\t-3 "), Ok(vec![1, 2, -3]));
+        assert_eq!(parse_list::<i32>(""), Ok(vec![]));
+        assert!(parse_list::<i32>("1 x 3").is_err());
+        assert_eq!(parse_duration("101"), Ok(Duration::from_secs(101)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("2 m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("1e3ms"), Ok(Duration::from_secs(1)));
+        assert_eq!(parse_duration("1.5E2"), Ok(Duration::from_secs(150)));
+        assert!(parse_duration("1 fortnight").is_err());
+        assert!(parse_duration("-1s").is_err());
+        assert!(parse_duration("s").is_err());
+    }
+}
@@ -0,0 +1,441 @@
+//a Documentation
+/*!
+
+# Sources module
+
+A [Span] identifies a place within a single text, but not which text;
+when a document is composed from several files (for example with an
+[Includer](crate::hml_reader::Includer)), an error must also say which
+file it is in.
+
+This module provides [Sources], a set of texts each registered with a
+[FileId], and [FilePosn], a position type (implementing [Posn](crate::Posn)) that
+carries the [FileId] of its text as well as the byte offset, line and
+column. A lexer that starts at [FilePosn::new] for a file produces
+tokens (and hence events and errors) whose spans identify the file.
+
+```text
+ let mut sources = Sources::default();
+ let file = sources.add("main.hml", &text);
+ let lexer: LexerOfStr<FilePosn, _, _> = LexerOfStr::new(&text);
+ let lexer_parsers = hml_rs::hml_reader::parse_fns();
+ let mut lexer_iter = ParserIterator::new(&lexer, FilePosn::new(file), &lexer_parsers);
+```
+
+[Sources::fmt_error] then formats an error as 'file:line:column:
+message', followed by the context of the error in its text (using
+lexer_rs::FmtContext), and the location of each include directive
+that led to it.
+
+A [SourceLoader] wraps the [Loader] of an
+[Includer](crate::hml_reader::Includer), registering each document
+that it loads with its [Sources].
+
+!*/
+
+//a Imports
+use lexer_rs::{FmtContext, LineColumn, PosnInCharStream, StreamCharPos, UserPosn};
+
+use crate::hml_reader::Loader;
+use crate::{HmlError, Span};
+
+//a FileId
+//tp FileId
+/// An identifier of a text registered with [Sources]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId(usize);
+
+//ip Display for FileId
+impl std::fmt::Display for FileId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+//a FilePosn
+//tp FilePosn
+/// A position within a text registered with [Sources]: the [FileId]
+/// of the text, and the byte offset, line and column within it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FilePosn {
+    file: FileId,
+    posn: StreamCharPos<LineColumn>,
+}
+
+//ip FilePosn
+impl FilePosn {
+    //fp new
+    /// Create a position at the start of a file
+    pub fn new(file: FileId) -> Self {
+        Self {
+            file,
+            posn: StreamCharPos::default(),
+        }
+    }
+
+    //ap file
+    /// Get the [FileId] of the text that the position is within
+    pub fn file(&self) -> FileId {
+        self.file
+    }
+}
+
+//ip UserPosn for FilePosn
+impl UserPosn for FilePosn {
+    fn advance_cols(mut self, num_bytes: usize, num_chars: usize) -> Self {
+        self.posn = self.posn.advance_cols(num_bytes, num_chars);
+        self
+    }
+    fn advance_line(mut self, num_bytes: usize) -> Self {
+        self.posn = self.posn.advance_line(num_bytes);
+        self
+    }
+    fn line(&self) -> usize {
+        self.posn.line()
+    }
+    fn column(&self) -> usize {
+        self.posn.column()
+    }
+    fn error_fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(fmt, "file {} ", self.file)?;
+        self.posn.error_fmt(fmt)
+    }
+}
+
+//ip PosnInCharStream for FilePosn
+impl PosnInCharStream for FilePosn {
+    fn byte_ofs(&self) -> usize {
+        self.posn.byte_ofs()
+    }
+}
+
+//ip Display for FilePosn
+impl std::fmt::Display for FilePosn {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        self.error_fmt(fmt)
+    }
+}
+
+//a Source and SourceContext
+//ti Source
+/// A text registered with [Sources]
+#[derive(Debug)]
+struct Source {
+    /// Name of the text, such as its path
+    name: String,
+    /// The text
+    text: String,
+    /// Byte offset of the start of each line, and its number of
+    /// characters; entry 0 is line 1
+    lines: Vec<(usize, usize)>,
+}
+
+//ii Source
+impl Source {
+    //fi new
+    /// Create a new [Source], finding the lines of the text
+    fn new(name: &str, text: &str) -> Self {
+        let mut lines = Vec::new();
+        let mut start = 0;
+        for line in text.split('\n') {
+            lines.push((start, line.chars().count()));
+            start += line.len() + 1;
+        }
+        Self {
+            name: name.into(),
+            text: text.into(),
+            lines,
+        }
+    }
+}
+
+//tp SourceContext
+/// The context of one text of [Sources], which implements
+/// lexer_rs::FmtContext to display the lines of the text around a
+/// span
+#[derive(Debug, Clone, Copy)]
+pub struct SourceContext<'a> {
+    source: &'a Source,
+}
+
+//ip FmtContext for SourceContext
+impl<P: UserPosn> FmtContext<P> for SourceContext<'_> {
+    fn line_length(&self, line: usize) -> usize {
+        match line.checked_sub(1).and_then(|l| self.source.lines.get(l)) {
+            Some((_, ncolumns)) => *ncolumns,
+            None => 0,
+        }
+    }
+
+    fn fmt_line(&self, f: &mut dyn std::fmt::Write, line: usize) -> std::fmt::Result {
+        let Some((start, _)) = line.checked_sub(1).and_then(|l| self.source.lines.get(l)) else {
+            return Ok(());
+        };
+        let s = &self.source.text[*start..];
+        let s = s.split_once('\n').map(|(s, _)| s).unwrap_or(s);
+        write!(f, "{}", s)
+    }
+}
+
+//a Sources
+//tp Sources
+/// A set of texts, each identified by a [FileId], so that positions
+/// and spans can be related back to the text (and the name of the
+/// text) that they are within
+#[derive(Debug, Default)]
+pub struct Sources {
+    sources: Vec<Source>,
+}
+
+//ip Sources
+impl Sources {
+    //mp add
+    /// Register a text with a name (such as its path), returning its
+    /// [FileId]
+    pub fn add(&mut self, name: &str, text: &str) -> FileId {
+        self.sources.push(Source::new(name, text));
+        FileId(self.sources.len() - 1)
+    }
+
+    //ap len
+    /// Get the number of texts that have been registered
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    //ap is_empty
+    /// Return true if no texts have been registered
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    //ap name
+    /// Get the name of a text, if it is registered
+    pub fn name(&self, file: FileId) -> Option<&str> {
+        self.sources.get(file.0).map(|s| s.name.as_str())
+    }
+
+    //ap text
+    /// Get a text, if it is registered
+    pub fn text(&self, file: FileId) -> Option<&str> {
+        self.sources.get(file.0).map(|s| s.text.as_str())
+    }
+
+    //ap context
+    /// Get the context of a text, which implements
+    /// lexer_rs::FmtContext, if it is registered
+    pub fn context(&self, file: FileId) -> Option<SourceContext<'_>> {
+        self.sources
+            .get(file.0)
+            .map(|source| SourceContext { source })
+    }
+
+    //mp location
+    /// Describe a position as 'file:line:column', using the name of
+    /// its text
+    pub fn location(&self, posn: &FilePosn) -> String {
+        match self.name(posn.file) {
+            Some(name) => format!("{}:{}:{}", name, posn.line(), posn.column()),
+            None => format!("{}:{}:{}", posn.file, posn.line(), posn.column()),
+        }
+    }
+
+    //mp fmt_span
+    /// Format the lines of text around a span, highlighting the span
+    pub fn fmt_span(&self, f: &mut dyn std::fmt::Write, span: &Span<FilePosn>) -> std::fmt::Result {
+        let Some(context) = self.context(span.start().file) else {
+            return Ok(());
+        };
+        if span.end().file == span.start().file {
+            context.fmt_context(f, span.start(), span.end())
+        } else {
+            context.fmt_context(f, span.start(), span.start())
+        }
+    }
+
+    //mp fmt_error
+    /// Format an error as 'file:line:column: message', followed by
+    /// its context, and then the location of each include directive
+    /// (innermost first) for an error within an included document
+    pub fn fmt_error(
+        &self,
+        f: &mut dyn std::fmt::Write,
+        e: &HmlError<FilePosn>,
+    ) -> std::fmt::Result {
+        let mut e = e;
+        let mut directives = Vec::new();
+        while let HmlError::InInclude { span, error, .. } = e {
+            directives.push(*span);
+            e = error;
+        }
+        match e.span() {
+            Some(span) => {
+                writeln!(f, "{}: {}", self.location(span.start()), e)?;
+                self.fmt_span(f, span)?;
+            }
+            None => writeln!(f, "{}", e)?,
+        }
+        for span in directives.iter().rev() {
+            writeln!(f, "included from {}", self.location(span.start()))?;
+        }
+        Ok(())
+    }
+
+    //mp error_string
+    /// Format an error as a String, as [Sources::fmt_error]
+    pub fn error_string(&self, e: &HmlError<FilePosn>) -> String {
+        let mut s = String::new();
+        self.fmt_error(&mut s, e).unwrap();
+        s
+    }
+}
+
+//a SourceLoader
+//tp SourceLoader
+/// A [Loader] that registers each document loaded by another
+/// [Loader] with [Sources], so that the positions within it identify
+/// it
+#[derive(Debug, Default)]
+pub struct SourceLoader<L>
+where
+    L: Loader<FilePosn>,
+{
+    loader: L,
+    sources: Sources,
+}
+
+//ip SourceLoader
+impl<L> SourceLoader<L>
+where
+    L: Loader<FilePosn>,
+{
+    //fp new
+    /// Create a new [SourceLoader] that uses `loader`, registering
+    /// documents with `sources` (which will usually already include
+    /// the top-level document)
+    pub fn new(loader: L, sources: Sources) -> Self {
+        Self { loader, sources }
+    }
+
+    //ap sources
+    /// Borrow the [Sources]
+    pub fn sources(&self) -> &Sources {
+        &self.sources
+    }
+
+    //mp into_sources
+    /// Consume the [SourceLoader], returning the [Sources]
+    pub fn into_sources(self) -> Sources {
+        self.sources
+    }
+}
+
+//ip Loader for SourceLoader
+impl<L> Loader<FilePosn> for SourceLoader<L>
+where
+    L: Loader<FilePosn>,
+{
+    fn load(&mut self, path: &str, from: Option<&str>) -> std::io::Result<(String, String)> {
+        self.loader.load(path, from)
+    }
+
+    fn document_name(&mut self, name: &str) -> String {
+        self.loader.document_name(name)
+    }
+
+    fn start_posn(&mut self, name: &str, text: &str) -> FilePosn {
+        FilePosn::new(self.sources.add(name, text))
+    }
+}
+
+//a Tests
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{FileId, FilePosn, SourceLoader, Sources};
+    use crate::hml_reader::{Includer, Parser};
+    use crate::names::{Namespace, NamespaceStack};
+    use crate::{HmlError, HmlResult};
+
+    use lexer_rs::{LexerOfStr, ParserIterator, UserPosn};
+
+    //fi parse
+    /// Parse the document 'main' of a set of documents, with includes
+    /// expanded, returning the [Sources] and the location of the start
+    /// of each event
+    fn parse(docs: &[(&str, &str)]) -> (Sources, HmlResult<Vec<String>, FilePosn>) {
+        let docs: HashMap<String, String> = docs
+            .iter()
+            .map(|(n, t)| (n.to_string(), t.to_string()))
+            .collect();
+        let text = docs["main"].clone();
+        let mut sources = Sources::default();
+        let main = sources.add("main", &text);
+        let mut namespace = Namespace::new(true);
+        let mut namespace_stack = NamespaceStack::new(&mut namespace);
+        let lexer: LexerOfStr<FilePosn, _, HmlError<FilePosn>> = LexerOfStr::new(&text);
+        let lexer_parsers = crate::hml_reader::parse_fns();
+        let mut lexer_iter = ParserIterator::new(&lexer, FilePosn::new(main), &lexer_parsers);
+        let mut parser = Parser::default();
+        let mut includer =
+            Includer::new(SourceLoader::new(docs, sources)).set_document_name("main");
+        let mut events = Vec::new();
+        let result = loop {
+            match includer.next_event(&mut namespace_stack, &mut parser, || lexer_iter.next()) {
+                Err(e) => break Err(e),
+                Ok(event) => {
+                    let start = event.borrow_span().start();
+                    let sources = includer.loader().sources();
+                    events.push(sources.location(start));
+                    if event.is_end_document() {
+                        break Ok(events);
+                    }
+                }
+            }
+        };
+        (includer.into_loader().into_sources(), result)
+    }
+
+    #[test]
+    fn test_sources() {
+        let docs = [
+            ("main", "#a\n##?include \"b\"\n##c"),
+            ("b", "; In b\n##b ###?include \"d\""),
+            ("d", "###d"),
+        ];
+        let (sources, events) = parse(&docs);
+        assert_eq!(sources.len(), 3);
+        assert_eq!(sources.name(FileId(2)), Some("d"));
+        assert_eq!(sources.text(FileId(2)), Some("###d"));
+        assert_eq!(
+            events.unwrap(),
+            vec![
+                "main:1:1", "main:1:1", "b:1:1", "b:2:1", "d:1:1", "d:1:5", "b:2:20", "main:3:1",
+                "main:3:4", "main:3:4", "main:3:4"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_context() {
+        let docs = [
+            ("main", "#a\n##?include \"b\""),
+            ("b", "; In b\n##b\n  ####bad"),
+        ];
+        let (sources, result) = parse(&docs);
+        let e = result.unwrap_err();
+        let message = match &e {
+            HmlError::InInclude { error, .. } => error.to_string(),
+            _ => panic!("Expected an error in an included document"),
+        };
+        assert_eq!(
+            sources.error_string(&e),
+            format!(
+                "b:3:3: {}\n    |  ##b\n   3|    ####bad\n    |    ^^^^^^^\n    |  \nincluded from main:2:1\n",
+                message
+            )
+        );
+        assert_eq!(e.span().unwrap().start().line(), 2);
+    }
+}
@@ -0,0 +1,46 @@
+//a Documentation
+/*!
+
+# XML reader module
+
+This module provides an XML parser which reads XML documents to markup
+events, in the same manner as the [crate::hml_reader] does for HML;
+it does not require the 'xml' feature.
+
+This uses the lexer_rs module to generate XML tokens, which are parsed
+with the XML [Parser]; as all the events have spans within the
+document, errors can be reported with the lexer_rs::FmtContext trait
+just as for HML documents.
+
+```text
+ let mut namespace = Namespace::new(true);
+ let mut namespace_stack = NamespaceStack::new(&mut namespace);
+ let lexer_string = lexer_rs::LexerOfString::default().set_text(text);
+ let lexer = lexer_string.lexer();
+ let lexer_parsers = hml_rs::xml_reader::parse_fns();
+ let mut lexer_iter = lexer.iter(&lexer_parsers);
+ let mut parser = hml_rs::xml_reader::Parser::default();
+ match parser.next_event(&mut namespace_stack, || lexer_iter.next()) {
+  ...
+ }
+```
+
+The parser checks that the document is well-formed, but it does not
+validate it against a document type declaration. Only the entities
+provided to the parser (by default the predefined XML entities) and
+the general entities declared with a literal value in the internal
+subset of the DOCTYPE ('<!DOCTYPE a [<!ENTITY e "x">]>') may be
+referenced; external subsets and parameter entities are not read.
+
+  !*/
+
+mod lexer_parsers;
+mod parser;
+mod token;
+
+mod test_parser;
+
+use token::{Token, TokenAttribute, TokenType};
+
+pub use lexer_parsers::parse_fns;
+pub use parser::Parser;
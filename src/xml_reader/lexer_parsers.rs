@@ -0,0 +1,506 @@
+//a Imports
+use crate::hml_reader::utils::{is_name, is_name_start};
+use crate::markup::AttributeSpan;
+use crate::{HmlError, HmlResult};
+use crate::{Posn, Span};
+
+use super::{Token, TokenAttribute};
+
+//a Utility functions
+//fi is_xml_whitespace
+/// Returns true if the character is XML whitespace
+#[inline]
+fn is_xml_whitespace(ch: char) -> bool {
+    matches!(ch, ' ' | '\t' | '\n' | '\r')
+}
+
+//fi unexpected
+/// Return an error for the character at `posn`, or for the end of
+/// the stream if there is no character there
+fn unexpected<L, P, T>(lexer: &L, posn: P) -> HmlResult<T, P>
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+{
+    match lexer.peek_at(&posn) {
+        Some(ch) => HmlError::unexpected_character(&posn, &lexer.consumed_char(posn, ch), ch),
+        None => HmlError::unexpected_eof(&posn, &posn),
+    }
+}
+
+//fi skip_while
+/// Skip the characters for which `f` returns true, tracking newlines
+fn skip_while<L, P, F>(lexer: &L, mut posn: P, f: F) -> P
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+    F: Fn(char) -> bool,
+{
+    while let Some(ch) = lexer.peek_at(&posn) {
+        if !f(ch) {
+            break;
+        }
+        posn = lexer.consumed_char(posn, ch);
+    }
+    posn
+}
+
+//fi skip_whitespace
+fn skip_whitespace<L, P>(lexer: &L, posn: P) -> P
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+{
+    skip_while(lexer, posn, is_xml_whitespace)
+}
+
+//fi find_terminator
+/// Find the next occurrence of `end` from `posn`, returning the
+/// positions of its start and just beyond it
+///
+/// If it is not found then an unexpected EOF error is returned, with
+/// the span from `start` to the end of the stream
+fn find_terminator<L, P>(lexer: &L, start: P, mut posn: P, end: &str) -> HmlResult<(P, P), P>
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+{
+    loop {
+        if lexer.matches_str(&posn, end) {
+            return Ok((posn, lexer.consumed_ascii_str(posn, end)));
+        }
+        let Some(ch) = lexer.peek_at(&posn) else {
+            return HmlError::unexpected_eof(&start, &posn);
+        };
+        posn = lexer.consumed_char(posn, ch);
+    }
+}
+
+//fi parse_name
+fn parse_name<L, P>(lexer: &L, posn: P) -> Option<(P, String)>
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+{
+    let ch = lexer.peek_at(&posn)?;
+    let (end, Some((start, _n))) = lexer.do_while(posn, ch, &|n, ch| {
+        (n == 0) && (is_name_start(ch)) || ((n > 0) && (is_name(ch)))
+    }) else {
+        return None;
+    };
+    Some((end, lexer.get_text(start, end).to_string()))
+}
+
+//fi parse_qualified_name
+/// Parse a name that may have a prefix, returning the end position,
+/// prefix (empty if there is none) and local name
+fn parse_qualified_name<L, P>(lexer: &L, posn: P) -> HmlResult<Option<(P, String, String)>, P>
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+{
+    let Some((end_name, name)) = parse_name(lexer, posn) else {
+        return Ok(None);
+    };
+    match lexer.peek_at(&end_name) {
+        Some(':') => {
+            let posn = lexer.consumed_char(end_name, ':');
+            let Some((end_name, name2)) = parse_name(lexer, posn) else {
+                return unexpected(lexer, posn);
+            };
+            Ok(Some((end_name, name, name2)))
+        }
+        _ => Ok(Some((end_name, "".into(), name))),
+    }
+}
+
+//fi parse_attribute_value
+/// Parse a quoted attribute value, returning the end position and
+/// the value between the quotes
+fn parse_attribute_value<L, P>(lexer: &L, posn: P) -> HmlResult<(P, String), P>
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+{
+    let quote = match lexer.peek_at(&posn) {
+        Some(ch) if ch == '"' || ch == '\'' => ch,
+        _ => {
+            return unexpected(lexer, posn);
+        }
+    };
+    let value_start = lexer.consumed_char(posn, quote);
+    let value_end = skip_while(lexer, value_start, |ch| ch != quote && ch != '<');
+    if lexer.peek_at(&value_end) != Some(quote) {
+        return unexpected(lexer, value_end);
+    }
+    let value = lexer.get_text(value_start, value_end).to_string();
+    Ok((lexer.consumed_char(value_end, quote), value))
+}
+
+//fi parse_attribute
+/// Parse an attribute 'name = "value"' at `posn`
+fn parse_attribute<L, P>(lexer: &L, posn: P) -> HmlResult<(P, TokenAttribute<P>), P>
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+{
+    let Some((end_name, prefix, name)) = parse_qualified_name(lexer, posn)? else {
+        return unexpected(lexer, posn);
+    };
+    let name_span = Span::new(posn, end_name);
+    let eq = skip_whitespace(lexer, end_name);
+    match lexer.peek_at(&eq) {
+        Some('=') => (),
+        Some(ch) => return HmlError::expected_equals(&eq, &lexer.consumed_char(eq, ch), ch),
+        None => return HmlError::unexpected_eof(&eq, &eq),
+    }
+    let value_start = skip_whitespace(lexer, lexer.consumed_char(eq, '='));
+    let (end, value) = parse_attribute_value(lexer, value_start)?;
+    let span = AttributeSpan::new(name_span, Span::new(value_start, end));
+    let attribute = TokenAttribute {
+        prefix,
+        name,
+        value,
+        span,
+    };
+    Ok((end, attribute))
+}
+
+//a Lexer functions
+//fi parse_text
+/// Parse character data up to the next '<' (or EOF)
+fn parse_text<L, P>(lexer: &L, posn: P, ch: char) -> HmlResult<Option<(P, Token<P>)>, P>
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+{
+    if ch == '<' {
+        return Ok(None);
+    }
+    let end = skip_while(lexer, posn, |ch| ch != '<');
+    let text = lexer.get_text(posn, end);
+    let span = Span::new(posn, end);
+    if text.chars().all(is_xml_whitespace) {
+        return Ok(Some((end, Token::whitespace(span, text.to_string()))));
+    }
+    if let Some(n) = text.find("]]>") {
+        let start = text[..n].chars().fold(posn, |p, ch| p.move_by_char(ch));
+        let end = lexer.consumed_ascii_str(start, "]]>");
+        return HmlError::unexpected_character(&start, &end, ']');
+    }
+    Ok(Some((end, Token::characters(span, text.to_string()))))
+}
+
+//fi parse_comment
+/// Parse a comment '<!-- ... -->'; the comment may not contain '--'
+fn parse_comment<L, P>(lexer: &L, posn: P, _ch: char) -> HmlResult<Option<(P, Token<P>)>, P>
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+{
+    if !lexer.matches_str(&posn, "<!--") {
+        return Ok(None);
+    }
+    let data_start = lexer.consumed_ascii_str(posn, "<!--");
+    let (data_end, after) = find_terminator(lexer, posn, data_start, "--")?;
+    if lexer.peek_at(&after) != Some('>') {
+        return HmlError::unexpected_character(&data_end, &after, '-');
+    }
+    let end = lexer.consumed_char(after, '>');
+    let data = lexer.get_text(data_start, data_end).to_string();
+    Ok(Some((end, Token::comment(Span::new(posn, end), data))))
+}
+
+//fi parse_cdata
+/// Parse a CDATA section '<![CDATA[ ... ]]>'
+fn parse_cdata<L, P>(lexer: &L, posn: P, _ch: char) -> HmlResult<Option<(P, Token<P>)>, P>
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+{
+    if !lexer.matches_str(&posn, "<![CDATA[") {
+        return Ok(None);
+    }
+    let data_start = lexer.consumed_ascii_str(posn, "<![CDATA[");
+    let (data_end, end) = find_terminator(lexer, posn, data_start, "]]>")?;
+    let data = lexer.get_text(data_start, data_end).to_string();
+    Ok(Some((end, Token::cdata(Span::new(posn, end), data))))
+}
+
+//fi parse_doctype
+/// Parse a document type declaration '<!DOCTYPE ... >', including
+/// any internal subset in '[...]'
+fn parse_doctype<L, P>(lexer: &L, posn: P, _ch: char) -> HmlResult<Option<(P, Token<P>)>, P>
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+{
+    if !lexer.matches_str(&posn, "<!DOCTYPE") {
+        return Ok(None);
+    }
+    let mut p = lexer.consumed_ascii_str(posn, "<!DOCTYPE");
+    let mut depth = 0;
+    let mut quote = None;
+    loop {
+        let Some(ch) = lexer.peek_at(&p) else {
+            return HmlError::unexpected_eof(&posn, &p);
+        };
+        p = lexer.consumed_char(p, ch);
+        match (quote, ch) {
+            (Some(q), ch) if ch == q => quote = None,
+            (Some(_), _) => (),
+            (None, '"' | '\'') => quote = Some(ch),
+            (None, '[') => depth += 1,
+            (None, ']') => depth -= 1,
+            (None, '>') if depth == 0 => break,
+            _ => (),
+        }
+    }
+    let data = lexer.get_text(posn, p).to_string();
+    Ok(Some((p, Token::doctype(Span::new(posn, p), data))))
+}
+
+//fi parse_declaration
+/// Parse the pseudo-attributes of an XML declaration, from just after
+/// '<?xml', returning the version
+fn parse_declaration<L, P>(lexer: &L, start: P, mut posn: P) -> HmlResult<(P, Token<P>), P>
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+{
+    let mut version = "1.0".to_string();
+    loop {
+        let p = skip_whitespace(lexer, posn);
+        if lexer.matches_str(&p, "?>") {
+            let end = lexer.consumed_ascii_str(p, "?>");
+            return Ok((end, Token::declaration(Span::new(start, end), version)));
+        }
+        if p == posn {
+            return unexpected(lexer, p);
+        }
+        let (end, attribute) = parse_attribute(lexer, p)?;
+        if attribute.prefix.is_empty() && attribute.name == "version" {
+            version = attribute.value;
+        }
+        posn = end;
+    }
+}
+
+//fi parse_processing_instruction
+/// Parse a processing instruction '<?name data?>', or an XML
+/// declaration '<?xml ...?>'
+fn parse_processing_instruction<L, P>(
+    lexer: &L,
+    posn: P,
+    _ch: char,
+) -> HmlResult<Option<(P, Token<P>)>, P>
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+{
+    if !lexer.matches_str(&posn, "<?") {
+        return Ok(None);
+    }
+    let name_start = lexer.consumed_ascii_str(posn, "<?");
+    let Some((name_end, name)) = parse_name(lexer, name_start) else {
+        return unexpected(lexer, name_start);
+    };
+    if name == "xml" {
+        return parse_declaration(lexer, posn, name_end).map(Some);
+    }
+    let data_start = skip_whitespace(lexer, name_end);
+    if data_start == name_end && !lexer.matches_str(&data_start, "?>") {
+        return unexpected(lexer, data_start);
+    }
+    let (data_end, end) = find_terminator(lexer, posn, data_start, "?>")?;
+    let data = lexer.get_text(data_start, data_end).to_string();
+    let span = Span::new(posn, end);
+    let name_span = Span::new(name_start, name_end);
+    Ok(Some((
+        end,
+        Token::processing_instruction(span, name_span, name, data),
+    )))
+}
+
+//fi parse_end_tag
+/// Parse an end tag '</name>'
+fn parse_end_tag<L, P>(lexer: &L, posn: P, _ch: char) -> HmlResult<Option<(P, Token<P>)>, P>
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+{
+    if !lexer.matches_str(&posn, "</") {
+        return Ok(None);
+    }
+    let name_start = lexer.consumed_ascii_str(posn, "</");
+    let Some((name_end, prefix, name)) = parse_qualified_name(lexer, name_start)? else {
+        return unexpected(lexer, name_start);
+    };
+    let p = skip_whitespace(lexer, name_end);
+    if lexer.peek_at(&p) != Some('>') {
+        return unexpected(lexer, p);
+    }
+    let end = lexer.consumed_char(p, '>');
+    let span = Span::new(posn, end);
+    let name_span = Span::new(name_start, name_end);
+    Ok(Some((end, Token::end_tag(span, name_span, prefix, name))))
+}
+
+//fi parse_start_tag
+/// Parse a start tag '<name attr="value" ...>', or an empty element
+/// tag '<name ... />'
+fn parse_start_tag<L, P>(lexer: &L, posn: P, ch: char) -> HmlResult<Option<(P, Token<P>)>, P>
+where
+    L: lexer_rs::Lexer<State = P> + lexer_rs::CharStream<P>,
+    P: Posn,
+{
+    if ch != '<' {
+        return Ok(None);
+    }
+    let name_start = lexer.consumed_char(posn, ch);
+    let Some((name_end, prefix, name)) = parse_qualified_name(lexer, name_start)? else {
+        return unexpected(lexer, name_start);
+    };
+    let mut attributes = Vec::new();
+    let mut p = name_end;
+    loop {
+        let after_ws = skip_whitespace(lexer, p);
+        let (end, empty) = {
+            if lexer.matches_str(&after_ws, "/>") {
+                (lexer.consumed_ascii_str(after_ws, "/>"), true)
+            } else if lexer.peek_at(&after_ws) == Some('>') {
+                (lexer.consumed_char(after_ws, '>'), false)
+            } else if after_ws == p {
+                return unexpected(lexer, p);
+            } else {
+                let (end, attribute) = parse_attribute(lexer, after_ws)?;
+                attributes.push(attribute);
+                p = end;
+                continue;
+            }
+        };
+        let span = Span::new(posn, end);
+        let name_span = Span::new(name_start, name_end);
+        return Ok(Some((
+            end,
+            Token::start_tag(span, name_span, prefix, name, attributes, empty),
+        )));
+    }
+}
+
+//fp parse_fns
+/// Return a Vec of parser functions that can be passed to a Lexer,
+/// which provide a complete parsing of tokens for an XML reader
+pub fn parse_fns<'parser, L, P>() -> Vec<lexer_rs::BoxDynLexerParseFn<'parser, L>>
+where
+    L: lexer_rs::Lexer<State = P, Token = Token<P>, Error = HmlError<P>>
+        + lexer_rs::CharStream<P>
+        + 'parser,
+    P: Posn + 'parser,
+{
+    // All markup starts with '<', and the start tag is the fallback
+    // for a '<' that is not any other markup
+    vec![
+        Box::new(parse_text) as lexer_rs::BoxDynLexerParseFn<'parser, L>,
+        Box::new(parse_comment) as lexer_rs::BoxDynLexerParseFn<'parser, L>,
+        Box::new(parse_cdata) as lexer_rs::BoxDynLexerParseFn<'parser, L>,
+        Box::new(parse_doctype) as lexer_rs::BoxDynLexerParseFn<'parser, L>,
+        Box::new(parse_processing_instruction) as lexer_rs::BoxDynLexerParseFn<'parser, L>,
+        Box::new(parse_end_tag) as lexer_rs::BoxDynLexerParseFn<'parser, L>,
+        Box::new(parse_start_tag) as lexer_rs::BoxDynLexerParseFn<'parser, L>,
+    ]
+}
+
+//a Tests
+#[cfg(test)]
+use super::TokenType;
+
+//ft test_parse_tokens
+#[test]
+fn test_parse_tokens() {
+    use lexer_rs::*;
+    type Posn = StreamCharPos<LineColumn>;
+    type TestLexer<'a> = LexerOfStr<'a, Posn, Token<Posn>, HmlError<Posn>>;
+    let parsers = parse_fns::<TestLexer, Posn>();
+
+    let text = r#"<?xml version="1.1"?>
+<!DOCTYPE a [<!ENTITY e "x>y">]>
+<a:b x = 'it"s' xmlns:a="urn:a"><c/>t &amp; u<![CDATA[<raw>]]><!-- note --><?pi some data?></a:b >"#;
+    let l = TestLexer::new(text);
+    let mut tokens = Vec::new();
+    let mut posn = Posn::default();
+    while let Some((p, token)) = l.parse(posn, &parsers).unwrap() {
+        tokens.push(token);
+        posn = p;
+    }
+    let types: Vec<TokenType> = tokens.iter().map(|t| t.token_type()).collect();
+    use TokenType::*;
+    assert_eq!(
+        types,
+        vec![
+            Declaration,
+            Whitespace,
+            Doctype,
+            Whitespace,
+            StartTag,
+            StartTag,
+            Characters,
+            CData,
+            Comment,
+            ProcessingInstruction,
+            EndTag
+        ]
+    );
+    assert_eq!(tokens[0].data(), "1.1");
+    let mut b = tokens[4].clone();
+    assert_eq!(b.qualified_name(), "a:b");
+    assert!(!b.is_empty_element());
+    let attributes = b.take_attributes();
+    assert_eq!(attributes.len(), 2);
+    assert_eq!(attributes[0].value, "it\"s");
+    assert_eq!(&text[attributes[0].span.value.byte_range()], "'it\"s'");
+    assert_eq!(attributes[1].prefix, "xmlns");
+    assert!(tokens[5].is_empty_element());
+    assert_eq!(tokens[6].data(), "t &amp; u");
+    assert_eq!(tokens[7].data(), "<raw>");
+    assert_eq!(tokens[8].data(), " note ");
+    assert_eq!((tokens[9].name(), tokens[9].data()), ("pi", "some data"));
+    assert_eq!(tokens[10].get_span().start().line(), 3);
+}
+
+//ft test_parse_errors
+#[test]
+fn test_parse_errors() {
+    use lexer_rs::*;
+    type Posn = StreamCharPos<LineColumn>;
+    type TestLexer<'a> = LexerOfStr<'a, Posn, Token<Posn>, HmlError<Posn>>;
+    let parsers = parse_fns::<TestLexer, Posn>();
+
+    for (text, kind, bad) in [
+        ("<a x='1'y='2'>", "unexpected_character", "y"),
+        ("<a x '1'>", "expected_equals", "'"),
+        ("<a x='<'>", "unexpected_character", "<"),
+        ("<!-- a -- b -->", "unexpected_character", "--"),
+        ("<!-- a", "unexpected_eof", "<!-- a"),
+        ("a ]]> b", "unexpected_character", "]]>"),
+        ("< a>", "unexpected_character", " "),
+        ("</a b>", "unexpected_character", "b"),
+    ] {
+        let l = TestLexer::new(text);
+        let mut posn = Posn::default();
+        let err = loop {
+            match l.parse(posn, &parsers) {
+                Ok(Some((p, _))) => posn = p,
+                Ok(None) => panic!("No error for {}", text),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(err.kind(), kind, "Error for {}", text);
+        assert_eq!(
+            &text[err.span().unwrap().byte_range()],
+            bad,
+            "Error for {}",
+            text
+        );
+    }
+}
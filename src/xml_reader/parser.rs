@@ -0,0 +1,442 @@
+//a Imports
+use std::borrow::Cow;
+
+use super::{Token, TokenAttribute, TokenType};
+use crate::escape::Entities;
+use crate::markup::{Event, TagSpans};
+use crate::names::{Attribute, Attributes, Name, NamespaceStack, Tag};
+use crate::{HmlError, HmlResult, Posn, Span};
+
+//a Internal types and functions
+//ti OpenElement
+/// An element whose start tag has been parsed but not its end tag
+#[derive(Debug)]
+struct OpenElement<P>
+where
+    P: Posn,
+{
+    /// Span of the start tag
+    span: Span<P>,
+    /// The name as it appears in the start tag, which the end tag
+    /// must match
+    qualified_name: String,
+    /// The resolved name of the element
+    name: Name,
+}
+
+//fi normalize_newlines
+/// Normalize line ends as XML requires - carriage return and
+/// newline pairs, and other carriage returns, become newlines
+fn normalize_newlines(s: &str) -> Cow<'_, str> {
+    if s.contains('\r') {
+        Cow::Owned(s.replace("\r\n", "\n").replace('\r', "\n"))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+//fi span_within
+/// Get the span within the document of a byte range of text after
+/// newline normalization, given the raw text and its start position
+fn span_within<P: Posn>(start: P, raw: &str, range: std::ops::Range<usize>) -> Span<P> {
+    let mut posn = start;
+    let mut ofs = 0;
+    let mut range_start = None;
+    let mut chars = raw.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if range_start.is_none() && ofs >= range.start {
+            range_start = Some(posn);
+        }
+        if ofs >= range.end {
+            break;
+        }
+        posn = posn.move_by_char(ch);
+        if ch != '\r' || chars.peek() != Some(&'\n') {
+            ofs += ch.len_utf8();
+        }
+    }
+    Span::new(range_start.unwrap_or(posn), posn)
+}
+
+//fi entity_declarations
+/// Find the byte ranges of the general entity declarations
+/// ('<!ENTITY name ...>') in the internal subset of the text of a
+/// document type declaration; comments, parameter entity
+/// declarations and other markup declarations are skipped
+fn entity_declarations(doctype: &str) -> Vec<std::ops::Range<usize>> {
+    let mut ranges = Vec::new();
+    let Some(subset) = doctype.find('[') else {
+        return ranges;
+    };
+    let mut i = subset + 1;
+    while let Some(n) = doctype[i..].find(['<', ']']) {
+        let start = i + n;
+        if doctype[start..].starts_with(']') {
+            break;
+        }
+        if doctype[start..].starts_with("<!--") {
+            i = doctype[start..]
+                .find("-->")
+                .map_or(doctype.len(), |n| start + n + 3);
+            continue;
+        }
+        let mut quote = None;
+        let mut end = doctype.len();
+        for (j, ch) in doctype[start..].char_indices() {
+            match (quote, ch) {
+                (Some(q), ch) if ch == q => quote = None,
+                (Some(_), _) => (),
+                (None, '"' | '\'') => quote = Some(ch),
+                (None, '>') => {
+                    end = start + j + 1;
+                    break;
+                }
+                _ => (),
+            }
+        }
+        let declaration = &doctype[start..end];
+        if let Some(rest) = declaration.strip_prefix("<!ENTITY") {
+            if !rest.trim_start().starts_with('%') {
+                ranges.push(start..end);
+            }
+        }
+        i = end;
+    }
+    ranges
+}
+
+//fi is_namespace_declaration
+/// Return true if the attribute declares a namespace
+fn is_namespace_declaration<P: Posn>(a: &TokenAttribute<P>) -> bool {
+    (a.prefix.is_empty() && a.name == "xmlns") || a.prefix == "xmlns"
+}
+
+//a Parser
+//tp Parser
+/// A parser of XML tokens (from the lexer functions of
+/// [crate::xml_reader::parse_fns]) that produces markup [Event]s
+///
+/// The document must be well-formed: start and end tags must match,
+/// there must be a single root element, and character data must be
+/// within it. Entity and character references in character data and
+/// attribute values are replaced using a set of [Entities] (by
+/// default the predefined XML entities); character data is then
+/// provided as [crate::markup::ContentType::Interpretable] content in
+/// HML escaped form, as for other event sources. General entity
+/// declarations in the internal subset of the document type
+/// declaration are added to the entities.
+///
+/// Namespace declarations ('xmlns' attributes) are added to the
+/// [NamespaceStack], and apply to the element's name and all of its
+/// attributes, wherever they appear in the start tag.
+pub struct Parser<P>
+where
+    P: Posn,
+{
+    entities: Entities,
+    start_emitted: bool,
+    finished: bool,
+    root_seen: bool,
+    stack: Vec<OpenElement<P>>,
+    pending_token: Option<Token<P>>,
+    pending_end: Option<Event<P>>,
+    token_pos: P,
+}
+
+//ip Default for Parser
+impl<P> Default for Parser<P>
+where
+    P: Posn,
+{
+    fn default() -> Self {
+        Parser {
+            entities: Entities::xml(),
+            start_emitted: false,
+            finished: false,
+            root_seen: false,
+            stack: Vec::new(),
+            pending_token: None,
+            pending_end: None,
+            token_pos: P::default(),
+        }
+    }
+}
+
+//ip Parser
+impl<P> Parser<P>
+where
+    P: Posn,
+{
+    //mp set_entities
+    /// Set the entities that references in character data and
+    /// attribute values may use; the default is the predefined XML
+    /// entities
+    #[inline]
+    pub fn set_entities(mut self, entities: Entities) -> Self {
+        self.entities = entities;
+        self
+    }
+
+    //mi decode
+    /// Replace references in text (character data or an attribute
+    /// value) that starts at `start` in the document, after newline
+    /// normalization (and, for attribute values, whitespace
+    /// normalization)
+    fn decode(&self, start: P, raw: &str, attribute: bool) -> HmlResult<String, P> {
+        let mut text = normalize_newlines(raw);
+        if attribute && text.contains(['\t', '\n']) {
+            text = Cow::Owned(text.replace(['\t', '\n'], " "));
+        }
+        match self.entities.unescape(&text) {
+            Ok(s) => Ok(s.into_owned()),
+            Err(source) => {
+                let span = span_within(start, raw, source.byte_range());
+                Err(HmlError::BadReference { span, source })
+            }
+        }
+    }
+
+    //mi declare_entities
+    /// Declare the general entities of the internal subset of a
+    /// document type declaration that starts at `start` in the
+    /// document; an entity that is already declared keeps its first
+    /// declaration, as XML requires
+    fn declare_entities(&mut self, start: P, raw: &str) -> HmlResult<(), P> {
+        let doctype = normalize_newlines(raw);
+        for range in entity_declarations(&doctype) {
+            if let Err(source) = self.entities.parse_declaration(&doctype[range.clone()]) {
+                let bad = source.byte_range();
+                let bad = (range.start + bad.start)..(range.start + bad.end);
+                let span = span_within(start, raw, bad);
+                return Err(HmlError::BadEntityDeclaration { span, source });
+            }
+        }
+        Ok(())
+    }
+
+    //mi in_element
+    /// Return an error if the parser is not within the root element,
+    /// for content that must be within it
+    fn in_element(&self, span: &Span<P>) -> HmlResult<(), P> {
+        if self.stack.is_empty() {
+            Err(HmlError::ContentOutsideRoot { span: *span })
+        } else {
+            Ok(())
+        }
+    }
+
+    //mi start_element
+    fn start_element(
+        &mut self,
+        ns_stack: &mut NamespaceStack,
+        mut token: Token<P>,
+    ) -> HmlResult<Event<P>, P> {
+        let span = *token.get_span();
+        if self.root_seen && self.stack.is_empty() {
+            return Err(HmlError::ContentOutsideRoot { span });
+        }
+        self.root_seen = true;
+        let token_attributes = token.take_attributes();
+        let mut values = Vec::new();
+        for a in &token_attributes {
+            let start = a.span.value.start().move_by_char('"');
+            values.push(Some(self.decode(start, &a.value, true)?));
+        }
+
+        ns_stack.push_frame();
+        let n = token_attributes.len();
+        let mut built: Vec<Option<Attribute>> = vec![None; n];
+        let ns_first = (0..n)
+            .filter(|i| is_namespace_declaration(&token_attributes[*i]))
+            .chain((0..n).filter(|i| !is_namespace_declaration(&token_attributes[*i])));
+        for i in ns_first {
+            let a = &token_attributes[i];
+            let value = values[i].take().unwrap();
+            let attribute = Attribute::new(ns_stack, &a.prefix, &a.name, value);
+            built[i] = Some(HmlError::map_markup_error(attribute, &a.span.span())?);
+        }
+
+        let mut attributes = Attributes::default();
+        let mut spans = TagSpans::new(*token.get_name_span());
+        for (a, attribute) in token_attributes.iter().zip(built) {
+            let attribute = attribute.unwrap();
+            if let Some(i) = attributes.position(&attribute.name) {
+                return HmlError::duplicate_attribute(
+                    a.span.span(),
+                    spans.attributes[i].span(),
+                    &attribute.name.to_string(ns_stack),
+                );
+            }
+            attributes.push(attribute);
+            spans.attributes.push(a.span);
+        }
+        let tag = HmlError::map_markup_error(
+            Tag::new(ns_stack, token.prefix(), token.name(), attributes),
+            &span,
+        )?;
+        if token.is_empty_element() {
+            self.pending_end = Some(Event::end_element(span, tag.name));
+        } else {
+            self.stack.push(OpenElement {
+                span,
+                qualified_name: token.qualified_name(),
+                name: tag.name,
+            });
+        }
+        Ok(Event::start_element_with_spans(span, tag, spans))
+    }
+
+    //mi end_element
+    fn end_element(
+        &mut self,
+        ns_stack: &mut NamespaceStack,
+        token: Token<P>,
+    ) -> HmlResult<Event<P>, P> {
+        let span = *token.get_span();
+        let found = token.qualified_name();
+        let Some(top) = self.stack.last() else {
+            return Err(HmlError::UnexpectedEndTag { span, name: found });
+        };
+        if top.qualified_name != found {
+            let expected = top.qualified_name.clone();
+            return Err(HmlError::MismatchedEndTag {
+                span,
+                expected,
+                found,
+            });
+        }
+        let top = self.stack.pop().unwrap();
+        ns_stack.pop_frame();
+        Ok(Event::end_element(span, top.name))
+    }
+
+    //mi end_document
+    fn end_document(
+        &mut self,
+        ns_stack: &mut NamespaceStack,
+        token: Token<P>,
+    ) -> HmlResult<Event<P>, P> {
+        if let Some(top) = self.stack.last() {
+            return Err(HmlError::UnclosedElement {
+                span: top.span,
+                name: top.qualified_name.clone(),
+            });
+        }
+        if !self.root_seen {
+            return Err(HmlError::UnexpectedEOF {
+                span: *token.get_span(),
+            });
+        }
+        ns_stack.pop_frame();
+        self.finished = true;
+        Ok(Event::end_document(*token.get_span()))
+    }
+
+    //mi handle_token
+    fn handle_token(
+        &mut self,
+        ns_stack: &mut NamespaceStack,
+        mut token: Token<P>,
+    ) -> HmlResult<Option<Event<P>>, P> {
+        let span = *token.get_span();
+        match token.token_type() {
+            TokenType::Declaration => Err(HmlError::MisplacedDeclaration { span }),
+            TokenType::Doctype => {
+                if self.root_seen {
+                    Err(HmlError::ContentOutsideRoot { span })
+                } else {
+                    self.declare_entities(*span.start(), token.data())?;
+                    Ok(None)
+                }
+            }
+            TokenType::Whitespace => {
+                if self.stack.is_empty() {
+                    Ok(None)
+                } else {
+                    let data = normalize_newlines(token.data()).into_owned();
+                    Ok(Some(Event::content_ws(span, data)))
+                }
+            }
+            TokenType::Characters => {
+                self.in_element(&span)?;
+                let data = self.decode(*span.start(), token.data(), false)?;
+                let data = crate::hml::escape::escape(&data, true).into_owned();
+                Ok(Some(Event::content_int(span, data)))
+            }
+            TokenType::CData => {
+                self.in_element(&span)?;
+                let data = normalize_newlines(token.data()).into_owned();
+                Ok(Some(Event::content_raw(span, data)))
+            }
+            TokenType::Comment => {
+                let data = normalize_newlines(token.data()).into_owned();
+                let lengths = data.split('\n').map(|l| l.len()).collect();
+                Ok(Some(Event::comment(span, data, lengths)))
+            }
+            TokenType::ProcessingInstruction => {
+                let name = ns_stack.add_name(token.name());
+                let data = token.take_data();
+                let data = (!data.is_empty()).then(|| normalize_newlines(&data).into_owned());
+                Ok(Some(Event::ProcessingInstruction {
+                    span,
+                    name,
+                    arg: None,
+                    data,
+                }))
+            }
+            TokenType::StartTag => self.start_element(ns_stack, token).map(Some),
+            TokenType::EndTag => self.end_element(ns_stack, token).map(Some),
+            TokenType::EndOfFile => self.end_document(ns_stack, token).map(Some),
+        }
+    }
+
+    //mp next_event
+    /// Get the next event, using `get_token` to get tokens from the
+    /// lexer as required
+    ///
+    /// The first event is always a StartDocument, with the version
+    /// from the XML declaration if the document has one, and the last
+    /// is an EndDocument
+    pub fn next_event<T>(
+        &mut self,
+        ns_stack: &mut NamespaceStack,
+        mut get_token: T,
+    ) -> HmlResult<Event<P>, P>
+    where
+        T: FnMut() -> Option<HmlResult<Token<P>, P>>,
+    {
+        if self.finished {
+            return HmlError::no_more_events();
+        }
+        if let Some(event) = self.pending_end.take() {
+            ns_stack.pop_frame();
+            return Ok(event);
+        }
+        loop {
+            let token = {
+                if let Some(token) = self.pending_token.take() {
+                    token
+                } else if let Some(token) = get_token() {
+                    token?
+                } else {
+                    Token::eof(Span::new_at(&self.token_pos))
+                }
+            };
+            self.token_pos = *token.get_span().end();
+            if !self.start_emitted {
+                self.start_emitted = true;
+                ns_stack.push_frame();
+                if token.token_type() == TokenType::Declaration {
+                    let version = if token.data() == "1.1" { 110 } else { 100 };
+                    return Ok(Event::start_document(*token.get_span(), version));
+                }
+                let span = Span::new_at(token.get_span().start());
+                self.pending_token = Some(token);
+                return Ok(Event::start_document(span, 100));
+            }
+            if let Some(event) = self.handle_token(ns_stack, token)? {
+                return Ok(event);
+            }
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//a Tests
+#[cfg(test)]
+mod test {
+    //a Imports
+    use crate::markup::{ContentOptions, ContentType, Event};
+    use crate::names::{Namespace, NamespaceStack};
+    use crate::xml_reader::Parser;
+    use crate::HmlError;
+
+    use lexer_rs::{Lexer, LexerOfString, LineColumn, StreamCharPos, UserPosn};
+
+    type LexerPos = StreamCharPos<LineColumn>;
+
+    //fi parse
+    /// Parse XML text, invoking `f` on each event, and return the
+    /// error if the parse fails
+    fn parse<F>(text: &str, mut f: F) -> Result<(), HmlError<LexerPos>>
+    where
+        F: FnMut(&NamespaceStack, &Event<LexerPos>),
+    {
+        let mut namespace = Namespace::new(true);
+        let mut namespace_stack = NamespaceStack::new(&mut namespace);
+        let lexer_string: LexerOfString<LexerPos, _, _> = LexerOfString::default().set_text(text);
+        let lexer = lexer_string.lexer();
+        let lexer_parsers = crate::xml_reader::parse_fns();
+        let mut lexer_iter = lexer.iter(&lexer_parsers);
+        let mut parser = Parser::default();
+        loop {
+            let event = parser.next_event(&mut namespace_stack, || lexer_iter.next())?;
+            f(&namespace_stack, &event);
+            if event.is_end_document() {
+                return Ok(());
+            }
+        }
+    }
+
+    //fi rewrite
+    /// Parse XML text and write it out as XML
+    fn rewrite(text: &str) -> String {
+        let mut writer = crate::xml_writer::Writer::new(Vec::new());
+        parse(text, |ns, event| writer.write_event(ns, event).unwrap()).unwrap();
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_document() {
+        let text = "<?xml version='1.1' encoding='UTF-8'?>\r\n<!DOCTYPE a>\n<!-- c -->\n<a p:x='1 &lt;\n2' xmlns:p=\"urn:p\" xmlns='urn:a'>\r\n <b>fish &amp; &#x63;hips</b><![CDATA[<raw>]]><?pi data?><c/>\n</a>\n";
+        assert_eq!(
+            rewrite(text),
+            "<?xml version=\"1.1\" encoding=\"UTF-8\"?><!-- c --><a p:x=\"1 &lt; 2\" xmlns:p=\"urn:p\" xmlns=\"urn:a\">\n <b>fish &amp; chips</b><![CDATA[<raw>]]><?pi data?><c/>\n</a>\n"
+        );
+    }
+
+    //fi hml_events
+    /// Parse HML text to events, adding whitespace content before
+    /// each element other than the root, invoking `f` on each event
+    fn hml_events<F>(text: &str, mut f: F)
+    where
+        F: FnMut(&NamespaceStack, &Event<LexerPos>),
+    {
+        let mut namespace = Namespace::new(true);
+        let mut namespace_stack = NamespaceStack::new(&mut namespace);
+        let lexer_string: LexerOfString<LexerPos, _, _> = LexerOfString::default().set_text(text);
+        let lexer = lexer_string.lexer();
+        let lexer_parsers = crate::hml_reader::parse_fns();
+        let mut lexer_iter = lexer.iter(&lexer_parsers);
+        let mut parser = crate::hml_reader::Parser::default();
+        let mut depth = 0;
+        loop {
+            let event = parser
+                .next_event(&mut namespace_stack, || lexer_iter.next())
+                .unwrap();
+            if matches!(event, Event::StartElement { .. }) {
+                if depth > 0 {
+                    let ws = Event::content_ws(*event.borrow_span(), "\n  ".to_string());
+                    f(&namespace_stack, &ws);
+                }
+                depth += 1;
+            }
+            f(&namespace_stack, &event);
+            if event.is_end_document() {
+                return;
+            }
+        }
+    }
+
+    //fi read_contents
+    /// Read XML text, returning its content events (with
+    /// interpretable content unescaped)
+    fn read_contents(xml: &str) -> Vec<(ContentType, String)> {
+        let mut contents = Vec::new();
+        parse(xml, |_, event| {
+            if let Event::Content { ctype, data, .. } = event {
+                let data = match ctype {
+                    ContentType::Interpretable => crate::hml::escape::Escapable::new(data)
+                        .unwrap()
+                        .as_ref()
+                        .to_string(),
+                    _ => data.clone(),
+                };
+                contents.push((*ctype, data));
+            }
+        })
+        .unwrap();
+        contents
+    }
+
+    //fi round_trip
+    /// Write HML text as XML with content options, and read the XML
+    /// back, returning the content events; if the 'xml' feature is
+    /// enabled then the same must result using [Event::write_xml]
+    fn round_trip(text: &str, options: ContentOptions) -> Vec<(ContentType, String)> {
+        let mut writer = crate::xml_writer::Writer::new(Vec::new()).set_content_options(options);
+        hml_events(text, |ns, event| writer.write_event(ns, event).unwrap());
+        let contents = read_contents(&String::from_utf8(writer.into_inner()).unwrap());
+
+        #[cfg(feature = "xml")]
+        {
+            let mut writer = xml::writer::EmitterConfig::new().create_writer(Vec::new());
+            hml_events(text, |ns, event| {
+                event.write_xml(ns, &options, &mut writer).unwrap()
+            });
+            let xml = String::from_utf8(writer.into_inner()).unwrap();
+            assert_eq!(read_contents(&xml), contents, "Using write_xml for {}", xml);
+        }
+        contents
+    }
+
+    #[test]
+    fn test_content_round_trip() {
+        use ContentType::*;
+        let text = r##"#a ##b r"raw <&> ]]> x" ##c "tab\there & <" ##d"##;
+        let ws = |s: &str| (Whitespace, s.to_string());
+        let int = |s: &str| (Interpretable, s.to_string());
+        assert_eq!(
+            round_trip(text, ContentOptions::default()),
+            vec![
+                ws("\n  "),
+                (Raw, "raw <&> ]]".to_string()),
+                (Raw, "> x".to_string()),
+                ws("\n  "),
+                int("tab\there & <"),
+                ws("\n  "),
+            ]
+        );
+        assert_eq!(
+            round_trip(
+                text,
+                ContentOptions::default()
+                    .set_raw_as_cdata(false)
+                    .set_keep_whitespace(false)
+                    .set_unescape(false)
+            ),
+            vec![int("raw <&> ]]> x"), int("tab\\there & <")]
+        );
+    }
+
+    #[cfg(feature = "xml")]
+    #[test]
+    fn test_pi_xml_writer() {
+        let text = "#a ##?p x \"d\" ##?q \"e\"";
+        let mut writer = xml::writer::EmitterConfig::new().create_writer(Vec::new());
+        let mut borrowed = Vec::new();
+        hml_events(text, |ns, event| {
+            if let Event::ProcessingInstruction { .. } = event {
+                borrowed.push(event.as_xml_writer(ns).is_ok());
+            }
+            event
+                .write_xml(ns, &ContentOptions::default(), &mut writer)
+                .unwrap()
+        });
+        assert_eq!(borrowed, vec![false, true]);
+        let xml = String::from_utf8(writer.into_inner()).unwrap();
+        assert!(xml.ends_with("<a><?p x d?><?q e?></a>"), "{xml}");
+    }
+
+    #[test]
+    fn test_names_and_spans() {
+        let text = "<a xmlns='urn:a' xmlns:p='urn:p'>\n  <p:b p:x = \"1\" y='2'/>\n</a>";
+        let mut found = Vec::new();
+        parse(text, |ns, event| {
+            if let Event::StartElement { tag, spans, .. } = event {
+                let spans = spans.as_ref().unwrap();
+                found.push(format!(
+                    "{{{}}}{} {} {:?}",
+                    ns.uri_str(tag.name.uri),
+                    ns.name_str(tag.name.name),
+                    &text[spans.name.byte_range()],
+                    spans
+                        .attributes
+                        .iter()
+                        .map(|a| (&text[a.name.byte_range()], &text[a.value.byte_range()]))
+                        .collect::<Vec<_>>()
+                ));
+            }
+            if let Event::EndElement { span, .. } = event {
+                found.push(format!("{}", span.start().line()));
+            }
+        })
+        .unwrap();
+        assert_eq!(
+            found,
+            vec![
+                r#"{urn:a}a a [("xmlns", "'urn:a'"), ("xmlns:p", "'urn:p'")]"#,
+                r#"{urn:p}b p:b [("p:x", "\"1\""), ("y", "'2'")]"#,
+                "2",
+                "3",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_doctype_entities() {
+        let text = "<!DOCTYPE a [\n<!ENTITY e \"x &amp; y\">\n<!-- <!ENTITY c 'no'> -->\n<!ENTITY % p 'q'>\n<!ELEMENT a ANY>\n<!ENTITY e 'second'>\n]>\n<a t='&e;'>&e;</a>";
+        assert_eq!(
+            rewrite(text),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><a t=\"x &amp; y\">x &amp; y</a>\n"
+        );
+        let err = parse("<!DOCTYPE a><a>&e;</a>", |_, _| ()).unwrap_err();
+        assert_eq!(err.kind(), "bad_reference");
+    }
+
+    #[test]
+    fn test_errors() {
+        for (text, kind, bad) in [
+            ("<a><b></a>", "mismatched_end_tag", "</a>"),
+            ("<a></a></b>", "unexpected_end_tag", "</b>"),
+            ("<a>\n<b>\n</b>", "unclosed_element", "<a>"),
+            ("<a/><b/>", "content_outside_root", "<b/>"),
+            ("text<a/>", "content_outside_root", "text"),
+            (
+                "<a/>\n<?xml version='1.0'?>",
+                "misplaced_declaration",
+                "<?xml version='1.0'?>",
+            ),
+            ("<a>x &bad; y</a>", "bad_reference", "&bad;"),
+            ("<a>\r\nx &#0; y</a>", "bad_reference", "&#0;"),
+            ("<a x='\n&unknown;'/>", "bad_reference", "&unknown;"),
+            ("<a x='1' x='2'/>", "duplicate_attribute", "x='2'"),
+            ("<p:a/>", "unmapped_prefix", "<p:a/>"),
+            ("<!-- just a comment -->", "unexpected_eof", ""),
+            (
+                "<!DOCTYPE a [\r\n<!ENTITY e SYSTEM 'e.xml'>]><a/>",
+                "bad_entity_declaration",
+                "SYSTEM 'e.xml'",
+            ),
+        ] {
+            let err = parse(text, |_, _| ()).unwrap_err();
+            assert_eq!(err.kind(), kind, "Error for {}: {}", text, err);
+            assert_eq!(
+                &text[err.span().unwrap().byte_range()],
+                bad,
+                "Error for {}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//a Imports
+use crate::markup::AttributeSpan;
+use crate::{Posn, Span};
+
+//a TokenType
+//tp TokenType
+/// The type of a [Token] in an XML document
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TokenType {
+    /// <?xml version="..." ...?>
+    Declaration,
+    /// <[prefix:]name [attributes] [/]>
+    StartTag,
+    /// </[prefix:]name>
+    EndTag,
+    /// Character data, with references not yet replaced
+    Characters,
+    /// Character data that is just whitespace
+    Whitespace,
+    /// <![CDATA[...]]>
+    CData,
+    /// <!--...-->
+    Comment,
+    /// <?name [data]?>
+    ProcessingInstruction,
+    /// <!DOCTYPE ...>
+    Doctype,
+    /// End of file
+    EndOfFile,
+}
+
+//a TokenAttribute
+//tp TokenAttribute
+/// An attribute of a start tag, with its value as it appears in the
+/// document (with references not yet replaced)
+#[derive(Debug, Clone)]
+pub struct TokenAttribute<P>
+where
+    P: Posn,
+{
+    pub prefix: String,
+    pub name: String,
+    pub value: String,
+    pub span: AttributeSpan<P>,
+}
+
+//a Token
+//tp Token
+/// [Token] represents a single item of markup, or run of character
+/// data, in an XML document
+#[derive(Debug, Clone)]
+pub struct Token<P>
+where
+    P: Posn,
+{
+    span: Span<P>,
+    tt: TokenType,
+    prefix: String,
+    name: String,
+    name_span: Span<P>,
+    attributes: Vec<TokenAttribute<P>>,
+    empty: bool,
+    data: String,
+}
+
+//ip Token
+impl<P> Token<P>
+where
+    P: Posn,
+{
+    //fi new
+    fn new(span: Span<P>, tt: TokenType) -> Self {
+        Self {
+            span,
+            tt,
+            prefix: String::new(),
+            name: String::new(),
+            name_span: span,
+            attributes: Vec::new(),
+            empty: false,
+            data: String::new(),
+        }
+    }
+
+    //cp set_name
+    fn set_name(mut self, name_span: Span<P>, prefix: String, name: String) -> Self {
+        self.name_span = name_span;
+        self.prefix = prefix;
+        self.name = name;
+        self
+    }
+
+    //cp set_data
+    fn set_data(mut self, data: String) -> Self {
+        self.data = data;
+        self
+    }
+
+    //fp declaration
+    /// An XML declaration; the data is the version
+    pub fn declaration(span: Span<P>, version: String) -> Self {
+        Self::new(span, TokenType::Declaration).set_data(version)
+    }
+
+    //fp start_tag
+    pub fn start_tag(
+        span: Span<P>,
+        name_span: Span<P>,
+        prefix: String,
+        name: String,
+        attributes: Vec<TokenAttribute<P>>,
+        empty: bool,
+    ) -> Self {
+        let mut t = Self::new(span, TokenType::StartTag).set_name(name_span, prefix, name);
+        t.attributes = attributes;
+        t.empty = empty;
+        t
+    }
+
+    //fp end_tag
+    pub fn end_tag(span: Span<P>, name_span: Span<P>, prefix: String, name: String) -> Self {
+        Self::new(span, TokenType::EndTag).set_name(name_span, prefix, name)
+    }
+
+    //fp characters
+    pub fn characters(span: Span<P>, s: String) -> Self {
+        Self::new(span, TokenType::Characters).set_data(s)
+    }
+
+    //fp whitespace
+    pub fn whitespace(span: Span<P>, s: String) -> Self {
+        Self::new(span, TokenType::Whitespace).set_data(s)
+    }
+
+    //fp cdata
+    pub fn cdata(span: Span<P>, s: String) -> Self {
+        Self::new(span, TokenType::CData).set_data(s)
+    }
+
+    //fp comment
+    pub fn comment(span: Span<P>, s: String) -> Self {
+        Self::new(span, TokenType::Comment).set_data(s)
+    }
+
+    //fp processing_instruction
+    pub fn processing_instruction(
+        span: Span<P>,
+        name_span: Span<P>,
+        name: String,
+        data: String,
+    ) -> Self {
+        Self::new(span, TokenType::ProcessingInstruction)
+            .set_name(name_span, String::new(), name)
+            .set_data(data)
+    }
+
+    //fp doctype
+    /// A document type declaration; the data is the whole text of
+    /// the declaration, including any internal subset
+    pub fn doctype(span: Span<P>, s: String) -> Self {
+        Self::new(span, TokenType::Doctype).set_data(s)
+    }
+
+    //fp eof
+    pub fn eof(span: Span<P>) -> Self {
+        Self::new(span, TokenType::EndOfFile)
+    }
+
+    //mp token_type
+    pub fn token_type(&self) -> TokenType {
+        self.tt
+    }
+
+    //mp get_span
+    pub fn get_span(&self) -> &Span<P> {
+        &self.span
+    }
+
+    //mp get_name_span
+    /// Get the span of the name of a tag or processing instruction
+    pub fn get_name_span(&self) -> &Span<P> {
+        &self.name_span
+    }
+
+    //mp prefix
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    //mp name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    //mp qualified_name
+    /// Get the name of a tag as it appears in the document
+    pub fn qualified_name(&self) -> String {
+        if self.prefix.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}:{}", self.prefix, self.name)
+        }
+    }
+
+    //mp is_empty_element
+    /// Return true if the token is a start tag that ends with '/>'
+    pub fn is_empty_element(&self) -> bool {
+        self.empty
+    }
+
+    //mp data
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+
+    //mp take_data
+    pub fn take_data(&mut self) -> String {
+        std::mem::take(&mut self.data)
+    }
+
+    //mp take_attributes
+    pub fn take_attributes(&mut self) -> Vec<TokenAttribute<P>> {
+        std::mem::take(&mut self.attributes)
+    }
+
+    //mp is_eof
+    pub fn is_eof(&self) -> bool {
+        self.tt == TokenType::EndOfFile
+    }
+}
+
+//ip std::fmt::Display for Token
+impl<P> std::fmt::Display for Token<P>
+where
+    P: Posn,
+{
+    //mp fmt - format a `Token` for display
+    /// Display the `Token` in a human-readable form
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use TokenType::*;
+        match self.tt {
+            Declaration => write!(f, "[{:?}]<?xml {}?>", self.span, self.data),
+            StartTag => {
+                let close = if self.empty { "/" } else { "" };
+                write!(f, "[{:?}]<{}{}>", self.span, self.qualified_name(), close)
+            }
+            EndTag => write!(f, "[{:?}]</{}>", self.span, self.qualified_name()),
+            Characters => write!(f, "[{:?}]chars ...", self.span),
+            Whitespace => write!(f, "[{:?}]whitespace", self.span),
+            CData => write!(f, "[{:?}]cdata ...", self.span),
+            Comment => write!(f, "[{:?}]<!-- ... -->", self.span),
+            ProcessingInstruction => write!(f, "[{:?}]<?{} ...?>", self.span, self.name),
+            Doctype => write!(f, "[{:?}]<!DOCTYPE ...>", self.span),
+            EndOfFile => write!(f, "[{:?}]<eof>", self.span),
+        }
+    }
+}
@@ -0,0 +1,413 @@
+//a Documentation
+/*!
+
+# XML writer
+
+This module provides a [Writer] that generates an XML document from a
+stream of markup [Event]s; it does not require the 'xml' feature.
+
+Text is escaped using [crate::escape]; content that is
+[ContentType::Interpretable] is in HML escaped form, and is unescaped
+before it is escaped for XML. Raw content is written as CDATA
+sections, and whitespace content is written as is; each of these may
+be changed with [Writer::set_content_options].
+
+Elements with no content are written as self-closing tags (unless
+disabled with [Writer::set_self_closing]). If indentation is enabled
+(with [Writer::set_indent]) then elements, comments and processing
+instructions start on new lines, indented by their depth, within
+elements whose content starts with one of these; an element whose
+content starts with text is not indented (as whitespace would change
+its content), and indentation within an element ends at any text
+that follows. The document is written as the events arrive, with
+only the start tag of the current element held back, until it is
+known whether it is self-closing.
+
+Text with characters that XML does not permit is an error, except
+that the restricted characters of XML 1.1 (such as U+0001) are
+written as character references in a 1.1 document.
+
+!*/
+
+//a Imports
+use std::borrow::Cow;
+use std::io::Write;
+
+use crate::escape::{escape_attribute, escape_cdata, escape_chars, escape_text, Quote};
+use crate::hml::escape::Escapable;
+use crate::markup::{ContentOptions, ContentType, Event};
+use crate::names::{Name, NamespaceStack};
+use crate::Posn;
+
+//a Internal types
+//ti OpenElement
+/// An element that has been started but not yet ended
+#[derive(Debug)]
+struct OpenElement {
+    /// Name of the element, for the end tag
+    name: Name,
+    /// The start tag, without its closing '>', until the element has
+    /// some content; if it has none it may be self-closing
+    start: Option<String>,
+    /// True if the content of the element is indented; this is
+    /// decided by its first node, and ends at any character content
+    indented: bool,
+}
+
+//fi invalid_data
+/// Create an error for an event that cannot be written as XML
+fn invalid_data(reason: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, reason.to_string())
+}
+
+//a Writer
+//tp Writer
+/// A writer of XML documents from markup [Event]s
+#[derive(Debug)]
+pub struct Writer<W: Write> {
+    writer: W,
+    indent: Option<usize>,
+    quote: Quote,
+    self_closing: bool,
+    declaration: bool,
+    content: ContentOptions,
+    /// The XML version of the document, from its StartDocument
+    version: usize,
+    stack: Vec<OpenElement>,
+    /// True if anything has been written
+    started: bool,
+}
+
+//ip Writer
+impl<W: Write> Writer<W> {
+    //fp new
+    /// Create a new [Writer] that writes to `writer`
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            indent: None,
+            quote: Quote::default(),
+            self_closing: true,
+            declaration: true,
+            content: ContentOptions::default(),
+            version: 100,
+            stack: Vec::new(),
+            started: false,
+        }
+    }
+
+    //cp set_indent
+    /// Set the number of spaces to indent each level of elements by,
+    /// or None (the default) to write no whitespace that is not in
+    /// the events
+    pub fn set_indent(mut self, indent: Option<usize>) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    //cp set_quote
+    /// Set the quote character used for attribute values
+    pub fn set_quote(mut self, quote: Quote) -> Self {
+        self.quote = quote;
+        self
+    }
+
+    //cp set_self_closing
+    /// Set whether elements with no content are written as
+    /// self-closing tags (the default), or with an explicit end tag
+    pub fn set_self_closing(mut self, self_closing: bool) -> Self {
+        self.self_closing = self_closing;
+        self
+    }
+
+    //cp set_declaration
+    /// Set whether the XML declaration is written at the start of the
+    /// document (the default)
+    pub fn set_declaration(mut self, declaration: bool) -> Self {
+        self.declaration = declaration;
+        self
+    }
+
+    //cp set_content_options
+    /// Set how each type of content is written
+    pub fn set_content_options(mut self, content: ContentOptions) -> Self {
+        self.content = content;
+        self
+    }
+
+    //mp into_inner
+    /// Consume the [Writer], returning the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    //mi chars
+    /// Check that text has only characters that XML permits, writing
+    /// restricted characters as character references if `references`
+    fn chars<'a>(&self, text: &'a str, references: bool) -> std::io::Result<Cow<'a, str>> {
+        escape_chars(text, self.version, references).map_err(|ch| {
+            let version = if self.version == 100 { "1.0" } else { "1.1" };
+            invalid_data(&format!(
+                "character U+{:04X} cannot be written in XML {}",
+                ch as u32, version
+            ))
+        })
+    }
+
+    //mi start_node
+    /// Start a node (element, comment, processing instruction or
+    /// text) in the current element, or in the document if there is
+    /// none, writing the start tag of the element if it is the first
+    /// node, and a new line and indentation if required
+    ///
+    /// The content of an element is indented if its first node is
+    /// not text, until any text within it
+    fn start_node(&mut self, is_text: bool) -> std::io::Result<()> {
+        let depth = self.stack.len();
+        let Some(e) = self.stack.last_mut() else {
+            if self.indent.is_some() && self.started {
+                self.writer.write_all(b"\n")?;
+            }
+            self.started = true;
+            return Ok(());
+        };
+        if let Some(start) = e.start.take() {
+            self.writer.write_all(start.as_bytes())?;
+            self.writer.write_all(b">")?;
+            e.indented = self.indent.is_some();
+        }
+        e.indented &= !is_text;
+        match self.indent {
+            Some(indent) if e.indented => write!(self.writer, "\n{:1$}", "", indent * depth),
+            _ => Ok(()),
+        }
+    }
+
+    //mi end_element
+    /// End the current element, as self-closing if it has no content
+    fn end_element(&mut self, ns: &NamespaceStack) -> std::io::Result<()> {
+        let Some(e) = self.stack.pop() else {
+            return Err(invalid_data("end element without a start element"));
+        };
+        let name = e.name.to_string(ns);
+        match e.start {
+            Some(start) if self.self_closing => write!(self.writer, "{start}/>"),
+            Some(start) => write!(self.writer, "{start}></{name}>"),
+            None => {
+                if let Some(indent) = self.indent.filter(|_| e.indented) {
+                    write!(self.writer, "\n{:1$}", "", indent * self.stack.len())?;
+                }
+                write!(self.writer, "</{name}>")
+            }
+        }
+    }
+
+    //mp write_event
+    /// Write an [Event] to the XML document
+    ///
+    /// An error is returned if the event cannot be written as XML,
+    /// such as text with a character that XML does not permit
+    pub fn write_event<P: Posn>(
+        &mut self,
+        ns: &NamespaceStack,
+        event: &Event<P>,
+    ) -> std::io::Result<()> {
+        match event {
+            Event::StartDocument { version, .. } => {
+                self.version = *version;
+                if self.declaration {
+                    let version = if *version == 100 { "1.0" } else { "1.1" };
+                    write!(
+                        self.writer,
+                        "<?xml version=\"{}\" encoding=\"UTF-8\"?>",
+                        version
+                    )?;
+                    self.started = true;
+                }
+                Ok(())
+            }
+            Event::EndDocument { .. } => {
+                if !self.stack.is_empty() {
+                    return Err(invalid_data(
+                        "end document with elements that are not ended",
+                    ));
+                }
+                if self.started {
+                    writeln!(self.writer)?;
+                }
+                self.writer.flush()
+            }
+            Event::StartElement { tag, .. } => {
+                let mut start = format!("<{}", tag.name.to_string(ns));
+                let q = self.quote.as_char();
+                for a in tag.attributes.attributes() {
+                    let name = if a.is_default_namespace(ns) {
+                        "xmlns".into()
+                    } else {
+                        a.name.to_string(ns)
+                    };
+                    let value = escape_attribute(&a.value, self.quote);
+                    let value = self.chars(&value, true)?;
+                    start.push_str(&format!(" {}={}{}{}", name, q, value, q));
+                }
+                self.start_node(false)?;
+                self.stack.push(OpenElement {
+                    name: tag.name,
+                    start: Some(start),
+                    indented: false,
+                });
+                Ok(())
+            }
+            Event::EndElement { .. } => self.end_element(ns),
+            Event::Content { ctype, data, .. } => {
+                if *ctype == ContentType::Whitespace && !self.content.keep_whitespace() {
+                    return Ok(());
+                }
+                let markup = match ctype {
+                    ContentType::Raw if self.content.raw_as_cdata() => {
+                        let data = self.chars(data, false)?;
+                        format!("<![CDATA[{}]]>", escape_cdata(&data))
+                    }
+                    ContentType::Whitespace => data.clone(),
+                    ContentType::Interpretable if self.content.unescape() => {
+                        let text = Escapable::new(data)
+                            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                        self.chars(&escape_text(text.as_ref()), true)?.into_owned()
+                    }
+                    _ => self.chars(&escape_text(data), true)?.into_owned(),
+                };
+                self.start_node(true)?;
+                self.writer.write_all(markup.as_bytes())
+            }
+            Event::ProcessingInstruction { name, .. } => {
+                let data = event.pi_data();
+                if data.as_ref().is_some_and(|d| d.contains("?>")) {
+                    return Err(invalid_data("processing instruction data contains '?>'"));
+                }
+                let markup = match data {
+                    Some(data) => {
+                        format!("<?{} {}?>", ns.name_str(*name), self.chars(&data, false)?)
+                    }
+                    None => format!("<?{}?>", ns.name_str(*name)),
+                };
+                self.start_node(false)?;
+                self.writer.write_all(markup.as_bytes())
+            }
+            Event::Comment { data, .. } => {
+                if data.contains("--") || data.ends_with('-') {
+                    return Err(invalid_data("comment contains '--' or ends with '-'"));
+                }
+                let data = self.chars(data, false)?;
+                let markup = format!("<!--{}-->", data);
+                self.start_node(false)?;
+                self.writer.write_all(markup.as_bytes())
+            }
+        }
+    }
+}
+
+//a Tests
+#[cfg(test)]
+mod test {
+    use super::Writer;
+    use crate::escape::Quote;
+    use crate::hml_reader::Parser;
+    use crate::markup::Event;
+    use crate::names::{Namespace, NamespaceStack};
+
+    use lexer_rs::{Lexer, LexerOfString, LineColumn, StreamCharPos};
+
+    type LexerPos = StreamCharPos<LineColumn>;
+
+    //fi to_xml
+    /// Parse HML text and write it out as XML
+    fn to_xml(text: &str, f: impl FnOnce(Writer<Vec<u8>>) -> Writer<Vec<u8>>) -> String {
+        write_xml(text, 100, f).unwrap()
+    }
+
+    //fi write_xml
+    /// Parse HML text for an XML version and write it out as XML,
+    /// returning any error from the writer
+    fn write_xml(
+        text: &str,
+        version: usize,
+        f: impl FnOnce(Writer<Vec<u8>>) -> Writer<Vec<u8>>,
+    ) -> std::io::Result<String> {
+        let mut namespace = Namespace::new(true);
+        let mut namespace_stack = NamespaceStack::new(&mut namespace);
+        let lexer_string: LexerOfString<LexerPos, _, _> = LexerOfString::default().set_text(text);
+        let lexer = lexer_string.lexer();
+        let lexer_parsers = crate::hml_reader::parse_fns();
+        let mut lexer_iter = lexer.iter(&lexer_parsers);
+        let mut parser = Parser::default().set_version(version);
+        let mut writer = f(Writer::new(Vec::new()));
+        loop {
+            let event: Event<LexerPos> = parser
+                .next_event(&mut namespace_stack, || lexer_iter.next())
+                .unwrap();
+            writer.write_event(&namespace_stack, &event)?;
+            if event.is_end_document() {
+                break;
+            }
+        }
+        Ok(String::from_utf8(writer.into_inner()).unwrap())
+    }
+
+    #[test]
+    fn test_compact() {
+        let text = r##"#a xmlns="urn:a" b='x<"y"' ##c ##d "1 < 2 \u{263a}" r"raw ]]> end" ; note
+##e"##;
+        assert_eq!(
+            to_xml(text, |w| w),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><a xmlns=\"urn:a\" b=\"x&lt;&quot;y&quot;\"><c/><d>1 &lt; 2 \u{263a}<![CDATA[raw ]]]]><![CDATA[> end]]><!-- note--></d><e/></a>\n"
+        );
+        assert_eq!(
+            to_xml(text, |w| w
+                .set_declaration(false)
+                .set_self_closing(false)
+                .set_quote(Quote::Single)),
+            "<a xmlns='urn:a' b='x&lt;\"y\"'><c></c><d>1 &lt; 2 \u{263a}<![CDATA[raw ]]]]><![CDATA[> end]]><!-- note--></d><e></e></a>\n"
+        );
+    }
+
+    #[test]
+    fn test_indent() {
+        let text = r##"; top
+#a ##b ###c "text" ###d ##e "mixed" ###f ##g{ #h{ "x" #h} "y" ##g} ##i ###j ####k"##;
+        assert_eq!(
+            to_xml(text, |w| w.set_indent(Some(2))),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- top-->
+<a>
+  <b>
+    <c>text</c>
+    <d/>
+  </b>
+  <e>mixed<f/></e>
+  <g>
+    <h>x</h>y</g>
+  <i>
+    <j>
+      <k/>
+    </j>
+  </i>
+</a>
+"#
+        );
+    }
+
+    #[test]
+    fn test_chars() {
+        let text = "#a ##b \"x\\u{1}y\" ##c d=\"\u{86}\"";
+        let err = write_xml(text, 100, |w| w.set_declaration(false)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(
+            write_xml(text, 110, |w| w.set_declaration(false)).unwrap(),
+            "<a><b>x&#x1;y</b><c d=\"&#x86;\"/></a>\n"
+        );
+        let text = "#a r\"x\u{1}y\"";
+        assert!(write_xml(text, 110, |w| w).is_err());
+        let text = "#a ;\u{7}";
+        assert!(write_xml(text, 110, |w| w).is_err());
+    }
+}